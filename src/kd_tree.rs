@@ -1,7 +1,25 @@
 use num_traits::Float;
-use std::marker::PhantomData;
-use std::collections::BinaryHeap;
-use std::cmp::Ordering;
+use core::marker::PhantomData;
+use core::cmp::Ordering;
+use core::cell::Cell;
+use alloc::collections::BinaryHeap;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::vec;
+#[cfg(feature="rayon")]
+use rayon::prelude::*;
+#[cfg(all(feature="rayon", feature="std"))]
+use std::time::{Duration, Instant};
+#[cfg(feature="std")]
+use std::io::{self, BufRead, Write};
+#[cfg(feature="std")]
+use std::sync::Mutex;
+#[cfg(feature="std")]
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature="default")]
+use ndarray::{Array1, ArrayView1, ArrayView2};
+#[cfg(feature="default")]
+use alloc::borrow::Cow;
 
 /// Node structure used by tree
 struct Node<DataType> {
@@ -12,17 +30,135 @@ struct Node<DataType> {
     right_child: usize,                          // Index of right child (0 if no right child)
     dimension: usize,                            // Split dimension of current node
     level: usize,                                // Level in tree of current node
+    removed: bool,                               // Node was removed but kept to preserve structure
 }
 
 /// Tree structure with vector of nodes
+///
+/// Queries only take `&self`, so a tree is `Sync` whenever its point type is `Send` and `Sync`,
+/// and can be shared between threads for concurrent reads, e.g. behind an `Arc` or with
+/// `par_query_map`. Mutation needs exclusive access, so mixing reads and writes across threads
+/// requires an external lock such as `RwLock`.
 pub struct KdTree<DataType, T> {
     tree: Vec<Option<Node<DataType>>>,           // Vector of nodes
     num_dimensions: usize,                       // Number of dimensions in DataType
     max_levels: usize,                           // Total levels in tree
     last_point: usize,                           // Index of last node in tree vector
+    tombstones: usize,                           // Number of removed nodes still in tree vector
+    metric: Metric,                              // Metric used to report distances
+    validate_inputs: bool,                       // Check inputs satisfy metric assumptions
+    active_dims: Option<Vec<bool>>,              // Dimensions used for distance and splits
+    scales: Option<Vec<T>>,                      // Per axis scale dividing coordinate differences
+    split_order: Option<Vec<usize>>,             // Dimensions split on in order of level
+    bucket_size: usize,                          // Most nodes in a leaf bucket
+    buckets: Vec<usize>,                         // Number of nodes in bucket rooted at each slot
+    growth_policy: GrowthPolicy,                 // How storage grows when full
+    distance_fn: Option<Box<dyn DistanceFn<T>>>, // Custom distance replacing the point type's
+    bounds: Option<Vec<T>>,                      // Min then max corner of each node's sub tree
+    auto_rebalance: Option<f64>,                 // Depth relative to balanced that triggers rebuild
+    split_tolerance: Option<T>,                  // Distance from a split within which both sides are searched
+    max_nodes: Option<usize>,                    // Most nodes, including removed ones, tree may hold
+    random_splits: Option<u64>,                  // Seed choosing split dimension of each level
+    #[cfg(feature="std")]
+    query_cache: Option<Mutex<QueryCache<DataType, T>>>, // Closest points of recent queries
     float_type: PhantomData<T>,                  // Specify what type of float the tree holds
 }
 
+/// Strategies for growing node storage when tree is full
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GrowthPolicy {
+    Double,                                      // Double storage, fewest reallocations
+    Exact,                                       // Grow by one point, never over allocates
+    Fixed(usize),                                // Grow by fixed number of points
+}
+
+/// Node of an index tree, holding only the split of a point and the row it is stored at
+struct IndexNode<T> {
+    row: usize,                                  // Row of point in external dataset
+    split: T,                                    // Value of point in split dimension
+    dimension: usize,                            // Split dimension of current node
+    left_child: usize,                           // Index of left child (0 if no left child)
+    right_child: usize,                          // Index of right child (0 if no right child)
+}
+
+/// Tree over rows of an external dataset that stores no point coordinates
+///
+/// Each node only keeps its split value, split dimension and row, and full points are fetched
+/// from the dataset with a closure passed to every query. This trades extra fetches for memory,
+/// which suits datasets too large to copy into a tree, such as memory mapped files.
+pub struct KdIndex<T> {
+    tree: Vec<Option<IndexNode<T>>>,             // Vector of nodes
+    num_dimensions: usize,                       // Number of dimensions of points
+    max_levels: usize,                           // Total levels in tree
+}
+
+/// Tree over the rows of a borrowed 2-D array, which references rows by index instead of copying
+/// each into a point
+///
+/// An index tree whose rows are read from the array as `Cow<[f64]>` points. Rows of arrays in
+/// standard layout are borrowed in place, other layouts copy each row as it is read.
+#[cfg(feature="default")]
+pub struct KdTreeRef<'a> {
+    index: KdIndex<f64>,                         // Tree of rows of the array
+    rows: ArrayView2<'a, f64>,                   // Array holding one point per row
+}
+
+/// Node of a flat tree, whose point is stored in the tree's coordinate array
+struct FlatNode {
+    left_child: usize,                           // Index of left child (0 if no left child)
+    right_child: usize,                          // Index of right child (0 if no right child)
+    dimension: usize,                            // Split dimension of current node
+}
+
+/// Tree of coordinate slices storing every point in one contiguous array
+///
+/// Nodes only hold their children and split dimension, and the point of node `i` is at
+/// `coords[i * num_dimensions..]`. For `Vec<f64>` points a `KdTree` node takes about 80 bytes plus
+/// a separate heap allocation per point, while a flat node takes 24 bytes next to its point's
+/// coordinates, which improves locality when searching. Distances are Euclidean.
+pub struct KdTreeFlat<T> {
+    nodes: Vec<FlatNode>,                        // Vector of nodes, root at index 0
+    coords: Vec<T>,                              // Coordinates of each node's point in node order
+    num_dimensions: usize,                       // Number of dimensions of points
+    max_levels: usize,                           // Total levels in tree
+}
+
+/// Tree pairing every point with a payload of type V
+///
+/// Payloads are stored in the order of the nodes holding their points, so they follow their points
+/// through balanced builds. Points can't be removed, since rebuilding after removals would move
+/// nodes away from their payloads.
+pub struct KdTreeMap<DataType, V, T> {
+    tree: KdTree<DataType, T>,                   // Tree of points
+    values: Vec<V>,                              // Payload of the point stored at each index
+}
+
+/// Closest points paired with their payloads, see `KdTreeMap::k_nearest`
+pub type ClosestWithValues<'a, DataType, V, T> = Vec<(Closest<DataType, T>, &'a V)>;
+
+/// Per axis normalization applied to coordinates before computing distances
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Normalization {
+    None,                                        // Use coordinates as given
+    MinMax,                                      // Scale each axis by its range
+    ZScore,                                      // Scale each axis by its standard deviation
+}
+
+/// Distance metrics supported by tree
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Metric {
+    Euclidean,                                   // Distance defined by point type
+    Cosine,                                      // Cosine distance, requires unit length points
+}
+
+/// How the dimension each level of a tree splits on is chosen
+#[derive(Clone, Debug, PartialEq)]
+pub enum SplitStrategy {
+    Cycle,                                       // Cycle through dimensions level by level
+    Order(Vec<usize>),                           // Cycle through given dimensions, which may repeat
+    Random(u64),                                 // Choose pseudo randomly from seed, see `KdTreeBuilder::random_splits`
+}
+
 /// Error types
 #[derive(Debug, PartialEq)]
 pub enum KdError {
@@ -30,11 +166,15 @@ pub enum KdError {
     EmptyTree,                                   // No nodes in tree
     NodeMissing,                                 // Node doesn't exist
     BinaryHeapError,                             // Error associated with binary heap object
+    InvalidInput,                                // Point doesn't satisfy assumptions of metric
+    InvalidConfiguration,                        // Incompatible tree options
+    NonFiniteValue { dimension: usize },         // Point has a NaN or infinite coordinate
+    CapacityExceeded,                            // Tree would hold more nodes than its limit
 }
 
 /// Node type used by tree to tell which direction to go in search
-#[derive(Copy, Clone)]
-enum NodeType {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NodeType {
     RootNode,                                    // First node in tree
     LeftChild,                                   // Node is left child
     RightChild,                                  // Node is right child
@@ -44,261 +184,3897 @@ enum NodeType {
 pub struct Closest<DataType, T> {
     pub point: DataType,                         // Closest point to query point
     pub distance: T,                             // Distance to closest point
+    index: Option<usize>,                        // Index of node point was found at, if known
+}
+
+/// Up to K closest points in ascending distance, padded with `None`, and how many were found,
+/// see `KdTree::k_nearest_array`
+pub type NearestArray<DataType, T, const K: usize> = ([Option<Closest<DataType, T>>; K], usize);
+
+/// Statistics collected while searching tree
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct SearchStats {
+    pub nodes_visited: usize,                    // Nodes whose distance to query was checked
+    pub subtrees_pruned: usize,                  // Sibling subtrees skipped by split plane check
+    pub max_depth_reached: usize,                // Deepest level of a visited node
+}
+
+/// Read only view of a node for walking the tree, see `KdTree::nodes`
+///
+/// Children are given by the index of their node, so following them means looking up the node
+/// with that index. Indices are the same as those of `get_point` and search results.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NodeInfo<'a, DataType> {
+    pub index: usize,                            // Index of node
+    pub dimension: usize,                        // Dimension node splits on
+    pub level: usize,                            // Depth of node, zero for the root
+    pub left: Option<usize>,                     // Node holding points less than the split
+    pub right: Option<usize>,                    // Node holding points greater or equal to the split
+    pub removed: bool,                           // Point was removed but node is kept for search
+    pub point: &'a DataType,                     // Point stored at node, its value in dimension is the split
+}
+
+/// Raw node of a tree, for custom persistence with `KdTree::into_parts` and `KdTree::from_parts`
+///
+/// Nodes mirror the tree's storage, so slot 0 of a node vector is always empty, the root is at
+/// slot 1 and children are slots of the same vector, with 0 meaning there is no child.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RawNode<DataType> {
+    pub point: DataType,                         // Point stored at node, its value in dimension is the split
+    pub child_type: NodeType,                    // Side of parent node is on
+    pub parent: usize,                           // Slot of parent node (0 for the root)
+    pub left_child: usize,                       // Slot of left child (0 if no left child)
+    pub right_child: usize,                      // Slot of right child (0 if no right child)
+    pub dimension: usize,                        // Split dimension of node
+    pub level: usize,                            // Depth of node, zero for the root
+    pub removed: bool,                           // Point was removed but node is kept for search
+}
+
+/// Closest points found by recent queries, keyed by the grid cell each query rounds to, see
+/// `KdTreeBuilder::query_cache`
+#[cfg(feature="std")]
+struct QueryCache<DataType, T> {
+    capacity: usize,                             // Most queries remembered
+    quantization: T,                             // Spacing of grid queries are rounded to
+    entries: HashMap<Vec<i64>, (DataType, u64)>, // Closest point of each cell and when it was last used
+    recency: BTreeMap<u64, Vec<i64>>,            // Cells by when they were last used, oldest first
+    clock: u64,                                  // Counter ordering uses of the cache
+}
+
+/// QueryCache functions
+#[cfg(feature="std")]
+impl<T: Float, DataType: Point<T> + Clone> QueryCache<DataType, T> {
+    /// Grid cell query point rounds to, or `None` if it is too far out to round
+    fn key(&self, query_point: &DataType) -> Option<Vec<i64>> {
+        (0..query_point.dimensions()).map(|i| (query_point.coord(i) / self.quantization).round().to_i64()).collect()
+    }
+
+    /// Create an empty cache remembering up to capacity cells
+    fn new(capacity: usize, quantization: T) -> Self {
+        QueryCache { capacity, quantization, entries: HashMap::new(), recency: BTreeMap::new(), clock: 0 }
+    }
+
+    /// Closest point remembered for cell, marking it as most recently used
+    fn get(&mut self, key: &[i64]) -> Option<DataType> {
+        self.clock += 1;
+        let (point, last_used) = self.entries.get_mut(key)?;
+        let cell = self.recency.remove(last_used)?;
+        *last_used = self.clock;
+        self.recency.insert(self.clock, cell);
+        Some(point.clone())
+    }
+
+    /// Remember closest point for cell, forgetting the least recently used one when full
+    fn insert(&mut self, key: Vec<i64>, point: DataType) {
+        self.clock += 1;
+        if let Some((_, last_used)) = self.entries.remove(&key) {
+            self.recency.remove(&last_used);
+        } else if self.entries.len() >= self.capacity {
+            let oldest = self.recency.keys().next().copied();
+            if let Some(cell) = oldest.and_then(|oldest| self.recency.remove(&oldest)) { self.entries.remove(&cell); }
+        }
+        self.recency.insert(self.clock, key.clone());
+        self.entries.insert(key, (point, self.clock));
+    }
+
+    /// Forget every cell
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+/// Reusable buffers for nearest neighbor queries, see `KdTree::find_n_closest_into`
+pub struct QueryScratch<T> {
+    candidates: BinaryHeap<Closest<usize, T>>,   // Indices of closest nodes found so far
+    path: Vec<(usize, NodeType)>,                // Nodes left to check on the way back up
+}
+
+/// Node reached searching back up a path, as measured for `search_path`
+struct PathNode<T> {
+    candidate: Option<Closest<usize, T>>,        // Node and its distance, none if it can't be kept
+    left_child: usize,                           // Index of left child (0 if no left child)
+    right_child: usize,                          // Index of right child (0 if no right child)
+    level: usize,                                // Level of node, 0 for trees that don't keep levels
 }
 
 /// Trait that must be satisfied for user defined point types (already defined for Vec types)
+///
+/// Only `coord`, `set_coord` and `dimensions` have to be implemented, the rest default to
+/// Euclidean distance and splits computed from coordinates. Types with a cheaper way to compute
+/// them, or another distance, can override the defaults.
 pub trait Point<T: Float> {
+    /// Dimensionality shared by every point of the type if it is known at compile time, which lets
+    /// code branch on it for fixed size points
+    const DIMS: Option<usize> = None;
     /// Distance from one point to another
-    fn distance(&self, other: &Self) -> Result<T, KdError>;
+    fn distance(&self, other: &Self) -> Result<T, KdError> {
+        if self.dimensions() != other.dimensions() { return Err(KdError::DimensionError); }
+
+        let distance = (0..self.dimensions()).fold(T::zero(), |distance, i| {
+            let diff = self.coord(i) - other.coord(i);
+            distance + diff * diff
+        });
+        Ok(distance.sqrt())
+    }
+    /// Square of the distance from one point to another, which orders points the same as
+    /// `distance`
+    ///
+    /// Searches compare squared distances and only take the square root of the ones they return,
+    /// so types that compute `distance` as the square root of a sum should override this to skip
+    /// it.
+    fn distance_squared(&self, other: &Self) -> Result<T, KdError> {
+        self.distance(other).map(|distance| distance * distance)
+    }
     /// Is point greater than other in current dimension
-    fn greater(&self, other: &Self, cur_dimesnion: usize) -> bool;
+    fn greater(&self, other: &Self, cur_dimesnion: usize) -> bool {
+        self.coord(cur_dimesnion) > other.coord(cur_dimesnion)
+    }
     /// Create point that only contains value in current dimension
-    fn split_plane(&self, cur_dimension: usize) -> Self;
+    fn split_plane(&self, cur_dimension: usize) -> Self where Self: Clone {
+        let mut plane = self.clone();
+        for i in (0..self.dimensions()).filter(|&i| i != cur_dimension) {
+            plane.set_coord(i, T::zero());
+        }
+        plane
+    }
+    /// Value of point in current dimension
+    fn coord(&self, cur_dimension: usize) -> T;
+    /// Set value of point in current dimension
+    fn set_coord(&mut self, cur_dimension: usize, value: T);
+    /// Point halfway between point and other in every dimension
+    fn midpoint(&self, other: &Self) -> Self where Self: Clone {
+        let mut midpoint = self.clone();
+        for i in 0..self.dimensions() {
+            midpoint.set_coord(i, (self.coord(i) + other.coord(i)) / (T::one() + T::one()));
+        }
+        midpoint
+    }
     /// Dimensionality of point
     fn dimensions(&self) -> usize;
+    /// Point holding only the values of point in dims, in that order, e.g. to build a tree over a
+    /// subset of the dimensions
+    ///
+    /// Returns `KdError::DimensionError` if a dimension is past the end of point. The default can
+    /// only reorder dimensions, so it also returns it unless dims has one entry per dimension.
+    /// Point types holding any number of dimensions override it to drop dimensions too.
+    fn project(&self, dims: &[usize]) -> Result<Self, KdError> where Self: Clone {
+        if dims.len() != self.dimensions() || dims.iter().any(|&dim| dim >= self.dimensions()) { return Err(KdError::DimensionError); }
+
+        let mut projected = self.clone();
+        for (i, &dim) in dims.iter().enumerate() {
+            projected.set_coord(i, self.coord(dim));
+        }
+        Ok(projected)
+    }
+    /// Minimum distance from point to the axis aligned box between corners min and max
+    ///
+    /// Zero for points inside the box. Point types overriding `distance` should override this to
+    /// match, so it stays a lower bound on the distance to any point in the box.
+    fn dist_to_box(&self, min: &Self, max: &Self) -> Result<T, KdError> {
+        if min.dimensions() != self.dimensions() || max.dimensions() != self.dimensions() { return Err(KdError::DimensionError); }
+
+        let distance = (0..self.dimensions()).fold(T::zero(), |distance, i| {
+            let value = self.coord(i);
+            let gap = (min.coord(i) - value).max(value - max.coord(i)).max(T::zero());
+            distance + gap * gap
+        });
+        Ok(distance.sqrt())
+    }
+    /// Whether every coordinate of point is finite
+    fn is_finite(&self) -> bool {
+        (0..self.dimensions()).all(|i| self.coord(i).is_finite())
+    }
+}
+
+/// Custom distance between coordinates used in place of the point type's distance
+///
+/// Searches prune a subtree when the bound on the split axis is at least the distance to the
+/// farthest neighbor found, so `axis_lower_bound` must be admissible: for any points `p` and `q`,
+/// `distance(p, q) >= axis_lower_bound(p[dim], q[dim], dim)`. An inadmissible bound silently
+/// misses neighbors. For metrics like Mahalanobis a safe bound is the difference scaled by the
+/// square root of the smallest eigenvalue of the inverse covariance, while zero is always
+/// admissible but disables pruning.
+pub trait DistanceFn<T>: Send + Sync {
+    /// Distance between two points given as coordinate slices
+    fn distance(&self, a: &[T], b: &[T]) -> T;
+    /// Lower bound on the distance between points whose values in dimension are a and b
+    fn axis_lower_bound(&self, a: T, b: T, dim: usize) -> T;
 }
 
 /// KdTree functions
 impl<T: Float, DataType: Point<T> + Clone> KdTree<DataType, T> {
     /// Create a new tree with specified number of dimensions
+    ///
+    /// Zero dimensions means dimensionality is inferred from the first point added, and
+    /// subsequent points are validated against it.
     pub fn new(dimensions: usize) -> Self {
         // Default to capacity of 100 if no capacity is given
-        let mut new_tree = KdTree {
-            tree: Vec::with_capacity(100),
-            num_dimensions: dimensions,
-            max_levels: 0,
-            last_point: 1,
-            float_type: PhantomData,
-        };
-        new_tree.tree.resize_with(100, Default::default);
-        new_tree
+        Self::with_capacity(dimensions, 100)
     }
 
     /// Create a new tree with specified number of dimensions and storage for specified capacity
+    ///
+    /// Storage is only reserved, not filled, so even huge capacities are created quickly.
     pub fn with_capacity(dimensions: usize, capacity: usize) -> Self {
-        let mut new_tree = KdTree {
-            tree: Vec::with_capacity(capacity),
+        Self::builder().dimensions(dimensions).capacity(capacity).create()
+    }
+
+    /// Create a builder to configure a new tree
+    pub fn builder() -> KdTreeBuilder<DataType, T> {
+        KdTreeBuilder::new()
+    }
+
+    /// Create a new tree where only active dimensions contribute to distance and are split on
+    ///
+    /// Results are equivalent to a tree of points with the inactive dimensions projected away.
+    pub fn with_active_dims(dimensions: usize, active: Vec<bool>) -> Result<Self, KdError> {
+        Self::builder().dimensions(dimensions).active_dims(active).build()
+    }
+
+    /// Create a new tree with specified number of dimensions that reports distances with metric
+    ///
+    /// `Metric::Cosine` searches with the point type's distance, which must be Euclidean, and
+    /// assumes all points are unit length.
+    pub fn with_metric(dimensions: usize, metric: Metric) -> Self {
+        Self::builder().dimensions(dimensions).metric(metric).create()
+    }
+
+    /// Create a new tree like `with_metric` that also verifies inserted and query points satisfy
+    /// the assumptions of the metric, returning `KdError::InvalidInput` otherwise. The check is
+    /// opt-in so unvalidated trees don't pay for it.
+    pub fn with_metric_validated(dimensions: usize, metric: Metric) -> Self {
+        Self::builder().dimensions(dimensions).metric(metric).validate_inputs(true).create()
+    }
+
+    /// Create a balanced tree from a vector of points by recursively splitting on the median
+    pub fn build_from_vec(dimensions: usize, points: Vec<DataType>) -> Result<Self, KdError> {
+        // Infer dimensions from first point if none were given
+        let dimensions = infer_dimensions(dimensions, &points);
+
+        // Verify all points have proper number of dimensions
+        if points.iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() { check_finite(point)?; }
+
+        let split_dims: Vec<usize> = (0..dimensions).collect();
+        Ok(Self::build_with_splits(dimensions, points, &split_dims))
+    }
+
+    /// Create a balanced tree like `build_from_vec`, calling progress with the number of points
+    /// placed so far and the total number of points as the build goes
+    ///
+    /// Progress is reported about every hundredth of the points and once all of them are placed,
+    /// so the number placed only increases and ends at the total. The build still runs on the
+    /// calling thread, the callback lets callers update a progress display or yield to other work.
+    pub fn build_from_vec_with_progress<F: FnMut(usize, usize)>(dimensions: usize, points: Vec<DataType>, mut progress: F) -> Result<Self, KdError> {
+        // Infer dimensions from first point if none were given
+        let dimensions = infer_dimensions(dimensions, &points);
+
+        // Verify all points have proper number of dimensions
+        if points.iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() { check_finite(point)?; }
+
+        let total = points.len();
+        let step = (total / 100).max(1);
+        let mut done = 0;
+        let split_dims: Vec<usize> = (0..dimensions).collect();
+        let (skeleton, max_levels) = Self::build_skeleton_with_progress(&points, &split_dims, &mut || {
+            done += 1;
+            if done % step == 0 || done == total { progress(done, total); }
+        });
+        if total == 0 { progress(0, 0); }
+
+        Ok(Self::assemble(dimensions, points, skeleton, max_levels))
+    }
+
+    /// Create a balanced tree like `build_from_vec` from points already sorted along sorted_axis,
+    /// taking the root split from the existing order instead of selecting a median
+    ///
+    /// The order can only be used when sorted_axis is the axis the root splits on, which is axis
+    /// 0, other axes build the tree as usual. Sub trees are split on medians like any balanced
+    /// build. Points that aren't sorted return `KdError::InvalidInput`.
+    pub fn build_presorted(dimensions: usize, points: Vec<DataType>, sorted_axis: usize) -> Result<Self, KdError> {
+        // Infer dimensions from first point if none were given
+        let dimensions = infer_dimensions(dimensions, &points);
+        if points.is_empty() { return Self::build_from_vec(dimensions, points); }
+
+        // Verify all points have proper number of dimensions and are sorted
+        if sorted_axis >= dimensions || points.iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() { check_finite(point)?; }
+        if points.windows(2).any(|pair| pair[0].greater(&pair[1], sorted_axis)) { return Err(KdError::InvalidInput); }
+
+        let split_dims: Vec<usize> = (0..dimensions).collect();
+        if sorted_axis != split_dims[0] { return Ok(Self::build_with_splits(dimensions, points, &split_dims)); }
+
+        // Left sub trees only hold points less than the split, so the root is the first point
+        // equal to the median
+        let mid = points.len() / 2;
+        let split = points[..mid].partition_point(|point| points[mid].greater(point, sorted_axis));
+
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        let mut skeleton: Vec<Option<Node<usize>>> = Vec::with_capacity(points.len());
+        skeleton.resize_with(points.len(), Default::default);
+        skeleton[0] = Some(Node {
+            point: split,
+            child_type: NodeType::RootNode,
+            parent: 0,
+            left_child: if split == 0 { 0 } else { 2 },
+            right_child: if split + 1 == points.len() { 0 } else { 2 + split },
+            dimension: sorted_axis,
+            level: 0,
+            removed: false,
+        });
+
+        let (left_order, rest) = order.split_at_mut(split);
+        let (left_nodes, right_nodes) = skeleton[1..].split_at_mut(split);
+        let left_levels = build_subtree(&points, left_order, left_nodes, 2, 1, NodeType::LeftChild, 1, &split_dims, &mut || {});
+        let right_levels = build_subtree(&points, &mut rest[1..], right_nodes, 2 + split, 1, NodeType::RightChild, 1, &split_dims, &mut || {});
+
+        Ok(Self::assemble(dimensions, points, skeleton, left_levels.max(right_levels)))
+    }
+
+    /// Build a balanced tree cycling through the given split dimensions
+    fn build_with_splits(dimensions: usize, points: Vec<DataType>, split_dims: &[usize]) -> Self {
+        let (skeleton, max_levels) = Self::build_skeleton(&points, split_dims);
+
+        Self::assemble(dimensions, points, skeleton, max_levels)
+    }
+
+    /// Generate the node skeleton of a balanced tree of points, returning it with its max level
+    fn build_skeleton(points: &[DataType], split_dims: &[usize]) -> (Vec<Option<Node<usize>>>, usize) {
+        Self::build_skeleton_with_progress(points, split_dims, &mut || {})
+    }
+
+    /// Generate the node skeleton like `build_skeleton`, calling placed after each node is placed
+    fn build_skeleton_with_progress(points: &[DataType], split_dims: &[usize], placed: &mut dyn FnMut()) -> (Vec<Option<Node<usize>>>, usize) {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        let mut skeleton: Vec<Option<Node<usize>>> = Vec::with_capacity(points.len());
+        skeleton.resize_with(points.len(), Default::default);
+        let max_levels = build_subtree(points, &mut order, &mut skeleton, 1, 0, NodeType::RootNode, 0, split_dims, placed);
+
+        (skeleton, max_levels)
+    }
+
+    /// Replace all points of the tree with a balanced tree of points, keeping tree settings
+    ///
+    /// Existing node storage is reused, so no allocation happens when points fit in the current
+    /// capacity. Points are validated before anything is changed, so the tree is left untouched
+    /// if any point is rejected.
+    pub fn replace_with(&mut self, points: Vec<DataType>) -> Result<(), KdError> {
+        // Trees without dimensions adopt them from the first point
+        let dimensions = infer_dimensions(self.num_dimensions, &points);
+        if points.iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() {
+            check_finite(point)?;
+            self.validate_input(point)?;
+        }
+        self.check_node_limit(points.len())?;
+
+        self.num_dimensions = dimensions;
+        let (skeleton, max_levels) = Self::build_skeleton(&points, &self.split_dims());
+        let num_points = points.len();
+
+        self.clear_query_cache();
+        self.tree.clear();
+        fill_nodes(&mut self.tree, points, skeleton);
+        self.max_levels = max_levels;
+        self.last_point = num_points + 1;
+        self.tombstones = 0;
+        self.update_bounds();
+        self.update_buckets();
+
+        Ok(())
+    }
+
+    /// Rebuild tree as a balanced tree of its points, dropping removed nodes
+    ///
+    /// Points are stored at new indices afterwards.
+    pub fn rebuild(&mut self) {
+        let split_dims = self.split_dims();
+        let points = self.take_points();
+        let rebuilt = Self::build_with_splits(self.num_dimensions, points, &split_dims);
+        self.replace_nodes(rebuilt);
+    }
+
+    /// Rebalance tree like `rebuild`, reusing the existing node storage instead of allocating a
+    /// new tree
+    ///
+    /// Removed nodes are dropped and capacity is unchanged. Points are stored at new indices
+    /// afterwards.
+    pub fn balance_in_place(&mut self) {
+        self.clear_query_cache();
+
+        // Drop removed nodes and move the rest to the front, keeping their order
+        let mut live = 1;
+        for index in 1..self.last_point {
+            if matches!(&self.tree[index], Some(node) if !node.removed) {
+                self.tree.swap(live, index);
+                live += 1;
+            } else {
+                self.tree[index] = None;
+            }
+        }
+
+        let split_dims = self.split_dims();
+        self.max_levels = balance_subtree(&mut self.tree[1..live], 1, 0, NodeType::RootNode, 0, &split_dims);
+        self.last_point = live;
+        self.tombstones = 0;
+        self.update_bounds();
+        self.update_buckets();
+    }
+
+    /// Drop removed nodes that aren't needed to reach other points and shift the rest down,
+    /// without rebalancing
+    ///
+    /// Removed nodes with children are kept to preserve the structure, so the cost is linear in
+    /// the number of nodes rather than the O(n log n) of `rebuild`. Unused storage is released.
+    /// Points are stored at new indices afterwards.
+    pub fn compact(&mut self) {
+        self.clear_query_cache();
+
+        // Children are always stored after their parents, so going through nodes in reverse
+        // decides whether children are kept before their parents
+        let mut keep = vec![false; self.last_point];
+        for index in (1..self.last_point).rev() {
+            if let Some(node) = &self.tree[index] {
+                keep[index] = !node.removed || keep[node.left_child] || keep[node.right_child];
+            }
+        }
+
+        // Shift kept nodes down keeping their order, which keeps children after their parents
+        let mut new_index = vec![0; self.last_point];
+        let mut live = 1;
+        for index in 1..self.last_point {
+            if keep[index] {
+                self.tree.swap(live, index);
+                new_index[index] = live;
+                live += 1;
+            }
+        }
+        for slot in self.tree[live..].iter_mut() { *slot = None; }
+
+        let mut tombstones = 0;
+        let mut max_levels = 0;
+        for node in self.tree[1..live].iter_mut().flatten() {
+            node.parent = new_index[node.parent];
+            node.left_child = new_index[node.left_child];
+            node.right_child = new_index[node.right_child];
+            if node.removed { tombstones += 1; }
+            max_levels = max_levels.max(node.level);
+        }
+
+        // Keep one free slot for insertion like a balanced build
+        self.tree.truncate(live + 1);
+        self.tree.shrink_to_fit();
+        self.last_point = live;
+        self.tombstones = tombstones;
+        self.max_levels = max_levels;
+        self.update_bounds();
+        self.update_buckets();
+    }
+
+    /// Check structure of tree, returning `KdError::NodeMissing` if it is corrupt
+    ///
+    /// Verifies that parents and children link to each other, children are stored after their
+    /// parents one level deeper and split on the dimension for their level, every point is on the
+    /// correct side of the splits above it, and the count of removed nodes matches. Checking sides
+    /// walks up from every node, taking O(n log n) time on a balanced tree.
+    pub fn validate(&self) -> Result<(), KdError> {
+        let tombstones = self.tree[1..self.last_point].iter().filter(|slot| !matches!(slot, Some(node) if !node.removed)).count();
+        if tombstones != self.tombstones { return Err(KdError::NodeMissing); }
+
+        let mut stack = match &self.tree[1] {
+            Some(root) if root.parent == 0 && root.level == 0 => vec![1],
+            Some(_) => return Err(KdError::NodeMissing),
+            None => Vec::new(),
+        };
+        while let Some(index) = stack.pop() {
+            let node = self.tree[index].as_ref().ok_or(KdError::NodeMissing)?;
+            if node.level > self.max_levels || node.dimension != self.split_dimension(node.level) { return Err(KdError::NodeMissing); }
+
+            for &(child, is_left) in [(node.left_child, true), (node.right_child, false)].iter() {
+                if child == 0 { continue; }
+                let linked = child > index && child < self.last_point && match &self.tree[child] {
+                    Some(child_node) => {
+                        let side_matches = match child_node.child_type {
+                            NodeType::LeftChild => is_left,
+                            NodeType::RightChild => !is_left,
+                            NodeType::RootNode => false,
+                        };
+                        child_node.parent == index && child_node.level == node.level + 1 && side_matches
+                    },
+                    None => false,
+                };
+                if !linked { return Err(KdError::NodeMissing); }
+                stack.push(child);
+            }
+
+            // Left sub trees hold points less than the split and right sub trees the rest
+            let (mut child_type, mut ancestor) = (node.child_type, node.parent);
+            while let Some(ancestor_node) = &self.tree[ancestor] {
+                let greater = ancestor_node.point.greater(&node.point, ancestor_node.dimension);
+                let correct_side = match child_type {
+                    NodeType::LeftChild => greater,
+                    NodeType::RightChild => !greater,
+                    NodeType::RootNode => true,
+                };
+                if !correct_side { return Err(KdError::NodeMissing); }
+                child_type = ancestor_node.child_type;
+                ancestor = ancestor_node.parent;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Zero child links of nodes that point at empty slots, returning how many were repaired
+    ///
+    /// This is a safety net for trees whose structure was damaged, e.g. by a bug in code mutating
+    /// it. Links past the last node count as empty, and the count of removed nodes is recomputed
+    /// from the slots. Points of sub trees that lost their link stay in the tree but are no longer
+    /// found by searches until it is rebuilt.
+    pub fn repair(&mut self) -> usize {
+        self.clear_query_cache();
+        let last_point = self.last_point;
+        let is_empty: Vec<bool> = self.tree[..last_point].iter().map(|slot| slot.is_none()).collect();
+        let dangling = |child: usize| child != 0 && (child >= last_point || is_empty[child]);
+
+        let mut repairs = 0;
+        for node in self.tree[1..last_point].iter_mut().flatten() {
+            for child in [&mut node.left_child, &mut node.right_child].iter_mut() {
+                if dangling(**child) {
+                    **child = 0;
+                    repairs += 1;
+                }
+            }
+        }
+
+        self.tombstones = self.tree[1..last_point].iter().filter(|slot| !matches!(slot, Some(node) if !node.removed)).count();
+        repairs
+    }
+
+    /// Empty the slot at index without unlinking it, corrupting the tree for tests of `repair`
+    #[cfg(test)]
+    pub(crate) fn clear_slot(&mut self, index: usize) {
+        self.tree[index] = None;
+    }
+
+    /// Move all points that haven't been removed out of the tree
+    fn take_points(&mut self) -> Vec<DataType> {
+        self.clear_query_cache();
+        self.tree.drain(..).flatten().filter(|node| !node.removed).map(|node| node.point).collect()
+    }
+
+    /// Replace nodes of tree with those of a rebuilt tree, keeping tree settings
+    fn replace_nodes(&mut self, rebuilt: Self) {
+        self.tree = rebuilt.tree;
+        self.max_levels = rebuilt.max_levels;
+        self.last_point = rebuilt.last_point;
+        self.tombstones = 0;
+        self.update_bounds();
+        self.update_buckets();
+    }
+
+    /// Move points into the node skeleton generated by a balanced build
+    fn assemble(dimensions: usize, points: Vec<DataType>, skeleton: Vec<Option<Node<usize>>>, max_levels: usize) -> Self {
+        let num_points = points.len();
+        let mut tree = Vec::with_capacity(num_points + 2);
+        fill_nodes(&mut tree, points, skeleton);
+
+        KdTree {
+            tree,
             num_dimensions: dimensions,
-            max_levels: 0,
-            last_point: 1,
+            max_levels,
+            last_point: num_points + 1,
+            tombstones: 0,
+            metric: Metric::Euclidean,
+            validate_inputs: false,
+            active_dims: None,
+            scales: None,
+            split_order: None,
+            bucket_size: 1,
+            buckets: Vec::new(),
+            growth_policy: GrowthPolicy::Double,
+            distance_fn: None,
+            bounds: None,
+            auto_rebalance: None,
+            split_tolerance: None,
+            max_nodes: None,
+            random_splits: None,
+            #[cfg(feature="std")]
+            query_cache: None,
             float_type: PhantomData,
-        };
-        new_tree.tree.resize_with(capacity, Default::default);
-        new_tree
+        }
     }
 
     /// Add a point to the tree
     pub fn add_point(&mut self, query_point: DataType) -> Result<(), KdError> {
+        // Adopt dimensionality of first point if tree was created without one
+        if self.num_dimensions == 0 && self.last_point == 1 { self.num_dimensions = query_point.dimensions(); }
+
         // Verify point has proper number of dimensions
-        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        if query_point.dimensions() != self.num_dimensions || self.num_dimensions == 0 { return Err(KdError::DimensionError); }
+        check_finite(&query_point)?;
+        self.validate_input(&query_point)?;
+        self.check_node_limit(self.last_point)?;
+
+        // Safety: dimensionality was verified above
+        unsafe { self.add_point_unchecked(query_point); }
+
+        Ok(())
+    }
+
+    /// Find the closest point already in the tree to point, then add point to the tree
+    ///
+    /// Returns `None` for the first point. The tree is left unchanged if point is rejected.
+    pub fn query_then_insert(&mut self, point: DataType) -> Result<Option<(DataType, T)>, KdError> {
+        let nearest = self.nearest(&point)?;
+        self.add_point(point)?;
+
+        Ok(nearest)
+    }
 
+    /// Add a point to the tree without verifying its dimensionality
+    ///
+    /// # Safety
+    ///
+    /// Caller must guarantee that `query_point.dimensions()` equals the number of dimensions of
+    /// the tree. Inserting a point with the wrong dimensionality corrupts the tree structure, after
+    /// which queries may panic or return incorrect results.
+    pub unsafe fn add_point_unchecked(&mut self, query_point: DataType) {
         // Check if root node, if not go down to find proper place in tree
         let (parent_index, child_type) = if self.tree[1].is_none() {
             (0, NodeType::RootNode)
         } else {
-            self.go_down(&query_point, 1)?
+            self.go_down(&query_point, 1)
         };
 
-        // Get level and split dimension of node
-        let (current_dimension, current_level) = if self.last_point == 1 {
-            (0, 0)
-        } else {
-            if let Some(node) = &mut self.tree[parent_index] {
+        // Get level of node and link it to its parent
+        let current_level = match &mut self.tree[parent_index] {
+            Some(node) => {
                 match child_type {
                     NodeType::LeftChild => { node.left_child = self.last_point; },
                     NodeType::RightChild => { node.right_child = self.last_point; },
                     NodeType::RootNode => { },
                 }
 
-                ((node.dimension + 1) % self.num_dimensions, node.level + 1)
-            } else {
-                return Err(KdError::DimensionError);
-            }
+                node.level + 1
+            },
+            None => 0,
         };
+        let current_dimension = self.split_dimension(current_level);
 
         // Update max levels
         self.max_levels = self.max_levels.max(current_level);
 
-        // Resize vector if at capacity
-        if self.last_point >= self.tree.len() - 1  {
-            let capacity = self.last_point * 2;
-            self.tree.reserve(capacity);
-            self.tree.resize_with(capacity, Default::default);
+        // The new node isn't stored with the rest of the bucket it falls in
+        self.clear_buckets(parent_index);
+
+        // Grow storage if at capacity
+        if self.last_point >= self.tree.capacity() {
+            let additional = match self.growth_policy {
+                GrowthPolicy::Double => self.last_point,
+                GrowthPolicy::Exact => 1,
+                GrowthPolicy::Fixed(amount) => amount.max(1),
+            };
+            // Never grow past the node limit
+            let additional = match self.max_nodes {
+                Some(max_nodes) => additional.min((max_nodes + 1).saturating_sub(self.last_point)).max(1),
+                None => additional,
+            };
+            self.reserve_exact(additional);
         }
 
-        // Add point
-        self.tree[self.last_point] = Some(Node {
-                                    point: query_point,
-                                    child_type: child_type,
-                                    parent: parent_index,
-                                    left_child: 0,
-                                    right_child: 0,
-                                    dimension: current_dimension,
-                                    level: current_level,
-                                });
+        // Add point, slots past the last point are only pushed once used except for the one kept
+        // free by balanced builds
+        self.clear_query_cache();
+        let node = Some(Node {
+                            point: query_point,
+                            child_type,
+                            parent: parent_index,
+                            left_child: 0,
+                            right_child: 0,
+                            dimension: current_dimension,
+                            level: current_level,
+                            removed: false,
+                        });
+        if self.last_point < self.tree.len() {
+            self.tree[self.last_point] = node;
+        } else {
+            self.tree.push(node);
+        }
+        self.include_in_bounds(self.last_point);
 
         self.last_point += 1;
+    }
 
-        Ok(())
+    /// Verify tree may hold num_nodes nodes without exceeding its node limit
+    fn check_node_limit(&self, num_nodes: usize) -> Result<(), KdError> {
+        match self.max_nodes {
+            Some(max_nodes) if num_nodes > max_nodes => Err(KdError::CapacityExceeded),
+            _ => Ok(()),
+        }
     }
 
-    /// Find absolute closest point to query point
-    pub fn find_closest(&self, query_point: &DataType) -> Result<(DataType, T), KdError> {
-        match self.find_n_closest(query_point, 1)?.pop() {
-            Some(closest) => { Ok((closest.point, closest.distance)) },
-            None => { Err(KdError::BinaryHeapError) },
+    /// Reserve storage for exactly additional more points, regardless of growth policy
+    ///
+    /// Storage is only reserved, so this takes the same time however many points it is for.
+    pub fn reserve_exact(&mut self, additional: usize) {
+        let required = self.last_point + additional;
+        if required > self.tree.capacity() {
+            self.tree.reserve_exact(required - self.tree.len());
         }
     }
 
-    /// Find n closest points to query point
-    pub fn find_n_closest(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
-        // Create binary heap structure to store closest points
-        let mut bh_closest = BinaryHeap::with_capacity(n);
-        // Table to signify whether point has been searched or not
-        let mut searched_table = vec![-1i64; self.max_levels + 1];
-        // Go down to bin containing point
-        let (mut index, mut child_type) = self.go_down(query_point, 1)?;
+    /// Number of points tree can hold without reallocating
+    pub fn capacity(&self) -> usize { self.tree.capacity() - 1 }
 
-        // Go back up tree to see if there are any closer points
-        while let Some(node) = &self.tree[index] {
-            // If node has already been searched go up
-            if searched_table[node.level] == index as i64 {
-                child_type = node.child_type;
-                index = node.parent;
-                continue;
-            }
+    /// Add every point from an iterator to the tree
+    pub fn extend<I: IntoIterator<Item = DataType>>(&mut self, points: I) -> Result<(), KdError> {
+        for point in points {
+            self.add_point(point)?;
+        }
 
-            // Check node
-            let distance = node.point.distance(query_point)?;
-            if bh_closest.len() < n {                               // If binary heap isn't full add point
-                bh_closest.push(Closest { point: index, distance: distance, });
-            } else {                                                // Otherwise check that distance is less than that of the max point in heap
-                if distance < self.get_max_min(&bh_closest)? {
-                    bh_closest.pop();
-                    bh_closest.push(Closest { point: index, distance: distance, });
-                }
-            }
+        Ok(())
+    }
 
-            // Update table to avoid checking node again
-            searched_table[node.level] = index as i64;
+    /// Add every point like `extend`, then rebalance the tree if it has become deeper than the
+    /// `auto_rebalance` threshold set on the builder allows
+    ///
+    /// Checking once per batch keeps the cost of rebalancing amortized over the batch, which suits
+    /// streaming sorted or clustered data that degrades trees built one point at a time. Trees
+    /// without a threshold are never rebalanced. The check also runs if a point is rejected, so the
+    /// points added before it are kept in a tree within the threshold.
+    pub fn add_points_maintained(&mut self, points: Vec<DataType>) -> Result<(), KdError> {
+        let added = self.extend(points);
+        if self.exceeds_rebalance_threshold() { self.balance_in_place(); }
 
-            // See if distance to split plane is less than min to see if other subtree needs to be
-            // searched
-            if node.point.split_plane(node.dimension).distance(&query_point.split_plane(node.dimension))? < self.get_max_min(&bh_closest)? {
-                let sub_tree = match child_type {
-                    NodeType::LeftChild => { node.right_child },
-                    NodeType::RightChild => { node.left_child},
-                    NodeType::RootNode => { 0 },
-                };
+        added
+    }
 
-                let go_down_result = self.go_down(query_point, sub_tree);
-                if let Ok((cur_ind, cur_child)) = go_down_result { index = cur_ind; child_type = cur_child; }
-            }
+    /// Whether tree is deeper than its `auto_rebalance` threshold allows
+    fn exceeds_rebalance_threshold(&self) -> bool {
+        match self.auto_rebalance {
+            Some(factor) => self.max_levels as f64 > factor * ((self.len() + 1) as f64).log2(),
+            None => false,
         }
+    }
 
-        // Get actual points from indices to points in tree vec
-        let mut bh_dtype = BinaryHeap::with_capacity(n);
-        for closest in bh_closest.iter() {
-            if let Some(node) = &self.tree[closest.point] {
-                bh_dtype.push(Closest { point: node.point.clone(), distance: closest.distance });
-            } else {
-                return Err(KdError::NodeMissing);
-            }
+    /// Find absolute closest point to query point
+    ///
+    /// Trees with a query cache first look for an earlier query in the same grid cell, see
+    /// `KdTreeBuilder::query_cache`.
+    pub fn find_closest(&self, query_point: &DataType) -> Result<(DataType, T), KdError> {
+        #[cfg(feature="std")]
+        if let Some(closest) = self.cached_closest(query_point)? { return Ok(closest); }
+
+        match self.find_n_closest_heap(query_point, 1)?.pop() {
+            Some(closest) => {
+                #[cfg(feature="std")]
+                self.cache_closest(query_point, &closest.point);
+                Ok((closest.point, closest.distance))
+            },
+            None => { Err(KdError::BinaryHeapError) },
         }
+    }
 
-        Ok(bh_dtype)
+    /// Closest point remembered for a query in the same grid cell as query point, with its
+    /// distance to query point
+    #[cfg(feature="std")]
+    fn cached_closest(&self, query_point: &DataType) -> Result<Option<(DataType, T)>, KdError> {
+        let cache = match &self.query_cache {
+            Some(cache) => cache,
+            None => return Ok(None),
+        };
+
+        // Queries the search would reject are rejected before they can hit the cache
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        self.validate_input(query_point)?;
+
+        let hit = match cache.lock() {
+            Ok(mut cache) => cache.key(query_point).and_then(|key| cache.get(&key)),
+            Err(_) => None,
+        };
+
+        match hit {
+            Some(point) => {
+                let distance = self.point_distance(&point, query_point)?;
+                Ok(Some((point, self.report_distance(distance))))
+            },
+            None => Ok(None),
+        }
     }
 
-    /// Brute force search for testing
-    pub fn brute_force(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
-        let mut bh_closest = BinaryHeap::with_capacity(n);
-        for (cur_ind, node) in self.tree.iter().enumerate() {
-            if let Some(cur_node) = node {
-                let distance = cur_node.point.distance(query_point)?;
-                if bh_closest.len() < n {
-                    bh_closest.push(Closest { point: cur_ind, distance: distance, });
-                } else {
-                    if distance < self.get_max_min(&bh_closest)? {
-                        bh_closest.pop();
-                        bh_closest.push(Closest { point: cur_ind, distance: distance, });
-                    }
-                }
-            }
+    /// Remember point as the closest to the grid cell of query point
+    #[cfg(feature="std")]
+    fn cache_closest(&self, query_point: &DataType, point: &DataType) {
+        if let Some(Ok(mut cache)) = self.query_cache.as_ref().map(Mutex::lock) {
+            if let Some(key) = cache.key(query_point) { cache.insert(key, point.clone()); }
         }
+    }
 
-        let mut bh_dtype = BinaryHeap::with_capacity(n);
-        for closest in bh_closest.iter() {
-            if let Some(node) = &self.tree[closest.point] {
-                bh_dtype.push(Closest { point: node.point.clone(), distance: closest.distance });
-            } else {
-                return Err(KdError::NodeMissing);
-            }
+    /// Forget closest points of earlier queries, which may no longer be closest once points of
+    /// the tree change
+    fn clear_query_cache(&mut self) {
+        #[cfg(feature="std")]
+        if let Some(cache) = &mut self.query_cache {
+            cache.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()).clear();
         }
+    }
 
-        Ok(bh_dtype)
+    /// Find every point at the minimum distance from query point, in index order
+    ///
+    /// `find_closest` returns an arbitrary one of several equidistant closest points, this
+    /// returns all of them. Distances to points that are equally far apart may round differently,
+    /// so points within a relative tolerance of machine epsilon per dimension count as ties.
+    pub fn find_all_closest(&self, query_point: &DataType) -> Result<Vec<DataType>, KdError> {
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        // Search the tree directly, a cached point may not be the closest one
+        let distance = self.find_n_closest_heap(query_point, 1)?.pop().ok_or(KdError::BinaryHeapError)?.distance;
+        let tolerance = distance * T::epsilon() * T::from(self.num_dimensions).unwrap_or_else(T::one);
+
+        let mut indices = Vec::new();
+        self.search_within_radius(query_point, distance + tolerance, |index, _| indices.push(index))?;
+        indices.sort_unstable();
+
+        Ok(indices.iter().filter_map(|&index| self.tree[index].as_ref().map(|node| node.point.clone())).collect())
+    }
+
+    /// Find absolute closest point to query point, or `None` if the tree is empty
+    ///
+    /// Unlike `find_closest` an empty tree isn't an error, which is kept for query points with the
+    /// wrong dimensionality or non finite coordinates.
+    pub fn nearest(&self, query_point: &DataType) -> Result<Option<(DataType, T)>, KdError> {
+        if self.num_dimensions != 0 && query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        if self.is_empty() { return Ok(None); }
+
+        self.find_closest(query_point).map(Some)
     }
 
-    /// Search tree from root to leaf node
-    fn go_down(&self, query_point: &DataType, root: usize) -> Result<(usize, NodeType), KdError> {
-        // Verify sub tree is not empty
-        if self.tree[root].is_none() {
-            return Err(KdError::EmptyTree);
+    /// Find the closest point to query point like `nearest`, passing a reference to it and its
+    /// distance to f instead of cloning it
+    ///
+    /// Returns what f produces, or `None` without calling f if the tree is empty.
+    pub fn peek_nearest<R, F: FnOnce(&DataType, T) -> R>(&self, query_point: &DataType, f: F) -> Result<Option<R>, KdError> {
+        if self.num_dimensions != 0 && query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        self.validate_input(query_point)?;
+        if self.is_empty() { return Ok(None); }
+
+        let closest = self.search_n_closest(query_point, 1)?.pop().ok_or(KdError::BinaryHeapError)?;
+        match &self.tree[closest.point] {
+            Some(node) => Ok(Some(f(&node.point, self.report_distance(closest.distance)))),
+            None => Err(KdError::NodeMissing),
         }
+    }
 
-        let mut current_index = root;               // Current index starting from root
+    /// Find n closest points to query point
+    ///
+    /// Same as `find_n_closest_heap`, kept for compatibility.
+    pub fn find_n_closest(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        self.find_n_closest_heap(query_point, n)
+    }
+
+    /// Find n closest points to query point like `find_n_closest`, treating points with identical
+    /// coordinates as one so the results are n distinct locations
+    ///
+    /// Which of the coincident points is returned is unspecified.
+    pub fn find_n_closest_dedup(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        self.validate_input(query_point)?;
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        self.search_n_closest_by(n, true, false, &mut SearchStats::default(), &mut scratch,
+                                 |point| self.point_distance(point, query_point),
+                                 |point, dimension| point.greater(query_point, dimension),
+                                 |point, dimension| self.plane_distance(point, query_point, dimension),
+                                 |dimension| query_point.coord(dimension))?;
+
+        scratch.candidates.iter().map(|closest| self.resolve(closest)).collect()
+    }
+
+    /// Find n closest points to query point like `find_n_closest`, leaving out points at distance
+    /// zero from it, e.g. the query itself and any copies of it when querying with a member
+    ///
+    /// Distances within float epsilon of zero count as zero. Fewer than n points are returned if
+    /// the tree doesn't hold n others.
+    pub fn find_n_closest_excl_query(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        self.validate_input(query_point)?;
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        // Excluded points are infinitely far, so any other point replaces them in the heap
+        self.search_n_closest_by(n, false, false, &mut SearchStats::default(), &mut scratch,
+                                 |point| {
+                                     let distance = self.point_distance(point, query_point)?;
+                                     Ok(if distance <= T::epsilon() { T::infinity() } else { distance })
+                                 },
+                                 |point, dimension| point.greater(query_point, dimension),
+                                 |point, dimension| self.plane_distance(point, query_point, dimension),
+                                 |dimension| query_point.coord(dimension))?;
+
+        scratch.candidates.iter()
+                          .filter(|closest| closest.distance < T::infinity())
+                          .map(|closest| self.resolve(closest))
+                          .collect()
+    }
+
+    /// Find n closest points to query point like `find_n_closest`, skipping stored points whose
+    /// distance can't be measured instead of failing, and returning how many were skipped
+    ///
+    /// This keeps queries working on trees holding a few corrupt points, e.g. ones added with
+    /// `add_point_unchecked`. Points with the wrong number of dimensions are skipped too. Split
+    /// planes that can't be measured are searched on both sides, so skipped points never hide
+    /// other neighbors. Errors in the query point itself still fail.
+    pub fn find_n_closest_lenient(&self, query_point: &DataType, n: usize) -> Result<(BinaryHeap<Closest<DataType, T>>, usize), KdError> {
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        self.validate_input(query_point)?;
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        let skipped = Cell::new(0);
+        // Skipped points are infinitely far, so any other point replaces them in the heap. Points
+        // with the wrong number of dimensions are never indexed by dimension, which may panic
+        let wrong_dimensions = |point: &DataType| point.dimensions() != self.num_dimensions;
+        self.search_n_closest_by(n, false, false, &mut SearchStats::default(), &mut scratch,
+                                 |point| match self.point_distance(point, query_point) {
+                                     Ok(distance) if !wrong_dimensions(point) => Ok(distance),
+                                     _ => {
+                                         skipped.set(skipped.get() + 1);
+                                         Ok(T::infinity())
+                                     },
+                                 },
+                                 |point, dimension| !wrong_dimensions(point) && point.greater(query_point, dimension),
+                                 |point, dimension| if wrong_dimensions(point) { Ok(T::zero()) } else {
+                                     Ok(self.plane_distance(point, query_point, dimension).unwrap_or_else(|_| T::zero()))
+                                 },
+                                 |dimension| query_point.coord(dimension))?;
+
+        let closest = scratch.candidates.iter()
+                                        .filter(|closest| closest.distance < T::infinity())
+                                        .map(|closest| self.resolve(closest))
+                                        .collect::<Result<_, _>>()?;
+        Ok((closest, skipped.get()))
+    }
+
+    /// Find n closest points to query point measuring distance only over the dimensions marked
+    /// active, so the others are wildcards that match any value
+    ///
+    /// Like `with_active_dims` this assumes Euclidean distance, so trees with another metric or a
+    /// custom distance return `KdError::InvalidConfiguration`. Sub trees split on a wildcard
+    /// dimension are always searched on both sides.
+    pub fn find_n_closest_masked(&self, query_point: &DataType, active: &[bool], n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        if self.metric != Metric::Euclidean || self.distance_fn.is_some() { return Err(KdError::InvalidConfiguration); }
+        if query_point.dimensions() != self.num_dimensions || active.len() != self.num_dimensions || !active.iter().any(|&is_active| is_active) {
+            return Err(KdError::DimensionError);
+        }
+
+        let is_active = |i: usize| active[i] && match &self.active_dims {
+            Some(tree_active) => tree_active[i],
+            None => true,
+        };
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        self.search_n_closest_by(n, false, false, &mut SearchStats::default(), &mut scratch,
+                                 |point| {
+                                     let distance = (0..self.num_dimensions).filter(|&i| is_active(i)).fold(T::zero(), |distance, i| {
+                                         let diff = (point.coord(i) - query_point.coord(i)) / self.axis_scale(i);
+                                         distance + diff * diff
+                                     });
+                                     Ok(distance.sqrt())
+                                 },
+                                 |point, dimension| point.greater(query_point, dimension),
+                                 |point, dimension| if active[dimension] { self.plane_distance(point, query_point, dimension) } else { Ok(T::zero()) },
+                                 // Max ignores NaN, which puts wildcard dimensions inside any sub tree bounds
+                                 |dimension| if active[dimension] { query_point.coord(dimension) } else { T::nan() })?;
+
+        scratch.candidates.iter().map(|closest| self.resolve(closest)).collect()
+    }
+
+    /// Find k closest points to query point sorted by ascending distance
+    pub fn k_nearest(&self, query_point: &DataType, k: usize) -> Result<Vec<Closest<DataType, T>>, KdError> {
+        Ok(self.find_n_closest_heap(query_point, k)?.into_sorted_vec())
+    }
+
+    /// Find K closest points to query point like `k_nearest`, returning them in a fixed size array
+    /// along with how many were found
+    ///
+    /// The first count entries hold points sorted by ascending distance and the rest are `None`.
+    /// The results aren't allocated on the heap, which adds up for small K at high query volume.
+    pub fn k_nearest_array<const K: usize>(&self, query_point: &DataType) -> Result<NearestArray<DataType, T, K>, KdError> {
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        self.validate_input(query_point)?;
+        let mut candidates = self.search_n_closest(query_point, K)?;
+
+        // Popping the heap yields the farthest point first, so fill from the back
+        let count = candidates.len();
+        let mut nearest = [(); K].map(|_| None);
+        while let Some(closest) = candidates.pop() {
+            nearest[candidates.len()] = Some(self.resolve(&closest)?);
+        }
+
+        Ok((nearest, count))
+    }
+
+    /// Distance to the kth closest point to query point, counting from 1
+    ///
+    /// Returns `KdError::BinaryHeapError` if k is zero or the tree has fewer than k points.
+    pub fn kth_nearest_distance(&self, query_point: &DataType, k: usize) -> Result<T, KdError> {
+        self.validate_input(query_point)?;
+        if k == 0 { return Err(KdError::BinaryHeapError); }
+        let bh_closest = self.search_n_closest(query_point, k)?;
+        if bh_closest.len() < k { return Err(KdError::BinaryHeapError); }
+
+        Ok(self.report_distance(self.worst_distance(&bh_closest, k)))
+    }
+
+    /// Distances from each query point to its k closest points, sorted ascending within each row
+    ///
+    /// Rows hold fewer than k distances if the tree has fewer than k points.
+    pub fn distance_matrix(&self, queries: &[DataType], k: usize) -> Result<Vec<Vec<T>>, KdError> {
+        queries.iter().map(|query_point| self.nearest_distances(query_point, k)).collect()
+    }
+
+    /// Sorted distances from query point to its k closest points
+    fn nearest_distances(&self, query_point: &DataType, k: usize) -> Result<Vec<T>, KdError> {
+        self.validate_input(query_point)?;
+        Ok(self.search_n_closest(query_point, k)?
+               .into_sorted_vec()
+               .into_iter()
+               .map(|closest| self.report_distance(closest.distance))
+               .collect())
+    }
+
+    /// Find n closest points to query point in a max heap keyed on distance
+    ///
+    /// This is the search primitive the other nearest neighbor queries are built on. Popping the
+    /// heap yields points from farthest to closest, so callers that maintain their own ordering can
+    /// avoid the sort done by `k_nearest`.
+    pub fn find_n_closest_heap(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        self.validate_input(query_point)?;
+        let bh_closest = self.search_n_closest(query_point, n)?;
+
+        // Get actual points from indices to points in tree vec
+        let mut bh_dtype = BinaryHeap::with_capacity(n);
+        for closest in bh_closest.iter() {
+            bh_dtype.push(self.resolve(closest)?);
+        }
+
+        Ok(bh_dtype)
+    }
+
+    /// Find n closest points to query point sorted by ascending distance, writing them into out
+    ///
+    /// Search buffers are kept in scratch and out is cleared before it is filled, so reusing both
+    /// across queries avoids allocating once their capacity has grown to fit.
+    pub fn find_n_closest_into(&self, query_point: &DataType, n: usize, scratch: &mut QueryScratch<T>, out: &mut Vec<Closest<DataType, T>>) -> Result<(), KdError> {
+        self.validate_input(query_point)?;
+        self.search_n_closest_by(n, false, false, &mut SearchStats::default(), scratch,
+                                 |point| self.point_distance(point, query_point),
+                                 |point, dimension| point.greater(query_point, dimension),
+                                 |point, dimension| self.plane_distance(point, query_point, dimension),
+                                 |dimension| query_point.coord(dimension))?;
+
+        // Heap pops from farthest to closest
+        out.clear();
+        while let Some(closest) = scratch.candidates.pop() {
+            out.push(self.resolve(&closest)?);
+        }
+        out.reverse();
+
+        Ok(())
+    }
+
+    /// Find n closest points to query point sorted by ascending distance, along with statistics
+    /// describing how much of the tree the search traversed
+    pub fn find_n_closest_instrumented(&self, query_point: &DataType, n: usize) -> Result<(Vec<Closest<DataType, T>>, SearchStats), KdError> {
+        self.validate_input(query_point)?;
+        let mut stats = SearchStats::default();
+        let bh_closest = self.search_n_closest_stats(query_point, n, &mut stats)?;
+
+        let mut closest_points = Vec::with_capacity(bh_closest.len());
+        for closest in bh_closest.into_sorted_vec() {
+            closest_points.push(self.resolve(&closest)?);
+        }
+
+        Ok((closest_points, stats))
+    }
+
+    /// Every point in tree sorted by ascending distance to query point
+    ///
+    /// This measures the distance to every point and sorts them, taking O(n log n) time, so only
+    /// use it when all points are needed. An empty tree returns an empty vector.
+    pub fn sorted_by_distance(&self, query_point: &DataType) -> Result<Vec<Closest<DataType, T>>, KdError> {
+        self.validate_input(query_point)?;
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+
+        let mut sorted = Vec::with_capacity(self.len());
+        for index in 1..self.last_point {
+            if let Some(node) = self.tree[index].as_ref().filter(|node| !node.removed) {
+                let distance = self.point_distance(&node.point, query_point)?;
+                sorted.push(Closest { point: node.point.clone(), distance: self.report_distance(distance), index: Some(index - 1) });
+            }
+        }
+        sorted.sort();
+
+        Ok(sorted)
+    }
+
+    /// Whether inserting new point would change the k nearest neighbors found for a previous query
+    ///
+    /// `prev_worst_dist` is the distance of the farthest of those neighbors as reported by the
+    /// tree. The new point only enters the neighbor set if it is strictly closer, matching how
+    /// ties are kept by the search. Points with the wrong dimensionality never affect results.
+    pub fn would_affect_knn(&self, prev_query: &DataType, prev_worst_dist: T, new_point: &DataType) -> bool {
+        match self.point_distance(new_point, prev_query) {
+            Ok(distance) => self.report_distance(distance) < prev_worst_dist,
+            Err(_) => false,
+        }
+    }
+
+    /// Find the k nearest neighbors of every point in the tree, excluding the point itself
+    ///
+    /// Row `i` holds the neighbors of the point stored at index `i` sorted by ascending distance,
+    /// with each neighbor's `point` being the index of the neighbor. For trees built with
+    /// `add_point` indices are in insertion order. Rows hold fewer than k neighbors if the tree has
+    /// k or fewer points, and rows of removed points are empty.
+    pub fn knn_graph(&self, k: usize) -> Result<Vec<Vec<Closest<usize, T>>>, KdError> {
+        (1..self.last_point).map(|index| self.point_neighbors(index, k)).collect()
+    }
+
+    /// Distance from every point in the tree to its kth nearest other point, counting from 1
+    ///
+    /// Distances are in the index order of `iter`, skipping removed points. Returns
+    /// `KdError::BinaryHeapError` if k is zero or the tree has k or fewer points.
+    pub fn knn_self_distances(&self, k: usize) -> Result<Vec<T>, KdError> {
+        (1..self.last_point).filter(|&index| self.is_live(index)).map(|index| self.self_distance(index, k)).collect()
+    }
+
+    /// Distance from the point stored at index to its kth nearest other point
+    fn self_distance(&self, index: usize, k: usize) -> Result<T, KdError> {
+        if k == 0 { return Err(KdError::BinaryHeapError); }
+
+        match self.point_neighbors(index, k)?.get(k - 1) {
+            Some(neighbor) => Ok(neighbor.distance),
+            None => Err(KdError::BinaryHeapError),
+        }
+    }
+
+    /// Whether a point that hasn't been removed is stored at index
+    fn is_live(&self, index: usize) -> bool {
+        matches!(&self.tree[index], Some(node) if !node.removed)
+    }
+
+    /// Find k nearest neighbors of the point stored at index excluding itself
+    fn point_neighbors(&self, index: usize, k: usize) -> Result<Vec<Closest<usize, T>>, KdError> {
+        let node = match &self.tree[index] {
+            Some(node) if node.removed => return Ok(Vec::new()),
+            Some(node) => node,
+            None => return Err(KdError::NodeMissing),
+        };
+
+        // Search one extra neighbor, the point itself unless too many points coincide with it
+        let mut neighbors = self.search_n_closest(&node.point, k + 1)?.into_sorted_vec();
+        match neighbors.iter().position(|neighbor| neighbor.point == index) {
+            Some(position) => { neighbors.remove(position); },
+            None => { neighbors.truncate(k); },
+        }
+
+        Ok(neighbors.into_iter()
+                    .map(|neighbor| Closest { point: neighbor.point - 1, distance: self.report_distance(neighbor.distance), index: None })
+                    .collect())
+    }
+
+    /// Search tree for indices of the n closest nodes to query point and their search distances
+    fn search_n_closest(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<usize, T>>, KdError> {
+        self.search_n_closest_stats(query_point, n, &mut SearchStats::default())
+    }
+
+    /// Search for n closest nodes like `search_n_closest`, recording statistics of the traversal
+    fn search_n_closest_stats(&self, query_point: &DataType, n: usize, stats: &mut SearchStats) -> Result<BinaryHeap<Closest<usize, T>>, KdError> {
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        self.search_n_closest_by(n, false, true, stats, &mut scratch,
+                                 |point| self.point_distance_squared(point, query_point),
+                                 |point, dimension| point.greater(query_point, dimension),
+                                 |point, dimension| self.plane_distance(point, query_point, dimension).map(|distance| distance * distance),
+                                 |dimension| query_point.coord(dimension))?;
+
+        // Only take the square root of the distances that are kept
+        Ok(scratch.candidates.into_iter().map(|closest| Closest { distance: closest.distance.sqrt(), ..closest }).collect())
+    }
+
+    /// Search for n closest nodes to a query described by its distance to a point, whether a point
+    /// is greater than it in a dimension, and its distance to a point's split plane, leaving them in
+    /// the candidate heap of scratch. With dedup only one of any coincident points is kept. With
+    /// squared the distances given are squared, and are left squared in the heap. The query's value
+    /// in each dimension is only used to prune with sub tree bounds.
+    #[allow(clippy::too_many_arguments)]
+    fn search_n_closest_by<D, G, P, C>(&self, n: usize, dedup: bool, squared: bool, stats: &mut SearchStats, scratch: &mut QueryScratch<T>, distance_to: D, greater: G, plane_distance: P, coord: C) -> Result<(), KdError>
+        where D: Fn(&DataType) -> Result<T, KdError>,
+              G: Fn(&DataType, usize) -> bool,
+              P: Fn(&DataType, usize) -> Result<T, KdError>,
+              C: Fn(usize) -> T,
+    {
+        // Verify tree is not empty
+        if self.is_empty() { return Err(KdError::EmptyTree); }
+
+        search_path(n, 1, stats, scratch,
+                    |index, bh_closest| {
+                        let node = self.tree[index].as_ref().ok_or(KdError::NodeMissing)?;
+
+                        // Check node, removed nodes are only kept to search through. Coincident
+                        // points are all at the same distance, keep the first one found.
+                        let mut candidate = None;
+                        if !node.removed {
+                            let distance = distance_to(&node.point)?;
+                            if !(dedup && self.has_coincident(bh_closest, &node.point, distance)) {
+                                candidate = Some(Closest::at_node(index, distance));
+                            }
+                        }
+                        Ok(PathNode { candidate, left_child: node.left_child, right_child: node.right_child, level: node.level })
+                    },
+                    |index, sub_tree, farthest| {
+                        // The split plane is only measured when there is a sub tree behind it, and
+                        // not at all if the query is within the split tolerance of it
+                        let node = self.tree[index].as_ref().ok_or(KdError::NodeMissing)?;
+                        if self.near_split(&node.point, node.dimension, &coord) { return Ok(true); }
+
+                        let bounds_distance = self.bounds_distance(sub_tree, &coord);
+                        let bounds_distance = if squared { bounds_distance * bounds_distance } else { bounds_distance };
+                        Ok(plane_distance(&node.point, node.dimension)? <= farthest && bounds_distance <= farthest)
+                    },
+                    |root, path| self.go_down_path(&greater, root, path))
+    }
+
+    /// Whether the query's value is within the split tolerance of point's split value in dimension,
+    /// so both sides of the split must be searched
+    fn near_split<C: Fn(usize) -> T>(&self, point: &DataType, dimension: usize, coord: &C) -> bool {
+        match self.split_tolerance {
+            Some(tolerance) => (point.coord(dimension) - coord(dimension)).abs() <= tolerance,
+            None => false,
+        }
+    }
+
+    /// Lower bound on search distance from query to any point in the sub tree at index, zero if
+    /// bounds aren't kept
+    fn bounds_distance<C: Fn(usize) -> T>(&self, index: usize, coord: &C) -> T {
+        let bounds = match &self.bounds {
+            Some(bounds) if index != 0 => bounds,
+            _ => return T::zero(),
+        };
+
+        let dimensions = self.num_dimensions;
+        let (min, max) = bounds[index * 2 * dimensions..(index + 1) * 2 * dimensions].split_at(dimensions);
+        let mut distance = T::zero();
+        for i in 0..dimensions {
+            if let Some(active) = &self.active_dims {
+                if !active[i] { continue; }
+            }
+
+            // Sub trees without points have inverted bounds, which are infinitely far away
+            let value = coord(i);
+            let gap = (min[i] - value).max(value - max[i]).max(T::zero()) / self.axis_scale(i);
+            distance = distance + gap * gap;
+        }
+        distance.sqrt()
+    }
+
+    /// Recompute sub tree bounds of every node if they are kept, leaving out removed points
+    fn update_bounds(&mut self) {
+        let mut bounds = match self.bounds.take() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        // Start from inverted bounds that any point expands
+        let dimensions = self.num_dimensions;
+        bounds.clear();
+        for _ in 0..self.last_point {
+            bounds.extend((0..dimensions).map(|_| T::infinity()));
+            bounds.extend((0..dimensions).map(|_| T::neg_infinity()));
+        }
+
+        // Children are always stored after their parents, so going through nodes in reverse
+        // completes the bounds of each node before they are merged into its parent's
+        for index in (1..self.last_point).rev() {
+            if let Some(node) = &self.tree[index] {
+                let offset = index * 2 * dimensions;
+                if !node.removed {
+                    for i in 0..dimensions {
+                        let value = node.point.coord(i);
+                        bounds[offset + i] = bounds[offset + i].min(value);
+                        bounds[offset + dimensions + i] = bounds[offset + dimensions + i].max(value);
+                    }
+                }
+
+                if node.parent != 0 {
+                    let parent_offset = node.parent * 2 * dimensions;
+                    for i in 0..2 * dimensions {
+                        let (child, parent) = (bounds[offset + i], bounds[parent_offset + i]);
+                        bounds[parent_offset + i] = if i < dimensions { parent.min(child) } else { parent.max(child) };
+                    }
+                }
+            }
+        }
+
+        self.bounds = Some(bounds);
+    }
+
+    /// Set bounds of the newly added node at index to its point and expand those of its ancestors
+    /// to include it, if bounds are kept
+    fn include_in_bounds(&mut self, index: usize) {
+        let dimensions = self.num_dimensions;
+        let bounds = match &mut self.bounds {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let node = match &self.tree[index] {
+            Some(node) => node,
+            None => return,
+        };
+
+        if bounds.len() < (index + 1) * 2 * dimensions { bounds.resize((index + 1) * 2 * dimensions, T::zero()); }
+        let offset = index * 2 * dimensions;
+        for i in 0..dimensions {
+            bounds[offset + i] = node.point.coord(i);
+            bounds[offset + dimensions + i] = node.point.coord(i);
+        }
+
+        let mut ancestor = node.parent;
+        while let Some(parent) = self.tree[ancestor].as_ref() {
+            let offset = ancestor * 2 * dimensions;
+            for i in 0..dimensions {
+                let value = node.point.coord(i);
+                bounds[offset + i] = bounds[offset + i].min(value);
+                bounds[offset + dimensions + i] = bounds[offset + dimensions + i].max(value);
+            }
+            ancestor = parent.parent;
+        }
+    }
+
+    /// Whether a candidate at distance has the same coordinates as point
+    fn has_coincident(&self, bh_closest: &BinaryHeap<Closest<usize, T>>, point: &DataType, distance: T) -> bool {
+        bh_closest.iter().filter(|closest| closest.distance == distance).any(|closest| match &self.tree[closest.point] {
+            Some(node) => (0..self.num_dimensions).all(|i| node.point.coord(i) == point.coord(i)),
+            None => false,
+        })
+    }
+
+    /// Find a point with exactly the coordinates of query point, returning its index as reported
+    /// by `Closest::index`, or `None` if there is none
+    ///
+    /// Points equal to a split are always stored right of it, so an exact match can only be on the
+    /// path the query descends, and the search stops at the first match it finds there. No
+    /// distances are computed, which makes this much cheaper than `find_closest` for checking
+    /// whether a point is already in the tree.
+    pub fn find_exact(&self, query_point: &DataType) -> Result<Option<usize>, KdError> {
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+
+        let mut index = 1;
+        while let Some(node) = &self.tree[index] {
+            if !node.removed && (0..self.num_dimensions).all(|i| node.point.coord(i) == query_point.coord(i)) {
+                return Ok(Some(index - 1));
+            }
+
+            index = if node.point.greater(query_point, node.dimension) { node.left_child } else { node.right_child };
+        }
+
+        Ok(None)
+    }
+
+    /// Find closest point to query point considering only the value in one axis
+    pub fn nearest_on_axis(&self, query_point: &DataType, axis: usize) -> Result<(DataType, T), KdError> {
+        if axis >= self.num_dimensions || query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        if self.is_empty() { return Err(KdError::EmptyTree); }
+
+        let query_value = query_point.coord(axis);
+        let mut best: Option<(usize, T)> = None;
+        let mut stack = vec![1];
+        while let Some(index) = stack.pop() {
+            if let Some(node) = &self.tree[index] {
+                let node_value = node.point.coord(axis);
+                let distance = (node_value - query_value).abs() / self.axis_scale(axis);
+                let is_closer = match best {
+                    Some((_, best_distance)) => distance < best_distance,
+                    None => true,
+                };
+                if is_closer && !node.removed {
+                    best = Some((index, distance));
+                }
+
+                // Sub trees split on a different axis can contain any value in axis, and a removed
+                // node can't stand in for the far side
+                if node.dimension != axis || node.removed {
+                    stack.push(node.left_child);
+                    stack.push(node.right_child);
+                    continue;
+                }
+
+                // Left sub tree only holds values less than the split and right holds the rest, so
+                // nothing on the far side can be closer than the node itself
+                if query_value < node_value {
+                    stack.push(node.left_child);
+                } else {
+                    stack.push(node.right_child);
+                }
+            }
+        }
+
+        match best {
+            Some((index, distance)) => match &self.tree[index] {
+                Some(node) => Ok((node.point.clone(), distance)),
+                None => Err(KdError::NodeMissing),
+            },
+            None => Err(KdError::EmptyTree),
+        }
+    }
+
+    /// Find closest point to the line segment from a to b, measuring the distance to the closest
+    /// point on the segment
+    ///
+    /// Like `find_n_closest_masked` this assumes Euclidean distance, so trees with another metric
+    /// or a custom distance return `KdError::InvalidConfiguration`. Sub trees are pruned by the
+    /// exact distance from the segment to the region of space their splits bound them to.
+    pub fn nearest_to_segment(&self, a: &DataType, b: &DataType) -> Result<(DataType, T), KdError> {
+        if self.metric != Metric::Euclidean || self.distance_fn.is_some() { return Err(KdError::InvalidConfiguration); }
+        if a.dimensions() != self.num_dimensions || b.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(a)?;
+        check_finite(b)?;
+        if self.is_empty() { return Err(KdError::EmptyTree); }
+
+        // Work in normalized coordinates of the active dimensions, where slot maps a dimension to
+        // its position among them
+        let dims = self.active_dimensions();
+        let mut slot = vec![0; self.num_dimensions];
+        for (k, &i) in dims.iter().enumerate() { slot[i] = k; }
+        let start: Vec<T> = dims.iter().map(|&i| a.coord(i) / self.axis_scale(i)).collect();
+        let direction: Vec<T> = dims.iter().map(|&i| (b.coord(i) - a.coord(i)) / self.axis_scale(i)).collect();
+
+        // Region bounding each sub tree on the stack is kept in a parallel stack of corners
+        let m = dims.len();
+        let mut stack = vec![1];
+        let mut regions = vec![T::neg_infinity(); m];
+        regions.resize(2 * m, T::infinity());
+        let (mut lo, mut hi) = (vec![T::zero(); m], vec![T::zero(); m]);
+        let mut offsets = Vec::with_capacity(2 * m + 2);
+        let mut best: Option<(usize, T)> = None;
+        while let Some(index) = stack.pop() {
+            let region = regions.len() - 2 * m;
+            lo.copy_from_slice(&regions[region..region + m]);
+            hi.copy_from_slice(&regions[region + m..]);
+            regions.truncate(region);
+
+            let node = match &self.tree[index] {
+                Some(node) => node,
+                None => continue,
+            };
+            if let Some((_, best_distance)) = best {
+                if segment_box_distance(&start, &direction, &lo, &hi, &mut offsets) >= best_distance { continue; }
+            }
+
+            if !node.removed {
+                let point: Vec<T> = dims.iter().map(|&i| node.point.coord(i) / self.axis_scale(i)).collect();
+                let distance = segment_point_distance(&start, &direction, &point);
+                let is_closer = match best {
+                    Some((_, best_distance)) => distance < best_distance,
+                    None => true,
+                };
+                if is_closer { best = Some((index, distance)); }
+            }
+
+            // Left sub tree only holds values less than the split and right holds the rest
+            let k = slot[node.dimension];
+            let split = node.point.coord(node.dimension) / self.axis_scale(node.dimension);
+            let children = [(node.left_child, m + k), (node.right_child, k)];
+
+            // Visit the side holding the segment's midpoint last so it is searched first and the
+            // best distance shrinks sooner
+            let midpoint = start[k] + direction[k] / (T::one() + T::one());
+            let order = if midpoint < split { [children[1], children[0]] } else { children };
+            for &(child, bound) in order.iter() {
+                if child == 0 { continue; }
+                stack.push(child);
+                let region = regions.len();
+                regions.extend_from_slice(&lo);
+                regions.extend_from_slice(&hi);
+                regions[region + bound] = if bound >= m { hi[k].min(split) } else { lo[k].max(split) };
+            }
+        }
+
+        match best {
+            Some((index, distance)) => match &self.tree[index] {
+                Some(node) => Ok((node.point.clone(), distance)),
+                None => Err(KdError::NodeMissing),
+            },
+            None => Err(KdError::EmptyTree),
+        }
+    }
+
+    /// Find k points closest to the hyperplane of points x with `dot(x, normal) == offset`,
+    /// sorted by ascending distance
+    ///
+    /// Distance is `|dot(point, normal) - offset| / |normal|` in the coordinates points are stored
+    /// with, whatever the tree metric. Sub trees are pruned by the distance from the hyperplane to
+    /// the region of space their splits bound them to. Normals must not be zero.
+    pub fn k_nearest_to_hyperplane(&self, normal: &DataType, offset: T, k: usize) -> Result<Vec<Closest<DataType, T>>, KdError> {
+        let dimensions = self.num_dimensions;
+        if normal.dimensions() != dimensions { return Err(KdError::DimensionError); }
+        check_finite(normal)?;
+        let normal: Vec<T> = (0..dimensions).map(|i| normal.coord(i)).collect();
+        let norm = normal.iter().fold(T::zero(), |norm, &n| norm + n * n).sqrt();
+        if norm == T::zero() || !offset.is_finite() { return Err(KdError::InvalidInput); }
+        if self.is_empty() { return Err(KdError::EmptyTree); }
+        if k == 0 { return Ok(Vec::new()); }
+
+        // Region bounding each sub tree on the stack is kept in a parallel stack of corners, and
+        // the stack holds the distance from the hyperplane to it
+        let mut bh_closest: BinaryHeap<Closest<usize, T>> = BinaryHeap::with_capacity(k);
+        let mut stack = vec![(1, T::zero())];
+        let mut regions = vec![T::neg_infinity(); dimensions];
+        regions.resize(2 * dimensions, T::infinity());
+        let (mut lo, mut hi) = (vec![T::zero(); dimensions], vec![T::zero(); dimensions]);
+        while let Some((index, bound)) = stack.pop() {
+            let region = regions.len() - 2 * dimensions;
+            lo.copy_from_slice(&regions[region..region + dimensions]);
+            hi.copy_from_slice(&regions[region + dimensions..]);
+            regions.truncate(region);
+
+            let node = match &self.tree[index] {
+                Some(node) => node,
+                None => continue,
+            };
+            if bound >= self.worst_distance(&bh_closest, k) { continue; }
+
+            if !node.removed {
+                let dot = normal.iter().enumerate().fold(T::zero(), |dot, (i, &n)| dot + n * node.point.coord(i));
+                let distance = (dot - offset).abs() / norm;
+                if bh_closest.len() < k {
+                    bh_closest.push(Closest::new(index, distance));
+                } else if distance < self.worst_distance(&bh_closest, k) {
+                    bh_closest.pop();
+                    bh_closest.push(Closest::new(index, distance));
+                }
+            }
+
+            // Left sub tree only holds values less than the split and right holds the rest
+            let dimension = node.dimension;
+            let split = node.point.coord(dimension);
+            let (lo_split, hi_split) = (lo[dimension], hi[dimension]);
+            let sides = [(node.left_child, lo_split, hi_split.min(split)), (node.right_child, lo_split.max(split), hi_split)];
+            let gaps = sides.map(|(_, side_lo, side_hi)| {
+                lo[dimension] = side_lo;
+                hi[dimension] = side_hi;
+                hyperplane_box_gap(&normal, offset, &lo, &hi) / norm
+            });
+
+            // Push the farther side first so the nearer one is searched first
+            let order = if gaps[0] < gaps[1] { [1, 0] } else { [0, 1] };
+            for &side in order.iter() {
+                let (child, side_lo, side_hi) = sides[side];
+                if child == 0 { continue; }
+                stack.push((child, gaps[side]));
+                lo[dimension] = side_lo;
+                hi[dimension] = side_hi;
+                regions.extend_from_slice(&lo);
+                regions.extend_from_slice(&hi);
+            }
+        }
+
+        bh_closest.into_sorted_vec().into_iter().map(|closest| match &self.tree[closest.point] {
+            Some(node) => Ok(Closest { point: node.point.clone(), distance: closest.distance, index: Some(closest.point - 1) }),
+            None => Err(KdError::NodeMissing),
+        }).collect()
+    }
+
+    /// Count points within radius of query point
+    pub fn count_within_radius(&self, query_point: &DataType, radius: T) -> Result<usize, KdError> {
+        let mut count = 0;
+        self.search_within_radius(query_point, radius, |_, _| count += 1)?;
+        Ok(count)
+    }
+
+    /// Count points inside the axis aligned box between corners min and max, bounds included
+    ///
+    /// Sub trees entirely outside the box along their split are skipped and no points are cloned.
+    pub fn count_in_range(&self, min: &DataType, max: &DataType) -> Result<usize, KdError> {
+        if min.dimensions() != self.num_dimensions || max.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(min)?;
+        check_finite(max)?;
+
+        let dimensions = self.num_dimensions;
+        let mut count = 0;
+        let mut stack = vec![1];
+        while let Some(index) = stack.pop() {
+            if let Some(node) = &self.tree[index] {
+                if !node.removed && (0..dimensions).all(|i| min.coord(i) <= node.point.coord(i) && node.point.coord(i) <= max.coord(i)) {
+                    count += 1;
+                }
+
+                // Left sub tree only holds values less than the split and right holds the rest
+                let split = node.point.coord(node.dimension);
+                if min.coord(node.dimension) < split { stack.push(node.left_child); }
+                if max.coord(node.dimension) >= split { stack.push(node.right_child); }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Find n closest points to query point among those inside the axis aligned box between
+    /// corners min and max, bounds included
+    ///
+    /// The box is applied before any distance is measured: sub trees entirely outside it along
+    /// their split are never entered, and points outside it are never measured. Fewer than n points
+    /// are returned if the box doesn't hold n.
+    pub fn find_n_closest_in_box(&self, query_point: &DataType, n: usize, min: &DataType, max: &DataType) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        let dimensions = self.num_dimensions;
+        if query_point.dimensions() != dimensions || min.dimensions() != dimensions || max.dimensions() != dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        check_finite(min)?;
+        check_finite(max)?;
+        self.validate_input(query_point)?;
+        if n == 0 { return Ok(BinaryHeap::new()); }
+
+        // Stack holds sub trees along with a lower bound on their distance to the query
+        let mut bh_closest: BinaryHeap<Closest<usize, T>> = BinaryHeap::with_capacity(n);
+        let mut stack = vec![(1, T::zero())];
+        while let Some((index, bound)) = stack.pop() {
+            let node = match &self.tree[index] {
+                Some(node) => node,
+                None => continue,
+            };
+            if bound >= self.worst_distance(&bh_closest, n) { continue; }
+
+            if !node.removed && (0..dimensions).all(|i| min.coord(i) <= node.point.coord(i) && node.point.coord(i) <= max.coord(i)) {
+                let distance = self.point_distance(&node.point, query_point)?;
+                if bh_closest.len() < n {
+                    bh_closest.push(Closest::new(index, distance));
+                } else if distance < self.worst_distance(&bh_closest, n) {
+                    bh_closest.pop();
+                    bh_closest.push(Closest::new(index, distance));
+                }
+            }
+
+            // Left sub tree only holds values less than the split and right holds the rest
+            let split = node.point.coord(node.dimension);
+            let left = (node.left_child, min.coord(node.dimension) < split);
+            let right = (node.right_child, max.coord(node.dimension) >= split);
+            let ((near, near_overlaps), (far, far_overlaps)) = if node.point.greater(query_point, node.dimension) { (left, right) } else { (right, left) };
+
+            // Search the side of the split holding the query first
+            if far_overlaps { stack.push((far, bound.max(self.plane_distance(&node.point, query_point, node.dimension)?))); }
+            if near_overlaps { stack.push((near, bound)); }
+        }
+
+        bh_closest.iter().map(|closest| self.resolve(closest)).collect()
+    }
+
+    /// Find the point closest to query point in space among those whose time, their value in
+    /// time_dim, is within `[t_min, t_max]`, or `None` if no point is
+    ///
+    /// Distance is Euclidean over every dimension but time_dim, so the query's own time is ignored.
+    /// Sub trees split on time_dim that lie outside the window are skipped. Like
+    /// `find_n_closest_masked` this assumes Euclidean distance, so trees with another metric or a
+    /// custom distance return `KdError::InvalidConfiguration`.
+    pub fn find_closest_in_window(&self, query_point: &DataType, time_dim: usize, t_min: T, t_max: T) -> Result<Option<(DataType, T)>, KdError> {
+        if self.metric != Metric::Euclidean || self.distance_fn.is_some() { return Err(KdError::InvalidConfiguration); }
+        let dimensions = self.num_dimensions;
+        if query_point.dimensions() != dimensions || time_dim >= dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        if t_min.is_nan() || t_max.is_nan() { return Err(KdError::InvalidInput); }
+
+        let is_spatial = |i: usize| i != time_dim && match &self.active_dims {
+            Some(active) => active[i],
+            None => true,
+        };
+        let mut best: Option<(usize, T)> = None;
+        // Stack holds sub trees along with a lower bound on their distance to the query
+        let mut stack = vec![(1, T::zero())];
+        while let Some((index, bound)) = stack.pop() {
+            let node = match &self.tree[index] {
+                Some(node) => node,
+                None => continue,
+            };
+            if let Some((_, best_distance)) = best {
+                if bound >= best_distance { continue; }
+            }
+
+            let time = node.point.coord(time_dim);
+            if !node.removed && t_min <= time && time <= t_max {
+                let distance = (0..dimensions).filter(|&i| is_spatial(i)).fold(T::zero(), |distance, i| {
+                    let diff = (node.point.coord(i) - query_point.coord(i)) / self.axis_scale(i);
+                    distance + diff * diff
+                }).sqrt();
+                let is_closer = match best {
+                    Some((_, best_distance)) => distance < best_distance,
+                    None => true,
+                };
+                if is_closer { best = Some((index, distance)); }
+            }
+
+            // Left sub tree only holds values less than the split and right holds the rest
+            let dimension = node.dimension;
+            let split = node.point.coord(dimension);
+            if dimension == time_dim {
+                if t_max >= split { stack.push((node.right_child, bound)); }
+                if t_min < split { stack.push((node.left_child, bound)); }
+                continue;
+            }
+
+            // Search the side of the split holding the query first
+            let plane = if is_spatial(dimension) { (split - query_point.coord(dimension)).abs() / self.axis_scale(dimension) } else { T::zero() };
+            let (near, far) = if node.point.greater(query_point, dimension) { (node.left_child, node.right_child) } else { (node.right_child, node.left_child) };
+            stack.push((far, bound.max(plane)));
+            stack.push((near, bound));
+        }
+
+        match best {
+            Some((index, distance)) => match &self.tree[index] {
+                Some(node) => Ok(Some((node.point.clone(), distance))),
+                None => Err(KdError::NodeMissing),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Find at most max_results points within radius of query point, keeping the closest ones and
+    /// sorting them by ascending distance
+    ///
+    /// Once max_results points are found, the search radius shrinks to the farthest of them, so a
+    /// large radius doesn't collect every point in it.
+    pub fn find_within_radius_capped(&self, query_point: &DataType, radius: T, max_results: usize) -> Result<Vec<Closest<DataType, T>>, KdError> {
+        self.validate_input(query_point)?;
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        if max_results == 0 { return Ok(Vec::new()); }
+
+        let radius = self.search_distance(radius);
+        let mut closest: BinaryHeap<Closest<usize, T>> = BinaryHeap::with_capacity(max_results);
+        let mut stack = vec![1];
+        while let Some(index) = stack.pop() {
+            if let Some(node) = &self.tree[index] {
+                let distance = self.point_distance(&node.point, query_point)?;
+                if distance <= radius && !node.removed {
+                    if closest.len() < max_results {
+                        closest.push(Closest::new(index, distance));
+                    } else if distance < self.worst_distance(&closest, max_results) {
+                        closest.pop();
+                        closest.push(Closest::new(index, distance));
+                    }
+                }
+
+                // Only search the far side of the split if it could hold a point within radius
+                // that is closer than the farthest point kept
+                let bound = self.worst_distance(&closest, max_results).min(radius);
+                let (near, far) = if node.point.greater(query_point, node.dimension) {
+                    (node.left_child, node.right_child)
+                } else {
+                    (node.right_child, node.left_child)
+                };
+                if self.plane_distance(&node.point, query_point, node.dimension)? <= bound {
+                    stack.push(far);
+                }
+                stack.push(near);
+            }
+        }
+
+        closest.into_sorted_vec().iter().map(|closest| self.resolve(closest)).collect()
+    }
+
+    /// Find closest point to query point and its distance if it is within max_dist, or `None` if
+    /// no point is
+    ///
+    /// max_dist bounds the search from the start, so sub trees beyond it are never visited and the
+    /// search is quick when nothing is near.
+    pub fn nearest_within(&self, query_point: &DataType, max_dist: T) -> Result<Option<(DataType, T)>, KdError> {
+        let closest = self.find_within_radius_capped(query_point, max_dist, 1)?;
+
+        Ok(closest.into_iter().next().map(|closest| (closest.point, closest.distance)))
+    }
+
+    /// Find points whose distance from center is between r_min and r_max inclusive, sorted by
+    /// ascending distance
+    ///
+    /// Only the outer radius prunes the search, the inner one just filters the points found.
+    pub fn within_annulus(&self, center: &DataType, r_min: T, r_max: T) -> Result<Vec<Closest<DataType, T>>, KdError> {
+        let r_min = self.search_distance(r_min);
+        let mut found = Vec::new();
+        self.search_within_radius(center, r_max, |index, distance| {
+            if distance >= r_min { found.push(Closest::new(index, distance)); }
+        })?;
+        found.sort();
+
+        found.iter().map(|closest| self.resolve(closest)).collect()
+    }
+
+    /// Find every unordered pair of points within radius of each other
+    ///
+    /// Pairs are `(i, j, distance)` with `i < j` indices as in `knn_graph`, sorted by `i` then `j`.
+    pub fn all_pairs_within(&self, radius: T) -> Result<Vec<(usize, usize, T)>, KdError> {
+        let mut pairs = Vec::new();
+        for index in 1..self.last_point {
+            pairs.extend(self.pairs_within(index, radius)?);
+        }
+
+        Ok(pairs)
+    }
+
+    /// Find pairs of the point stored at index with points stored after it within radius
+    fn pairs_within(&self, index: usize, radius: T) -> Result<Vec<(usize, usize, T)>, KdError> {
+        let node = match &self.tree[index] {
+            Some(node) if node.removed => return Ok(Vec::new()),
+            Some(node) => node,
+            None => return Err(KdError::NodeMissing),
+        };
+
+        // Each pair is found from both of its points, only keep it from the lower index
+        let mut pairs = Vec::new();
+        self.search_within_radius(&node.point, radius, |other, distance| {
+            if other > index { pairs.push((index - 1, other - 1, self.report_distance(distance))); }
+        })?;
+        pairs.sort_by_key(|&(_, other, _)| other);
+
+        Ok(pairs)
+    }
+
+    /// Call visit with the index and search distance of every node within radius of query point
+    fn search_within_radius<F: FnMut(usize, T)>(&self, query_point: &DataType, radius: T, mut visit: F) -> Result<(), KdError> {
+        self.validate_input(query_point)?;
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+
+        let radius = self.search_distance(radius);
+        let mut stack = vec![1];
+        while let Some(index) = stack.pop() {
+            if let Some(node) = &self.tree[index] {
+                let distance = self.point_distance(&node.point, query_point)?;
+                if distance <= radius && !node.removed { visit(index, distance); }
+
+                // Always search side of split containing query, other side only if split plane is
+                // within radius
+                let (near, far) = if node.point.greater(query_point, node.dimension) {
+                    (node.left_child, node.right_child)
+                } else {
+                    (node.right_child, node.left_child)
+                };
+                if self.plane_distance(&node.point, query_point, node.dimension)? <= radius {
+                    stack.push(far);
+                }
+                stack.push(near);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Count points in each cell of a regular grid starting at min, with cells of cell_size along
+    /// each axis and dims cells per axis
+    ///
+    /// Counts are flattened in row-major order, so the last axis varies fastest. Points outside
+    /// the grid aren't counted, and cells must have a positive size.
+    pub fn grid_histogram(&self, min: &DataType, cell_size: &DataType, dims: &[usize]) -> Result<Vec<usize>, KdError> {
+        let dimensions = self.num_dimensions;
+        if min.dimensions() != dimensions || cell_size.dimensions() != dimensions || dims.len() != dimensions { return Err(KdError::DimensionError); }
+        if (0..dimensions).any(|i| cell_size.coord(i) <= T::zero() || cell_size.coord(i).is_nan()) { return Err(KdError::InvalidInput); }
+
+        let mut counts = vec![0; dims.iter().product()];
+        'points: for point in self.iter() {
+            let mut cell = 0;
+            for (i, &cells) in dims.iter().enumerate() {
+                let offset = ((point.coord(i) - min.coord(i)) / cell_size.coord(i)).floor();
+                match offset.to_usize() {
+                    Some(offset) if offset < cells => { cell = cell * cells + offset; },
+                    _ => continue 'points,
+                }
+            }
+            counts[cell] += 1;
+        }
+
+        Ok(counts)
+    }
+
+    /// Remove all points within radius of center, returning how many were removed
+    ///
+    /// Removed nodes stay in the tree to preserve its structure, and the tree is rebuilt once they
+    /// outnumber the remaining points.
+    pub fn remove_within_radius(&mut self, center: &DataType, radius: T) -> Result<usize, KdError> {
+        let mut indices = Vec::new();
+        self.search_within_radius(center, radius, |index, _| indices.push(index))?;
+
+        for &index in indices.iter() {
+            self.remove_node(index);
+        }
+        self.rebuild_if_sparse();
+
+        Ok(indices.len())
+    }
+
+    /// Remove the points at indices, indexed like `knn_graph`, returning how many were removed
+    ///
+    /// Indices of points that were already removed or past the end of the tree are skipped. Like
+    /// `remove_within_radius` removed nodes stay in the tree, which is checked for a rebuild only
+    /// once all of them are removed, so indices refer to the same points throughout.
+    pub fn bulk_remove_indices(&mut self, indices: &[usize]) -> usize {
+        let mut removed = 0;
+        for &index in indices.iter() {
+            if index < self.last_point - 1 && self.remove_node(index + 1) { removed += 1; }
+        }
+        self.rebuild_if_sparse();
+
+        removed
+    }
+
+    /// Remove one point at the same location as point, returning whether one was found
+    ///
+    /// Like `remove_within_radius` this leaves a removed node behind and rebuilds the tree once
+    /// they outnumber the remaining points.
+    pub fn remove_point(&mut self, point: &DataType) -> Result<bool, KdError> {
+        let mut found = None;
+        self.search_within_radius(point, T::zero(), |index, _| { found.get_or_insert(index); })?;
+
+        let removed = match found {
+            Some(index) => self.remove_node(index),
+            None => false,
+        };
+        self.rebuild_if_sparse();
+
+        Ok(removed)
+    }
+
+    /// Find the closest point to query point and remove it, returning it and its distance, or
+    /// `None` if the tree is empty
+    ///
+    /// The counterpart of `query_then_insert`, repeated calls pop points in order of distance.
+    /// Removal leaves a removed node behind like `remove_point`.
+    pub fn remove_nearest(&mut self, query_point: &DataType) -> Result<Option<(DataType, T)>, KdError> {
+        if self.num_dimensions != 0 && query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        if self.is_empty() { return Ok(None); }
+
+        let closest = match self.search_n_closest(query_point, 1)?.pop() {
+            Some(closest) => self.resolve(&closest)?,
+            None => return Err(KdError::BinaryHeapError),
+        };
+        if let Some(index) = closest.index() { self.remove_node(index + 1); }
+        self.rebuild_if_sparse();
+
+        Ok(Some((closest.point, closest.distance)))
+    }
+
+    /// Rebuild tree once removed nodes outnumber the remaining points
+    fn rebuild_if_sparse(&mut self) {
+        if self.tombstones > self.len() { self.rebuild(); }
+    }
+
+    /// Mark node at index as removed, returning whether it was present
+    fn remove_node(&mut self, index: usize) -> bool {
+        match &mut self.tree[index] {
+            Some(node) if !node.removed => {
+                node.removed = true;
+                self.tombstones += 1;
+            },
+            _ => return false,
+        }
+        self.clear_query_cache();
+
+        self.unlink_removed_leaves(index);
+        true
+    }
+
+    /// Unlink removed nodes without children from their parents, starting at index and going up,
+    /// so searches no longer descend into them
+    ///
+    /// Unlinked nodes stay in the tree vector until it is rebuilt. If the deepest level loses its
+    /// last reachable node, max levels is recomputed so query buffers match the real depth.
+    fn unlink_removed_leaves(&mut self, mut index: usize) {
+        let mut deepest_unlinked = None;
+        loop {
+            let (parent, child_type, level) = match &self.tree[index] {
+                Some(node) if node.removed && node.left_child == 0 && node.right_child == 0 && node.parent != 0 => (node.parent, node.child_type, node.level),
+                _ => break,
+            };
+            if let Some(parent_node) = &mut self.tree[parent] {
+                match child_type {
+                    NodeType::LeftChild => { parent_node.left_child = 0; },
+                    NodeType::RightChild => { parent_node.right_child = 0; },
+                    NodeType::RootNode => { },
+                }
+            }
+
+            deepest_unlinked.get_or_insert(level);
+            index = parent;
+        }
+
+        // Reachable nodes are either live or removed with a child, unlinked ones are neither
+        if deepest_unlinked == Some(self.max_levels) {
+            self.max_levels = self.tree[1..self.last_point].iter().flatten()
+                .filter(|node| !node.removed || node.left_child != 0 || node.right_child != 0)
+                .map(|node| node.level)
+                .max()
+                .unwrap_or(0);
+        }
+    }
+
+    /// Brute force search for testing
+    pub fn brute_force(&self, query_point: &DataType, n: usize) -> Result<BinaryHeap<Closest<DataType, T>>, KdError> {
+        self.validate_input(query_point)?;
+
+        // Compare squared distances, only taking the square root of those that are kept
+        let mut bh_closest = BinaryHeap::with_capacity(n);
+        for (cur_ind, node) in self.tree.iter().enumerate() {
+            if let Some(cur_node) = node.as_ref().filter(|node| !node.removed) {
+                let distance = self.point_distance_squared(&cur_node.point, query_point)?;
+                if bh_closest.len() < n {
+                    bh_closest.push(Closest { point: cur_ind, distance, index: None });
+                } else {
+                    if distance < self.worst_distance(&bh_closest, n) {
+                        bh_closest.pop();
+                        bh_closest.push(Closest { point: cur_ind, distance, index: None });
+                    }
+                }
+            }
+        }
+
+        let mut bh_dtype = BinaryHeap::with_capacity(n);
+        for closest in bh_closest.iter() {
+            bh_dtype.push(self.resolve(&Closest { distance: closest.distance.sqrt(), ..*closest })?);
+        }
+
+        Ok(bh_dtype)
+    }
+
+    /// Check that searching the tree finds the same distances as brute force for n closest points
+    /// to every query, returning whether all of them match
+    ///
+    /// Which of several equally distant points is returned may differ, so only the distances are
+    /// compared.
+    #[cfg(feature="testing")]
+    pub fn verify_against_brute_force(&self, queries: &[DataType], n: usize) -> Result<bool, KdError> {
+        let distances = |closest: BinaryHeap<Closest<DataType, T>>| -> Vec<T> {
+            closest.into_sorted_vec().into_iter().map(|closest| closest.distance).collect()
+        };
+
+        for query_point in queries {
+            if distances(self.find_n_closest(query_point, n)?) != distances(self.brute_force(query_point, n)?) {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Search tree from non-empty sub tree root to leaf node
+    fn go_down(&self, query_point: &DataType, root: usize) -> (usize, NodeType) {
+        let mut current_index = root;               // Current index starting from root
         let mut index = current_index;              // Index to return
         let mut child_type = NodeType::RootNode;    // Type of node
         while let Some(node) = &self.tree[current_index] {
-            index = current_index;
-            
-            // Go left if node point is greater than query in current dimension
-            if node.point.greater(query_point, node.dimension) {
+            index = current_index;
+            
+            // Go left if node point is greater than query in current dimension
+            if node.point.greater(query_point, node.dimension) {
+                current_index = node.left_child;
+                child_type = NodeType::LeftChild;
+            // Otherwise go right
+            } else {
+                current_index = node.right_child;
+                child_type = NodeType::RightChild;
+            }
+        };
+
+        (index, child_type)
+    }
+
+    /// Verify point satisfies the assumptions of the tree metric if validation is enabled
+    fn validate_input(&self, point: &DataType) -> Result<(), KdError> {
+        self.validate_coords((0..point.dimensions()).map(|i| point.coord(i)))
+    }
+
+    /// Verify coordinates of a point satisfy the assumptions of the metric, like `validate_input`
+    fn validate_coords<I: Iterator<Item = T>>(&self, coords: I) -> Result<(), KdError> {
+        if self.validate_inputs && self.metric == Metric::Cosine {
+            let norm_squared = coords.fold(T::zero(), |norm, coord| norm + coord * coord);
+            if (norm_squared - T::one()).abs() > T::epsilon().sqrt() {
+                return Err(KdError::InvalidInput);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Distance between points used in search, only summing over active dimensions if set and
+    /// scaling each axis if tree is weighted or normalized
+    fn point_distance(&self, a: &DataType, b: &DataType) -> Result<T, KdError> {
+        if self.active_dims.is_none() && self.scales.is_none() && self.distance_fn.is_none() { return a.distance(b); }
+        if a.dimensions() != b.dimensions() { return Err(KdError::DimensionError); }
+
+        // Custom distances work on coordinate slices
+        if let Some(distance_fn) = &self.distance_fn {
+            let a: Vec<T> = (0..a.dimensions()).map(|i| a.coord(i)).collect();
+            let b: Vec<T> = (0..b.dimensions()).map(|i| b.coord(i)).collect();
+            return Ok(distance_fn.distance(&a, &b));
+        }
+
+        let mut distance = T::zero();
+        for i in 0..a.dimensions() {
+            if let Some(active) = &self.active_dims {
+                if !active[i] { continue; }
+            }
+
+            let diff = (a.coord(i) - b.coord(i)) / self.axis_scale(i);
+            distance = distance + diff * diff;
+        }
+        Ok(distance.sqrt())
+    }
+
+    /// Square of the distance between points used in search, skipping the square root when the
+    /// point type's own distance is used
+    fn point_distance_squared(&self, a: &DataType, b: &DataType) -> Result<T, KdError> {
+        if self.active_dims.is_none() && self.scales.is_none() && self.distance_fn.is_none() { return a.distance_squared(b); }
+
+        self.point_distance(a, b).map(|distance| distance * distance)
+    }
+
+    /// Distance from query point to the split plane of point in dimension used in search
+    fn plane_distance(&self, point: &DataType, query_point: &DataType, dimension: usize) -> Result<T, KdError> {
+        if let Some(distance_fn) = &self.distance_fn {
+            return Ok(distance_fn.axis_lower_bound(point.coord(dimension), query_point.coord(dimension), dimension));
+        }
+
+        match &self.scales {
+            Some(scales) => Ok((point.coord(dimension) - query_point.coord(dimension)).abs() / scales[dimension]),
+            None => point.split_plane(dimension).distance(&query_point.split_plane(dimension)),
+        }
+    }
+
+    /// Scale dividing coordinate differences in dimension, one if tree isn't weighted or normalized
+    fn axis_scale(&self, dimension: usize) -> T {
+        match &self.scales {
+            Some(scales) => scales[dimension],
+            None => T::one(),
+        }
+    }
+
+    /// Number of nodes in the leaf bucket rooted at index, zero if index isn't a bucket root
+    fn bucket_len(&self, index: usize) -> usize {
+        self.buckets.get(index).copied().unwrap_or(0)
+    }
+
+    /// Mark the roots of the largest sub trees of at most bucket_size nodes filling consecutive
+    /// slots, which searches measure one after another instead of descending through
+    fn update_buckets(&mut self) {
+        self.buckets.clear();
+        if self.bucket_size < 2 { return; }
+
+        // Children are always stored after their parents, so going through nodes in reverse
+        // completes the size and last slot of each sub tree before they are merged into its parent's
+        let mut sizes = vec![0; self.last_point];
+        let mut last_slots: Vec<usize> = (0..self.last_point).collect();
+        for index in (1..self.last_point).rev() {
+            if let Some(node) = &self.tree[index] {
+                sizes[index] += 1;
+                if node.parent != 0 {
+                    sizes[node.parent] += sizes[index];
+                    last_slots[node.parent] = last_slots[node.parent].max(last_slots[index]);
+                }
+            }
+        }
+
+        // A sub tree fills consecutive slots when it has a node for every slot up to its last,
+        // and going forward the first slot reached of a bucket is its root
+        self.buckets.resize(self.last_point, 0);
+        let mut index = 1;
+        while index < self.last_point {
+            let size = sizes[index];
+            if size > 1 && size <= self.bucket_size && last_slots[index] + 1 == index + size {
+                self.buckets[index] = size;
+                index += size;
+            } else {
+                index += 1;
+            }
+        }
+    }
+
+    /// Clear bucket marks of the node at index and its ancestors, since a node added below it isn't
+    /// stored with the rest of the bucket it falls in
+    fn clear_buckets(&mut self, mut index: usize) {
+        if self.buckets.is_empty() { return; }
+
+        while let Some(node) = &self.tree[index] {
+            let parent = node.parent;
+            if let Some(bucket_len) = self.buckets.get_mut(index) { *bucket_len = 0; }
+            index = parent;
+        }
+    }
+
+    /// Split dimension for nodes at level, following the split order if set, choosing randomly if
+    /// seeded or cycling through active dimensions
+    fn split_dimension(&self, level: usize) -> usize {
+        if let Some(order) = &self.split_order { return order[level % order.len()]; }
+        match (self.random_splits, &self.active_dims) {
+            (Some(seed), None) => random_split(seed, level, self.num_dimensions),
+            (Some(seed), Some(_)) => {
+                let active = self.active_dimensions();
+                active[random_split(seed, level, active.len())]
+            },
+            (None, Some(_)) => {
+                let split_dims = self.split_dims();
+                split_dims[level % split_dims.len()]
+            },
+            (None, None) => level % self.num_dimensions,
+        }
+    }
+
+    /// Dimensions nodes are split on in order of level, repeating once the end is reached
+    fn split_dims(&self) -> Vec<usize> {
+        if let Some(order) = &self.split_order { return order.clone(); }
+        if self.random_splits.is_some() && self.num_dimensions != 0 {
+            return (0..RANDOM_SPLIT_PERIOD).map(|level| self.split_dimension(level)).collect();
+        }
+
+        self.active_dimensions()
+    }
+
+    /// Dimensions used for distance and splits
+    fn active_dimensions(&self) -> Vec<usize> {
+        match &self.active_dims {
+            Some(active) => (0..active.len()).filter(|&i| active[i]).collect(),
+            None => (0..self.num_dimensions).collect(),
+        }
+    }
+
+    /// Distance from query value to split value of a node in dimension used in search
+    fn slice_plane_distance(&self, split: T, query: T, dimension: usize) -> T {
+        match &self.distance_fn {
+            Some(distance_fn) => distance_fn.axis_lower_bound(split, query, dimension),
+            None => (split - query).abs() / self.axis_scale(dimension),
+        }
+    }
+
+    /// Euclidean distance between coordinate slices, only summing over active dimensions if set
+    /// and scaling each axis if tree is weighted or normalized
+    fn slice_distance(&self, a: &[T], b: &[T]) -> T {
+        if let Some(distance_fn) = &self.distance_fn { return distance_fn.distance(a, b); }
+
+        let mut distance = T::zero();
+        for i in 0..a.len() {
+            if let Some(active) = &self.active_dims {
+                if !active[i] { continue; }
+            }
+
+            let diff = (a[i] - b[i]) / self.axis_scale(i);
+            distance = distance + diff * diff;
+        }
+        distance.sqrt()
+    }
+
+    /// Convert distance used in search to distance reported by tree metric
+    fn report_distance(&self, distance: T) -> T {
+        match self.metric {
+            Metric::Euclidean => distance,
+            // For unit vectors the cosine distance is half the squared euclidean distance
+            Metric::Cosine => distance * distance / (T::one() + T::one()),
+        }
+    }
+
+    /// Search tree from sub tree root to leaf node, pushing each node and the direction taken from
+    /// it onto the path. Every node of a leaf bucket reached is pushed without a direction, so it's
+    /// measured without searching below it.
+    fn go_down_path<G: Fn(&DataType, usize) -> bool>(&self, greater: &G, root: usize, path: &mut Vec<(usize, NodeType)>) {
+        let mut current_index = root;
+        while let Some(node) = &self.tree[current_index] {
+            let bucket_len = self.bucket_len(current_index);
+            if bucket_len > 0 {
+                path.extend((current_index..current_index + bucket_len).filter(|&index| self.tree[index].is_some()).map(|index| (index, NodeType::RootNode)));
+                return;
+            }
+
+            // Go left if node point is greater than query in current dimension, otherwise go right
+            if greater(&node.point, node.dimension) {
+                path.push((current_index, NodeType::LeftChild));
+                current_index = node.left_child;
+            } else {
+                path.push((current_index, NodeType::RightChild));
+                current_index = node.right_child;
+            }
+        }
+    }
+
+    /// Convert distance reported by tree metric to distance used in search
+    fn search_distance(&self, distance: T) -> T {
+        match self.metric {
+            Metric::Euclidean => distance,
+            Metric::Cosine => (distance * (T::one() + T::one())).sqrt(),
+        }
+    }
+
+    /// Look up the point of a node found by search, reporting its distance with the tree metric
+    fn resolve(&self, closest: &Closest<usize, T>) -> Result<Closest<DataType, T>, KdError> {
+        match &self.tree[closest.point] {
+            Some(node) => Ok(Closest { point: node.point.clone(), distance: self.report_distance(closest.distance), index: Some(closest.point - 1) }),
+            None => Err(KdError::NodeMissing),
+        }
+    }
+
+    /// Distance a point must beat to be kept in a binary heap of the n closest points
+    ///
+    /// Closest orders by distance, so the top of the heap is the farthest point kept. Until n points
+    /// are kept any point gets in, so the distance is infinite and nothing can be pruned. A heap
+    /// of no points keeps nothing, so no distance beats it.
+    pub(crate) fn worst_distance<P>(&self, bh_closest: &BinaryHeap<Closest<P, T>>, n: usize) -> T {
+        worst_distance(bh_closest, n)
+    }
+
+    /// Iterate over points in tree in index order, skipping removed points
+    pub fn iter(&self) -> impl Iterator<Item = &DataType> {
+        self.tree[..self.last_point].iter().flatten().filter(|node| !node.removed).map(|node| &node.point)
+    }
+
+    /// Point stored at index, as reported by `Closest::index`, or `None` if it was removed
+    pub fn get_point(&self, index: usize) -> Option<&DataType> {
+        match self.tree[1..self.last_point].get(index) {
+            Some(Some(node)) if !node.removed => Some(&node.point),
+            _ => None,
+        }
+    }
+
+    /// Iterate over nodes in index order, starting at the root, for walking the tree with custom
+    /// algorithms
+    ///
+    /// Removed points still split the space below them, so their nodes are included and marked.
+    /// Removed nodes that no longer have children may be unlinked from their parent.
+    pub fn nodes(&self) -> impl Iterator<Item = NodeInfo<'_, DataType>> {
+        // Storage slots are one past the public index, with slot 0 marking a missing child
+        let child = |slot: usize| if slot == 0 { None } else { Some(slot - 1) };
+        self.tree[1..self.last_point].iter().enumerate().filter_map(move |(index, node)| node.as_ref().map(|node| NodeInfo {
+            index,
+            dimension: node.dimension,
+            level: node.level,
+            left: child(node.left_child),
+            right: child(node.right_child),
+            removed: node.removed,
+            point: &node.point,
+        }))
+    }
+
+    /// Clone all points in tree into a vector in index order, skipping removed points
+    pub fn to_vec(&self) -> Vec<DataType> {
+        self.iter().cloned().collect()
+    }
+
+    /// Move all points out of tree into a vector in index order, skipping removed points
+    ///
+    /// Points added one at a time are in insertion order, balanced builds store them in tree order.
+    pub fn into_points(mut self) -> Vec<DataType> {
+        self.take_points()
+    }
+
+    /// Take tree apart into its number of dimensions, max level and raw nodes, which `from_parts`
+    /// puts back together
+    ///
+    /// Tree settings such as the metric or sub tree bounds are not part of the parts.
+    pub fn into_parts(self) -> (usize, usize, Vec<Option<RawNode<DataType>>>) {
+        let nodes = self.tree.into_iter().map(|slot| slot.map(|node| RawNode {
+            point: node.point,
+            child_type: node.child_type,
+            parent: node.parent,
+            left_child: node.left_child,
+            right_child: node.right_child,
+            dimension: node.dimension,
+            level: node.level,
+            removed: node.removed,
+        })).collect();
+
+        (self.num_dimensions, self.max_levels, nodes)
+    }
+
+    /// Create a tree from the parts given by `into_parts`, e.g. after reading them from a custom
+    /// storage format
+    ///
+    /// Returns `KdError::DimensionError` or `KdError::NonFiniteValue` for points a tree couldn't
+    /// hold, and `KdError::NodeMissing` if the nodes don't form a valid tree, see `validate`.
+    pub fn from_parts(num_dimensions: usize, max_levels: usize, nodes: Vec<Option<RawNode<DataType>>>) -> Result<Self, KdError> {
+        let mut tree: Vec<Option<Node<DataType>>> = nodes.into_iter().map(|slot| slot.map(|node| Node {
+            point: node.point,
+            child_type: node.child_type,
+            parent: node.parent,
+            left_child: node.left_child,
+            right_child: node.right_child,
+            dimension: node.dimension,
+            level: node.level,
+            removed: node.removed,
+        })).collect();
+
+        // Index 0 is reserved to signify a missing child and the root is always at index 1
+        if matches!(tree.first(), Some(Some(_))) { return Err(KdError::NodeMissing); }
+        if tree.len() < 2 { tree.resize_with(2, Default::default); }
+        for node in tree.iter().flatten() {
+            if node.point.dimensions() != num_dimensions { return Err(KdError::DimensionError); }
+            check_finite(&node.point)?;
+        }
+
+        // Every node is linked from its parent, which comes before it, so walking down from the
+        // root reaches every node and validating that walk covers all of them
+        for (index, slot) in tree.iter().enumerate().skip(1) {
+            let node = match slot {
+                Some(node) => node,
+                None => continue,
+            };
+            let linked = match (node.child_type, tree.get(node.parent)) {
+                (NodeType::RootNode, _) => index == 1,
+                (_, Some(Some(parent))) if node.parent < index => match node.child_type {
+                    NodeType::LeftChild => parent.left_child == index,
+                    _ => parent.right_child == index,
+                },
+                _ => false,
+            };
+            if !linked { return Err(KdError::NodeMissing); }
+        }
+
+        let last_point = tree.iter().rposition(Option::is_some).map_or(1, |index| index + 1);
+        let tombstones = tree[1..last_point].iter().filter(|slot| !matches!(slot, Some(node) if !node.removed)).count();
+        let mut kd_tree = Self::assemble(num_dimensions, Vec::new(), Vec::new(), max_levels);
+        kd_tree.tree = tree;
+        kd_tree.last_point = last_point;
+        kd_tree.tombstones = tombstones;
+        kd_tree.validate()?;
+
+        Ok(kd_tree)
+    }
+
+    /// Write all points in tree to w in index order, one per line as comma separated coordinates
+    ///
+    /// Only the points are written, not the tree structure, so the output can be read by other
+    /// tools or by `import_points_csv`. Coordinates are written with enough digits to read back
+    /// exactly.
+    #[cfg(feature="std")]
+    pub fn export_points_csv<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for point in self.iter() {
+            for i in 0..point.dimensions() {
+                if i > 0 { w.write_all(b",")?; }
+                write!(w, "{}", point.coord(i).to_f64().unwrap_or(f64::NAN))?;
+            }
+            w.write_all(b"\n")?;
+        }
+
+        w.flush()
+    }
+
+    /// Number of removed nodes still kept in tree
+    pub fn num_tombstones(&self) -> usize { self.tombstones }
+
+    /// Fraction of used node slots holding removed nodes, zero for a tree that was never filled
+    ///
+    /// Removal rebuilds the tree once this passes one half, so thresholds for calling `rebuild`
+    /// manually should be below that.
+    pub fn tombstone_ratio(&self) -> f64 {
+        match self.last_point - 1 {
+            0 => 0.0,
+            used => self.tombstones as f64 / used as f64,
+        }
+    }
+
+    /// Number of points in tree
+    pub fn len(&self) -> usize { self.last_point - 1 - self.tombstones }
+
+    /// Whether tree has no points
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Getter for dimensions of tree
+    pub fn get_num_dimensions(&self) -> usize { self.num_dimensions }
+
+    /// Level of the deepest node in tree, with the root at level zero
+    pub fn depth(&self) -> usize { self.max_levels }
+}
+
+
+/// Builder collecting configuration options for a new tree
+pub struct KdTreeBuilder<DataType, T> {
+    dimensions: usize,                           // Number of dimensions, 0 to infer
+    capacity: usize,                             // Initial storage for nodes
+    metric: Metric,                              // Metric used to report distances
+    validate_inputs: bool,                       // Check inputs satisfy metric assumptions
+    active_dims: Option<Vec<bool>>,              // Dimensions used for distance and splits
+    weights: Option<Vec<T>>,                     // Per axis weight of squared differences in distance
+    split_order: Option<Vec<usize>>,             // Dimensions split on in order of level
+    bucket_size: usize,                          // Most nodes in a leaf bucket
+    growth_policy: GrowthPolicy,                 // How storage grows when full
+    normalization: Normalization,                // Per axis normalization computed from points
+    distance_fn: Option<Box<dyn DistanceFn<T>>>, // Custom distance replacing the point type's
+    subtree_bounds: bool,                        // Keep bounding box of each sub tree for pruning
+    auto_rebalance: Option<f64>,                 // Depth relative to balanced that triggers rebuild
+    split_tolerance: Option<T>,                  // Distance from a split within which both sides are searched
+    max_nodes: Option<usize>,                    // Most nodes, including removed ones, tree may hold
+    random_splits: Option<u64>,                  // Seed choosing split dimension of each level
+    #[cfg(feature="std")]
+    query_cache: Option<(usize, T)>,             // Capacity and grid spacing of cache of recent queries
+    tree_type: PhantomData<(DataType, T)>,       // Specify what type of tree is built
+}
+
+/// KdTreeBuilder functions
+impl<T: Float, DataType: Point<T> + Clone> KdTreeBuilder<DataType, T> {
+    /// Create a builder with default options
+    pub fn new() -> Self {
+        KdTreeBuilder {
+            dimensions: 0,
+            capacity: 100,
+            metric: Metric::Euclidean,
+            validate_inputs: false,
+            active_dims: None,
+            weights: None,
+            split_order: None,
+            bucket_size: 1,
+            growth_policy: GrowthPolicy::Double,
+            normalization: Normalization::None,
+            distance_fn: None,
+            subtree_bounds: false,
+            auto_rebalance: None,
+            split_tolerance: None,
+            max_nodes: None,
+            random_splits: None,
+            #[cfg(feature="std")]
+            query_cache: None,
+            tree_type: PhantomData,
+        }
+    }
+
+    /// Set number of dimensions, zero infers it from the first point added
+    pub fn dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = dimensions;
+        self
+    }
+
+    /// Set number of points to allocate storage for
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set metric used to report distances, see `KdTree::with_metric`
+    pub fn metric(mut self, metric: Metric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Set whether inputs are verified against the assumptions of the metric
+    pub fn validate_inputs(mut self, validate_inputs: bool) -> Self {
+        self.validate_inputs = validate_inputs;
+        self
+    }
+
+    /// Set mask of dimensions used for distance and splits, see `KdTree::with_active_dims`
+    pub fn active_dims(mut self, active: Vec<bool>) -> Self {
+        self.active_dims = Some(active);
+        self
+    }
+
+    /// Set weight of each axis, so distance is the square root of the weighted sum of squared
+    /// differences
+    ///
+    /// Weights must be positive and finite with one per dimension. Like normalization they
+    /// rescale axes, so they can't be combined with it, the cosine metric or a custom distance.
+    pub fn weights(mut self, weights: Vec<T>) -> Self {
+        self.weights = Some(weights);
+        self
+    }
+
+    /// Set how the dimension each level splits on is chosen
+    pub fn split_strategy(mut self, strategy: SplitStrategy) -> Self {
+        (self.split_order, self.random_splits) = match strategy {
+            SplitStrategy::Cycle => (None, None),
+            SplitStrategy::Order(order) => (Some(order), None),
+            SplitStrategy::Random(seed) => (None, Some(seed)),
+        };
+        self
+    }
+
+    /// Set most nodes in a leaf bucket, which searches measure one after another instead of
+    /// descending through
+    ///
+    /// Buckets are the sub trees of a balanced build that fill consecutive slots, so they only
+    /// form in trees built with `build_from_vec` or rebalanced with `rebuild` or
+    /// `balance_in_place`. Adding a point below a bucket splits it back into nodes. The default of
+    /// one leaves every node to be searched on its own.
+    pub fn bucket_size(mut self, bucket_size: usize) -> Self {
+        self.bucket_size = bucket_size;
+        self
+    }
+
+    /// Set how storage grows when tree is full
+    pub fn growth_policy(mut self, growth_policy: GrowthPolicy) -> Self {
+        self.growth_policy = growth_policy;
+        self
+    }
+
+    /// Set normalization of each axis, computed from the points passed to `build_from_vec`
+    ///
+    /// Stored points and queries keep their original coordinates, while distances are measured
+    /// and reported in normalized space. Points added after the build use the same scales.
+    /// Normalization can't be combined with weights or the cosine metric.
+    pub fn normalization(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Set custom distance used in place of the point type's, see `DistanceFn` for the requirements
+    /// pruning places on it
+    ///
+    /// Custom distances can't be combined with the cosine metric, active dimensions, weights,
+    /// normalization or sub tree bounds, which all assume Euclidean distance.
+    pub fn distance_fn(mut self, distance_fn: Box<dyn DistanceFn<T>>) -> Self {
+        self.distance_fn = Some(distance_fn);
+        self
+    }
+
+    /// Set whether each node keeps the bounding box of its sub tree
+    ///
+    /// Searches prune sub trees whose bounding box is farther than the neighbors found so far,
+    /// which is tighter than the split plane alone and visits fewer nodes on clustered data, at
+    /// the cost of storing two corners per node. Bounds assume the point type's distance is
+    /// Euclidean, and are computed by balanced builds and kept up to date as points are added.
+    /// Removed points stay within the bounds until the tree is rebuilt. Can't be combined with a
+    /// custom distance.
+    pub fn subtree_bounds(mut self, subtree_bounds: bool) -> Self {
+        self.subtree_bounds = subtree_bounds;
+        self
+    }
+
+    /// Set how much deeper than a balanced tree the tree may get before `add_points_maintained`
+    /// rebalances it
+    ///
+    /// The tree is rebalanced once its depth exceeds factor times the base two logarithm of one
+    /// more than the number of points. Factors must be at least one, since a balanced tree already
+    /// reaches that depth with a factor of one.
+    pub fn auto_rebalance(mut self, factor: f64) -> Self {
+        self.auto_rebalance = Some(factor);
+        self
+    }
+
+    /// Set how close a query may be to a split value for searches to always visit both sides of it
+    ///
+    /// Searches normally skip the far side of a split when the distance to its plane exceeds the
+    /// neighbors found so far. Points lying on a split plane are easily lost to float rounding
+    /// when that distance is overstated, e.g. by a custom distance computed in lower precision.
+    /// Queries whose value is within tolerance of a split search both of its sides regardless.
+    /// Tolerance must not be negative.
+    pub fn split_tolerance(mut self, tolerance: T) -> Self {
+        self.split_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Set the most nodes tree may hold, so adding points past it returns
+    /// `KdError::CapacityExceeded` instead of growing storage
+    ///
+    /// Removed points keep their node until the tree is rebuilt, so they count against the limit.
+    /// Storage is never grown past the limit, whatever the growth policy.
+    pub fn max_nodes(mut self, max_nodes: usize) -> Self {
+        self.max_nodes = Some(max_nodes);
+        self
+    }
+
+    /// Split each level on a dimension chosen pseudo randomly from seed instead of cycling
+    /// through them
+    ///
+    /// Points inserted one at a time in an order crafted against the usual cycle, e.g. moving away
+    /// from every split along its dimension, chain into a tree as deep as the number of points.
+    /// Without knowing the seed such an order can't be constructed, while the tree stays a
+    /// deterministic function of the seed and the points. The choice repeats every 64 levels,
+    /// deeper than any balanced build.
+    pub fn random_splits(self, seed: u64) -> Self {
+        self.split_strategy(SplitStrategy::Random(seed))
+    }
+
+    /// Remember the closest points found by up to capacity recent `find_closest` queries, and
+    /// return them again for queries that round to the same point of a grid with spacing
+    /// quantization
+    ///
+    /// A hit returns the point found for the earlier query with its distance to the new one, so
+    /// with a coarse grid it may not be the closest point. The cache is cleared whenever points
+    /// are added, removed or moved. It is locked while used, so trees can still be shared between
+    /// threads.
+    #[cfg(feature="std")]
+    pub fn query_cache(mut self, capacity: usize, quantization: T) -> Self {
+        self.query_cache = Some((capacity, quantization));
+        self
+    }
+
+    /// Validate combination of options and create tree
+    pub fn build(self) -> Result<KdTree<DataType, T>, KdError> {
+        // Normalization needs points to compute statistics from
+        if self.normalization != Normalization::None { return Err(KdError::InvalidConfiguration); }
+        self.validate()?;
+
+        Ok(self.create())
+    }
+
+    /// Validate combination of options and create a balanced tree from a vector of points
+    pub fn build_from_vec(mut self, points: Vec<DataType>) -> Result<KdTree<DataType, T>, KdError> {
+        // Infer dimensions from first point if none were given
+        self.dimensions = infer_dimensions(self.dimensions, &points);
+        if points.iter().any(|point| point.dimensions() != self.dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() { check_finite(point)?; }
+        self.validate()?;
+        if points.len() > self.max_nodes.unwrap_or(usize::MAX) { return Err(KdError::CapacityExceeded); }
+
+        let scales = self.axis_scales(&points);
+        let mut tree = self.create();
+        for point in points.iter() { tree.validate_input(point)?; }
+
+        // Only take the nodes from the balanced build so configured options are kept
+        let rebuilt = KdTree::build_with_splits(tree.num_dimensions, points, &tree.split_dims());
+        tree.replace_nodes(rebuilt);
+        tree.scales = scales;
+
+        Ok(tree)
+    }
+
+    /// Validate options and create an index tree over rows `0..num_rows` of an external dataset,
+    /// reading each row through fetch
+    ///
+    /// Building reads every row once per level of the tree. Index trees only support Euclidean
+    /// distance as defined by the point type, so other options return
+    /// `KdError::InvalidConfiguration`.
+    pub fn build_index<F: Fn(usize) -> DataType>(self, num_rows: usize, fetch: F) -> Result<KdIndex<T>, KdError> {
+        if self.metric != Metric::Euclidean || self.active_dims.is_some() || self.normalization != Normalization::None || self.distance_fn.is_some() || self.subtree_bounds || self.random_splits.is_some() {
+            return Err(KdError::InvalidConfiguration);
+        }
+        // Index trees are built once with cycling splits and never grow, so neither storage nor
+        // layout options apply
+        if self.weights.is_some() || self.split_order.is_some() || self.bucket_size > 1 || self.split_tolerance.is_some() || self.max_nodes.is_some() || self.growth_policy != GrowthPolicy::Double || self.auto_rebalance.is_some() {
+            return Err(KdError::InvalidConfiguration);
+        }
+        #[cfg(feature="std")]
+        if self.query_cache.is_some() { return Err(KdError::InvalidConfiguration); }
+
+        // Infer dimensions from first row if none were given
+        let dimensions = match self.dimensions {
+            0 if num_rows > 0 => fetch(0).dimensions(),
+            dimensions => dimensions,
+        };
+        if dimensions == 0 && num_rows > 0 { return Err(KdError::DimensionError); }
+
+        let mut rows: Vec<usize> = (0..num_rows).collect();
+        let mut nodes: Vec<Option<IndexNode<T>>> = Vec::with_capacity(num_rows + 1);
+        nodes.resize_with(num_rows + 1, Default::default);
+        let max_levels = build_index_subtree(&fetch, &mut rows, &mut nodes[1..], 1, 0, dimensions)?;
+
+        Ok(KdIndex { tree: nodes, num_dimensions: dimensions, max_levels })
+    }
+
+    /// Verify configured options are compatible
+    fn validate(&self) -> Result<(), KdError> {
+        if let Some(active) = &self.active_dims {
+            // Mask must cover every dimension and leave at least one to split on
+            if active.len() != self.dimensions || !active.iter().any(|&is_active| is_active) { return Err(KdError::DimensionError); }
+            // Cosine distance assumes unit length over all dimensions
+            if self.metric == Metric::Cosine { return Err(KdError::InvalidConfiguration); }
+        }
+        if let Some(weights) = &self.weights {
+            if weights.len() != self.dimensions { return Err(KdError::DimensionError); }
+            // Scaling axes breaks the unit length cosine distance relies on
+            if weights.iter().any(|&weight| !weight.is_finite() || weight <= T::zero()) || self.metric == Metric::Cosine {
+                return Err(KdError::InvalidConfiguration);
+            }
+        }
+        if let Some(order) = &self.split_order {
+            // Only dimensions used for distance can be split on
+            let is_active = |dimension: usize| match &self.active_dims {
+                Some(active) => active[dimension],
+                None => true,
+            };
+            if order.is_empty() || order.iter().any(|&dimension| dimension >= self.dimensions || !is_active(dimension)) {
+                return Err(KdError::InvalidConfiguration);
+            }
+        }
+        if self.bucket_size == 0 { return Err(KdError::InvalidConfiguration); }
+        if self.normalization != Normalization::None {
+            // Scaling axes breaks the unit length cosine distance relies on, and weights already
+            // set the scale of each axis
+            if self.metric == Metric::Cosine || self.weights.is_some() { return Err(KdError::InvalidConfiguration); }
+        }
+        // Other options change how the Euclidean distance is computed
+        if self.distance_fn.is_some() && (self.metric != Metric::Euclidean || self.active_dims.is_some() || self.weights.is_some() || self.normalization != Normalization::None || self.subtree_bounds) {
+            return Err(KdError::InvalidConfiguration);
+        }
+        if let Some(factor) = self.auto_rebalance {
+            if factor.is_nan() || factor < 1.0 { return Err(KdError::InvalidConfiguration); }
+        }
+        if let Some(tolerance) = self.split_tolerance {
+            if tolerance.is_nan() || tolerance < T::zero() { return Err(KdError::InvalidConfiguration); }
+        }
+        #[cfg(feature="std")]
+        if let Some((capacity, quantization)) = self.query_cache {
+            if capacity == 0 || !quantization.is_finite() || quantization <= T::zero() { return Err(KdError::InvalidConfiguration); }
+        }
+
+        Ok(())
+    }
+
+    /// Scale of each axis for the configured weights, dividing differences by it multiplies their
+    /// square by the weight
+    fn weight_scales(&self) -> Option<Vec<T>> {
+        self.weights.as_ref().map(|weights| weights.iter().map(|&weight| weight.sqrt().recip()).collect())
+    }
+
+    /// Scale of each axis for the configured normalization or weights, axes without spread keep a
+    /// scale of one
+    fn axis_scales(&self, points: &[DataType]) -> Option<Vec<T>> {
+        if self.normalization == Normalization::None || points.is_empty() { return self.weight_scales(); }
+
+        let count = T::from(points.len()).unwrap();
+        let scales = (0..self.dimensions).map(|i| {
+            let scale = match self.normalization {
+                Normalization::MinMax => {
+                    let min = points.iter().fold(T::infinity(), |min, point| min.min(point.coord(i)));
+                    let max = points.iter().fold(T::neg_infinity(), |max, point| max.max(point.coord(i)));
+                    max - min
+                },
+                Normalization::ZScore => {
+                    let mean = points.iter().fold(T::zero(), |sum, point| sum + point.coord(i)) / count;
+                    let variance = points.iter().fold(T::zero(), |sum, point| sum + (point.coord(i) - mean).powi(2)) / count;
+                    variance.sqrt()
+                },
+                Normalization::None => T::one(),
+            };
+
+            if scale > T::zero() && scale.is_finite() { scale } else { T::one() }
+        }).collect();
+
+        Some(scales)
+    }
+
+    /// Create tree from options that are known to be valid
+    fn create(self) -> KdTree<DataType, T> {
+        // Index 0 is reserved and the root always needs a slot, the rest of the storage is only
+        // reserved so creating a tree takes the same time for any capacity
+        let capacity = self.capacity.min(self.max_nodes.unwrap_or(usize::MAX)).max(1) + 1;
+        let scales = self.weight_scales();
+        let mut new_tree = KdTree {
+            tree: Vec::with_capacity(capacity),
+            num_dimensions: self.dimensions,
+            max_levels: 0,
+            last_point: 1,
+            tombstones: 0,
+            metric: self.metric,
+            validate_inputs: self.validate_inputs,
+            active_dims: self.active_dims,
+            scales,
+            split_order: self.split_order,
+            bucket_size: self.bucket_size,
+            buckets: Vec::new(),
+            growth_policy: self.growth_policy,
+            distance_fn: self.distance_fn,
+            bounds: if self.subtree_bounds { Some(Vec::new()) } else { None },
+            auto_rebalance: self.auto_rebalance,
+            split_tolerance: self.split_tolerance,
+            max_nodes: self.max_nodes,
+            random_splits: self.random_splits,
+            #[cfg(feature="std")]
+            query_cache: self.query_cache.map(|(capacity, quantization)| Mutex::new(QueryCache::new(capacity, quantization))),
+            float_type: PhantomData,
+        };
+        new_tree.tree.resize_with(2, Default::default);
+        new_tree
+    }
+}
+
+impl<T: Float, DataType: Point<T> + Clone> Default for KdTreeBuilder<DataType, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// KdTree functions for trees of Vec points
+impl<T: Float> KdTree<Vec<T>, T> where Vec<T>: Point<T> {
+    /// Find n closest points to query coordinates given as a slice, without allocating a point
+    pub fn find_closest_slice(&self, coords: &[T], n: usize) -> Result<BinaryHeap<Closest<Vec<T>, T>>, KdError> {
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        self.search_slice(coords, n, &mut scratch)?;
+
+        let mut bh_dtype = BinaryHeap::with_capacity(n);
+        for closest in scratch.candidates.iter() {
+            bh_dtype.push(self.resolve(closest)?);
+        }
+
+        Ok(bh_dtype)
+    }
+
+    /// Find closest point to query coordinates, writing its coordinates into out_coords and its
+    /// distance into out_dist without allocating
+    ///
+    /// Both slices must have the dimensionality of the tree, otherwise `KdError::DimensionError`
+    /// is returned and the outputs are left untouched.
+    pub fn find_closest_into_slice(&self, query: &[T], out_coords: &mut [T], out_dist: &mut T) -> Result<(), KdError> {
+        if out_coords.len() != self.num_dimensions { return Err(KdError::DimensionError); }
+
+        let mut scratch = QueryScratch::with_capacity(1, self.max_levels + 1);
+        self.search_slice(query, 1, &mut scratch)?;
+
+        let closest = match scratch.candidates.peek() {
+            Some(closest) => closest,
+            None => return Err(KdError::BinaryHeapError),
+        };
+        match &self.tree[closest.point] {
+            Some(node) => {
+                out_coords.copy_from_slice(&node.point);
+                *out_dist = self.report_distance(closest.distance);
+                Ok(())
+            },
+            None => Err(KdError::NodeMissing),
+        }
+    }
+
+    /// Create a balanced tree from points read from r in the format written by `export_points_csv`
+    ///
+    /// Dimensions are taken from the first point and blank lines are skipped. Malformed
+    /// coordinates and points rejected by the tree are reported as `io::ErrorKind::InvalidData`.
+    #[cfg(feature="std")]
+    pub fn import_points_csv<R: BufRead>(r: R) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut points = Vec::new();
+        for line in r.lines() {
+            let line = line?;
+            if line.trim().is_empty() { continue; }
+
+            let point = line.split(',').map(|value| T::from_str_radix(value.trim(), 10).map_err(|_| invalid(format!("invalid coordinate {:?}", value))));
+            points.push(point.collect::<io::Result<Vec<T>>>()?);
+        }
+
+        Self::build_from_vec(0, points).map_err(|err| invalid(err.to_string()))
+    }
+
+    /// Search for n closest nodes to query coordinates, leaving them in the candidate heap of scratch
+    fn search_slice(&self, coords: &[T], n: usize, scratch: &mut QueryScratch<T>) -> Result<(), KdError> {
+        if coords.len() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite_slice(coords)?;
+        self.validate_coords(coords.iter().copied())?;
+
+        self.search_n_closest_by(n, false, false, &mut SearchStats::default(), scratch,
+                                 |point| Ok(self.slice_distance(point, coords)),
+                                 |point, dimension| point[dimension] > coords[dimension],
+                                 |point, dimension| Ok(self.slice_plane_distance(point[dimension], coords[dimension], dimension)),
+                                 |dimension| coords[dimension])
+    }
+}
+
+/// KdTree functions for trees of ndarray points
+#[cfg(feature="default")]
+impl KdTree<Array1<f64>, f64> {
+    /// Find n closest points to a borrowed query view
+    ///
+    /// `Point` can't be implemented for views since points must be able to create new points,
+    /// so this searches with the view's coordinates directly instead. Queries from Python use it
+    /// to read numpy arrays in place, which saves copying every query into an owned array.
+    /// Non-contiguous views are copied once.
+    pub fn find_n_closest_view(&self, query_point: ArrayView1<f64>, n: usize) -> Result<BinaryHeap<Closest<Array1<f64>, f64>>, KdError> {
+        if query_point.len() != self.num_dimensions { return Err(KdError::DimensionError); }
+
+        let owned;
+        let coords = match query_point.as_slice() {
+            Some(coords) => coords,
+            None => { owned = query_point.to_vec(); &owned },
+        };
+        check_finite_slice(coords)?;
+        self.validate_coords(coords.iter().copied())?;
+
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        self.search_n_closest_by(n, false, false, &mut SearchStats::default(), &mut scratch,
+                                 |point| match point.as_slice() {
+                                     Some(point) => Ok(self.slice_distance(point, coords)),
+                                     None => Ok(self.slice_distance(&point.to_vec(), coords)),
+                                 },
+                                 |point, dimension| point[dimension] > coords[dimension],
+                                 |point, dimension| Ok(self.slice_plane_distance(point[dimension], coords[dimension], dimension)),
+                                 |dimension| coords[dimension])?;
+
+        scratch.candidates.iter().map(|closest| self.resolve(closest)).collect()
+    }
+}
+
+/// QueryScratch functions
+impl<T: Float> QueryScratch<T> {
+    /// Create empty buffers, which grow to fit the first queries they are used for
+    pub fn new() -> Self {
+        Self::with_capacity(0, 0)
+    }
+
+    /// Create buffers for n neighbors in a tree with the given number of levels
+    fn with_capacity(n: usize, levels: usize) -> Self {
+        QueryScratch {
+            candidates: BinaryHeap::with_capacity(n),
+            path: Vec::with_capacity(levels),
+        }
+    }
+}
+
+impl<T: Float> Default for QueryScratch<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// KdIndex functions
+impl<T: Float> KdIndex<T> {
+    /// Find the rows of the n closest points to query point sorted by ascending distance, reading
+    /// rows through fetch, which must return the same points the index was built from
+    pub fn find_n_closest<DataType: Point<T>, F: Fn(usize) -> DataType>(&self, query_point: &DataType, n: usize, fetch: F) -> Result<Vec<Closest<usize, T>>, KdError> {
+        if query_point.dimensions() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite(query_point)?;
+        if self.is_empty() { return Err(KdError::EmptyTree); }
+        if n == 0 { return Ok(Vec::new()); }
+
+        // Candidates are ordered by row, so ties are broken by row
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        search_path(n, 1, &mut SearchStats::default(), &mut scratch,
+                    |index, _| {
+                        let node = self.tree[index].as_ref().ok_or(KdError::NodeMissing)?;
+                        let distance = fetch(node.row).distance(query_point)?;
+                        let candidate = Closest { point: node.row, distance, index: Some(node.row) };
+                        Ok(PathNode { candidate: Some(candidate), left_child: node.left_child, right_child: node.right_child, level: 0 })
+                    },
+                    |index, _, farthest| {
+                        let node = self.tree[index].as_ref().ok_or(KdError::NodeMissing)?;
+                        Ok((node.split - query_point.coord(node.dimension)).abs() <= farthest)
+                    },
+                    |root, path| self.go_down_path(query_point, root, path))?;
+
+        Ok(scratch.candidates.into_sorted_vec().into_iter().map(|closest| Closest::new(closest.point, closest.distance)).collect())
+    }
+
+    /// Search tree from sub tree root to leaf node, pushing each node and the direction taken from
+    /// it onto the path
+    fn go_down_path<DataType: Point<T>>(&self, query_point: &DataType, root: usize, path: &mut Vec<(usize, NodeType)>) {
+        let mut current_index = root;
+        while let Some(node) = &self.tree[current_index] {
+            // Go left if split is greater than query in current dimension, otherwise go right
+            if node.split > query_point.coord(node.dimension) {
+                path.push((current_index, NodeType::LeftChild));
                 current_index = node.left_child;
-                child_type = NodeType::LeftChild;
-            // Otherwise go right
             } else {
+                path.push((current_index, NodeType::RightChild));
                 current_index = node.right_child;
-                child_type = NodeType::RightChild;
             }
+        }
+    }
+
+    /// Number of rows in tree
+    pub fn len(&self) -> usize { self.tree.len() - 1 }
+
+    /// Whether tree has no rows
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Getter for dimensions of tree
+    pub fn get_num_dimensions(&self) -> usize { self.num_dimensions }
+}
+
+/// KdTreeRef functions
+#[cfg(feature="default")]
+impl<'a> KdTreeRef<'a> {
+    /// Create a balanced tree over the rows of an array, which stays borrowed by the tree
+    pub fn new(rows: ArrayView2<'a, f64>) -> Result<Self, KdError> {
+        let index = KdTreeBuilder::new().dimensions(rows.ncols()).build_index(rows.nrows(), |row| Self::read_row(&rows, row))?;
+
+        Ok(KdTreeRef { index, rows })
+    }
+
+    /// Find the rows of the n closest points to query point sorted by ascending distance
+    pub fn find_n_closest(&self, query_point: ArrayView1<f64>, n: usize) -> Result<Vec<Closest<usize, f64>>, KdError> {
+        let query_point = match query_point.as_slice() {
+            Some(coords) => Cow::Borrowed(coords),
+            None => Cow::Owned(query_point.to_vec()),
         };
 
-        Ok((index, child_type))
+        self.index.find_n_closest(&query_point, n, |row| Self::read_row(&self.rows, row))
     }
 
-    /// Get the maximum distance in binary heap of closest points
-    fn get_max_min(&self, bh_closest: &BinaryHeap<Closest<usize, T>>) -> Result<T, KdError> {
-        match bh_closest.peek() {
-            Some(max) => { Ok(max.distance) },
-            None => { Err(KdError::BinaryHeapError) },
+    /// Point of row, borrowed from the array if its rows are contiguous
+    fn read_row<'r>(rows: &'r ArrayView2<'a, f64>, row: usize) -> Cow<'r, [f64]> {
+        let dimensions = rows.ncols();
+        match rows.as_slice() {
+            Some(coords) => Cow::Borrowed(&coords[row * dimensions..(row + 1) * dimensions]),
+            None => Cow::Owned(rows.row(row).to_vec()),
+        }
+    }
+
+    /// Array whose rows the tree is over
+    pub fn rows(&self) -> ArrayView2<'a, f64> { self.rows }
+
+    /// Number of rows in tree
+    pub fn len(&self) -> usize { self.index.len() }
+
+    /// Whether tree has no rows
+    pub fn is_empty(&self) -> bool { self.index.is_empty() }
+
+    /// Getter for dimensions of tree
+    pub fn get_num_dimensions(&self) -> usize { self.index.get_num_dimensions() }
+}
+
+/// KdTreeFlat functions
+impl<T: Float> KdTreeFlat<T> where Vec<T>: Point<T> {
+    /// Create a new flat tree with specified number of dimensions
+    pub fn new(dimensions: usize) -> Self {
+        KdTreeFlat { nodes: Vec::new(), coords: Vec::new(), num_dimensions: dimensions, max_levels: 0 }
+    }
+
+    /// Create a balanced flat tree from a vector of points by recursively splitting on the median
+    pub fn build_from_vec(dimensions: usize, points: Vec<Vec<T>>) -> Result<Self, KdError> {
+        // Infer dimensions from first point if none were given
+        let dimensions = infer_dimensions(dimensions, &points);
+        if points.iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() { check_finite(point)?; }
+
+        let split_dims: Vec<usize> = (0..dimensions).collect();
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        let mut skeleton: Vec<Option<Node<usize>>> = Vec::with_capacity(points.len());
+        skeleton.resize_with(points.len(), Default::default);
+        let max_levels = build_subtree(&points, &mut order, &mut skeleton, 1, 0, NodeType::RootNode, 0, &split_dims, &mut || {});
+
+        // Skeleton indices start at one, while the flat root is at zero
+        let mut tree = KdTreeFlat { nodes: Vec::with_capacity(points.len()), coords: Vec::with_capacity(points.len() * dimensions), num_dimensions: dimensions, max_levels };
+        for node in skeleton.into_iter().flatten() {
+            tree.nodes.push(FlatNode {
+                left_child: node.left_child.saturating_sub(1),
+                right_child: node.right_child.saturating_sub(1),
+                dimension: node.dimension,
+            });
+            tree.coords.extend_from_slice(&points[node.point]);
+        }
+
+        Ok(tree)
+    }
+
+    /// Add a point to the tree
+    pub fn add_point(&mut self, point: &[T]) -> Result<(), KdError> {
+        if point.len() != self.num_dimensions || self.num_dimensions == 0 { return Err(KdError::DimensionError); }
+        if let Some(dimension) = point.iter().position(|value| !value.is_finite()) { return Err(KdError::NonFiniteValue { dimension }); }
+
+        let index = self.nodes.len();
+        let mut level = 0;
+        if index > 0 {
+            // Go down to the leaf whose empty child the point belongs in
+            let mut current_index = 0;
+            loop {
+                level += 1;
+                let node = &mut self.nodes[current_index];
+                let child = if self.coords[current_index * self.num_dimensions + node.dimension] > point[node.dimension] {
+                    &mut node.left_child
+                } else {
+                    &mut node.right_child
+                };
+                if *child == 0 {
+                    *child = index;
+                    break;
+                }
+                current_index = *child;
+            }
+        }
+
+        self.max_levels = self.max_levels.max(level);
+        self.nodes.push(FlatNode { left_child: 0, right_child: 0, dimension: level % self.num_dimensions });
+        self.coords.extend_from_slice(point);
+
+        Ok(())
+    }
+
+    /// Find n closest points to query point sorted by ascending distance
+    pub fn find_n_closest(&self, query_point: &[T], n: usize) -> Result<Vec<Closest<Vec<T>, T>>, KdError> {
+        if query_point.len() != self.num_dimensions { return Err(KdError::DimensionError); }
+        check_finite_slice(query_point)?;
+        if self.is_empty() { return Err(KdError::EmptyTree); }
+        if n == 0 { return Ok(Vec::new()); }
+
+        // The root is at index 0, which is never a child, so 0 still marks a missing child
+        let mut scratch = QueryScratch::with_capacity(n, self.max_levels + 1);
+        search_path(n, 0, &mut SearchStats::default(), &mut scratch,
+                    |index, _| {
+                        let node = &self.nodes[index];
+                        let distance = self.point(index).iter().zip(query_point.iter()).fold(T::zero(), |distance, (a, b)| distance + (*a - *b) * (*a - *b)).sqrt();
+                        let candidate = Closest { point: index, distance, index: Some(index) };
+                        Ok(PathNode { candidate: Some(candidate), left_child: node.left_child, right_child: node.right_child, level: 0 })
+                    },
+                    |index, _, farthest| {
+                        let dimension = self.nodes[index].dimension;
+                        Ok((self.point(index)[dimension] - query_point[dimension]).abs() <= farthest)
+                    },
+                    |root, path| self.go_down_path(query_point, root, path))?;
+
+        Ok(scratch.candidates.into_sorted_vec()
+                             .into_iter()
+                             .map(|closest| Closest { point: self.point(closest.point).to_vec(), distance: closest.distance, index: closest.index })
+                             .collect())
+    }
+
+    /// Search tree from sub tree root to leaf node, pushing each node and the direction taken from
+    /// it onto the path
+    fn go_down_path(&self, query_point: &[T], root: usize, path: &mut Vec<(usize, NodeType)>) {
+        let mut current_index = root;
+        loop {
+            let node = &self.nodes[current_index];
+            // Go left if node point is greater than query in current dimension, otherwise go right
+            let (child_type, child) = if self.point(current_index)[node.dimension] > query_point[node.dimension] {
+                (NodeType::LeftChild, node.left_child)
+            } else {
+                (NodeType::RightChild, node.right_child)
+            };
+            path.push((current_index, child_type));
+
+            if child == 0 { break; }
+            current_index = child;
         }
     }
 
+    /// Coordinates of the point stored at index
+    pub fn point(&self, index: usize) -> &[T] {
+        &self.coords[index * self.num_dimensions..(index + 1) * self.num_dimensions]
+    }
+
+    /// Number of points in tree
+    pub fn len(&self) -> usize { self.nodes.len() }
+
+    /// Whether tree has no points
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
     /// Getter for dimensions of tree
     pub fn get_num_dimensions(&self) -> usize { self.num_dimensions }
 }
 
+/// KdTreeMap functions
+impl<T: Float, DataType: Point<T> + Clone, V> KdTreeMap<DataType, V, T> {
+    /// Create a new map with specified number of dimensions, zero infers them from the first point
+    pub fn new(dimensions: usize) -> Self {
+        KdTreeMap { tree: KdTree::new(dimensions), values: Vec::new() }
+    }
+
+    /// Create a balanced map from pairs of points and their payloads
+    ///
+    /// Only the points are sorted while splitting on medians, and each payload is moved to the
+    /// node its point ends up in afterwards.
+    pub fn build_from_pairs(dimensions: usize, pairs: Vec<(DataType, V)>) -> Result<Self, KdError> {
+        let (points, values): (Vec<DataType>, Vec<V>) = pairs.into_iter().unzip();
+
+        // Infer dimensions from first point if none were given
+        let dimensions = infer_dimensions(dimensions, &points);
+        if points.iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        for point in points.iter() { check_finite(point)?; }
+
+        // Each slot of the skeleton records which point, and so which payload, it holds
+        let split_dims: Vec<usize> = (0..dimensions).collect();
+        let (skeleton, max_levels) = KdTree::<DataType, T>::build_skeleton(&points, &split_dims);
+        let mut values: Vec<Option<V>> = values.into_iter().map(Some).collect();
+        let values = skeleton.iter().map(|slot| slot.as_ref().and_then(|node| values[node.point].take())).collect::<Option<Vec<V>>>();
+
+        match values {
+            Some(values) => Ok(KdTreeMap { tree: KdTree::<DataType, T>::assemble(dimensions, points, skeleton, max_levels), values }),
+            None => Err(KdError::NodeMissing),
+        }
+    }
+
+    /// Add a point with its payload to the map
+    pub fn insert(&mut self, point: DataType, value: V) -> Result<(), KdError> {
+        self.tree.add_point(point)?;
+        self.values.push(value);
+
+        Ok(())
+    }
+
+    /// Find k closest points to query point sorted by ascending distance, each with its payload
+    pub fn k_nearest(&self, query_point: &DataType, k: usize) -> Result<ClosestWithValues<'_, DataType, V, T>, KdError> {
+        self.tree.k_nearest(query_point, k)?.into_iter().map(|closest| match closest.index() {
+            Some(index) => Ok((closest, &self.values[index])),
+            None => Err(KdError::NodeMissing),
+        }).collect()
+    }
+
+    /// Find closest point to query point along with its payload and distance
+    pub fn find_closest(&self, query_point: &DataType) -> Result<(DataType, &V, T), KdError> {
+        match self.k_nearest(query_point, 1)?.pop() {
+            Some((closest, value)) => Ok((closest.point, value, closest.distance)),
+            None => Err(KdError::BinaryHeapError),
+        }
+    }
+
+    /// Point and payload stored at index, as reported by `Closest::index`
+    pub fn get(&self, index: usize) -> Option<(&DataType, &V)> {
+        Some((self.tree.get_point(index)?, self.values.get(index)?))
+    }
+
+    /// Tree holding the points of the map
+    pub fn tree(&self) -> &KdTree<DataType, T> { &self.tree }
+
+    /// Number of points in map
+    pub fn len(&self) -> usize { self.values.len() }
+
+    /// Whether map has no points
+    pub fn is_empty(&self) -> bool { self.values.is_empty() }
+}
+
+/// Default tree has no dimensions, which are adopted from the first point added
+impl<T: Float, DataType: Point<T> + Clone> Default for KdTree<DataType, T> {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// Parallel KdTree functions
+#[cfg(feature="rayon")]
+impl<T: Float + Send + Sync, DataType: Point<T> + Clone + Send + Sync> KdTree<DataType, T> {
+    /// Create a balanced tree from a vector of points, building sub trees in parallel
+    pub fn par_build_from_vec(dimensions: usize, points: Vec<DataType>) -> Result<Self, KdError> {
+        // Infer dimensions from first point if none were given
+        let dimensions = infer_dimensions(dimensions, &points);
+
+        // Verify all points have proper number of dimensions
+        if points.par_iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        points.par_iter().try_for_each(check_finite)?;
+
+        let split_dims: Vec<usize> = (0..dimensions).collect();
+        Ok(Self::par_build_with_splits(dimensions, points, &split_dims))
+    }
+
+    /// Create a balanced tree in parallel like `par_build_from_vec`, also returning how long the
+    /// build took
+    #[cfg(feature="std")]
+    pub fn par_build_balanced(dimensions: usize, points: Vec<DataType>) -> Result<(Self, Duration), KdError> {
+        let start = Instant::now();
+        let tree = Self::par_build_from_vec(dimensions, points)?;
+
+        Ok((tree, start.elapsed()))
+    }
+
+    /// Build a balanced tree in parallel cycling through the given split dimensions
+    fn par_build_with_splits(dimensions: usize, points: Vec<DataType>, split_dims: &[usize]) -> Self {
+        let mut order: Vec<usize> = (0..points.len()).collect();
+        let mut skeleton: Vec<Option<Node<usize>>> = Vec::with_capacity(points.len());
+        skeleton.resize_with(points.len(), Default::default);
+        let max_levels = par_build_subtree(&points, &mut order, &mut skeleton, 1, 0, NodeType::RootNode, 0, split_dims);
+
+        Self::assemble(dimensions, points, skeleton, max_levels)
+    }
+
+    /// Run an arbitrary query function for each query in parallel, returning results in order
+    pub fn par_query_map<Q, R, F>(&self, queries: &[Q], f: F) -> Vec<R>
+        where Q: Sync, R: Send, F: Fn(&Self, &Q) -> R + Sync
+    {
+        queries.par_iter().map(|query| f(self, query)).collect()
+    }
+
+    /// Find the k nearest neighbors of every point in the tree in parallel, see `knn_graph`
+    pub fn par_knn_graph(&self, k: usize) -> Result<Vec<Vec<Closest<usize, T>>>, KdError> {
+        (1..self.last_point).into_par_iter().map(|index| self.point_neighbors(index, k)).collect()
+    }
+
+    /// Distance from every point in the tree to its kth nearest other point in parallel, see
+    /// `knn_self_distances`
+    pub fn par_knn_self_distances(&self, k: usize) -> Result<Vec<T>, KdError> {
+        (1..self.last_point).into_par_iter().filter(|&index| self.is_live(index)).map(|index| self.self_distance(index, k)).collect()
+    }
+
+    /// Distances from each query point to its k closest points in parallel, see `distance_matrix`
+    pub fn par_distance_matrix(&self, queries: &[DataType], k: usize) -> Result<Vec<Vec<T>>, KdError> {
+        queries.par_iter().map(|query_point| self.nearest_distances(query_point, k)).collect()
+    }
+
+    /// Find every unordered pair of points within radius of each other in parallel, see
+    /// `all_pairs_within`
+    pub fn par_all_pairs_within(&self, radius: T) -> Result<Vec<(usize, usize, T)>, KdError> {
+        let pairs: Vec<Vec<(usize, usize, T)>> = (1..self.last_point).into_par_iter().map(|index| self.pairs_within(index, radius)).collect::<Result<_, _>>()?;
+        Ok(pairs.into_iter().flatten().collect())
+    }
+
+    /// Add a batch of points to the tree in parallel
+    ///
+    /// Incremental insertion is inherently sequential, so this collects the existing and new
+    /// points and rebuilds a balanced tree from all of them with `par_build_from_vec` rather than
+    /// inserting each point individually.
+    pub fn par_extend<I: IntoParallelIterator<Item = DataType>>(&mut self, points: I) -> Result<(), KdError> {
+        let dimensions = self.num_dimensions;
+        let mut points: Vec<DataType> = points.into_par_iter().collect();
+        let dimensions = if self.last_point == 1 { infer_dimensions(dimensions, &points) } else { dimensions };
+
+        // Verify new points before touching the existing tree
+        if points.par_iter().any(|point| point.dimensions() != dimensions) { return Err(KdError::DimensionError); }
+        points.par_iter().try_for_each(check_finite)?;
+        for point in points.iter() { self.validate_input(point)?; }
+        self.check_node_limit(self.len() + points.len())?;
+
+        self.num_dimensions = dimensions;
+        let split_dims = self.split_dims();
+        points.extend(self.take_points());
+
+        // Only replace the nodes so tree settings are kept
+        let rebuilt = Self::par_build_with_splits(dimensions, points, &split_dims);
+        self.replace_nodes(rebuilt);
+
+        Ok(())
+    }
+}
+
+/// Verify every coordinate of point is finite, since NaN and infinity break ordering in the tree
+fn check_finite<DataType: Point<T>, T: Float>(point: &DataType) -> Result<(), KdError> {
+    if point.is_finite() { return Ok(()); }
+
+    match (0..point.dimensions()).find(|&i| !point.coord(i).is_finite()) {
+        Some(dimension) => Err(KdError::NonFiniteValue { dimension }),
+        None => Ok(()),
+    }
+}
+
+/// Verify every coordinate of a slice is finite, like `check_finite`
+fn check_finite_slice<T: Float>(coords: &[T]) -> Result<(), KdError> {
+    match coords.iter().position(|coord| !coord.is_finite()) {
+        Some(dimension) => Err(KdError::NonFiniteValue { dimension }),
+        None => Ok(()),
+    }
+}
+
+/// Distance a point must beat to be kept in a binary heap of the n closest points, see
+/// `KdTree::worst_distance`
+fn worst_distance<P, T: Float>(bh_closest: &BinaryHeap<Closest<P, T>>, n: usize) -> T {
+    if n == 0 { return T::neg_infinity(); }
+    match bh_closest.peek() {
+        Some(max) if bh_closest.len() >= n => max.distance,
+        _ => T::infinity(),
+    }
+}
+
+/// Search a tree from the sub tree at root for the n closest nodes, leaving them in the candidate
+/// heap of scratch. This is the backtracking shared by every tree type: visit measures the node at
+/// an index against the candidates kept so far, search_sub_tree says whether the sub tree behind a
+/// node could hold a point no farther than the worst kept distance, and go_down pushes the nodes
+/// from a sub tree root to a leaf onto the path.
+fn search_path<T: Float, V, S, D>(n: usize, root: usize, stats: &mut SearchStats, scratch: &mut QueryScratch<T>, visit: V, search_sub_tree: S, go_down: D) -> Result<(), KdError>
+    where V: Fn(usize, &BinaryHeap<Closest<usize, T>>) -> Result<PathNode<T>, KdError>,
+          S: Fn(usize, usize, T) -> Result<bool, KdError>,
+          D: Fn(usize, &mut Vec<(usize, NodeType)>),
+{
+    // Binary heap stores closest points
+    let bh_closest = &mut scratch.candidates;
+    bh_closest.clear();
+    // Go down to bin containing point, recording the path so it can be searched back up
+    let path = &mut scratch.path;
+    path.clear();
+    go_down(root, path);
+
+    // Go back up tree to see if there are any closer points
+    while let Some((index, child_type)) = path.pop() {
+        let node = visit(index, bh_closest)?;
+        stats.nodes_visited += 1;
+        stats.max_depth_reached = stats.max_depth_reached.max(node.level);
+
+        if let Some(candidate) = node.candidate {
+            if bh_closest.len() < n {                        // If binary heap isn't full add point
+                bh_closest.push(candidate);
+            } else if bh_closest.peek().is_some_and(|max| candidate < *max) {    // Otherwise check that point orders before the max point in heap, ties going to the lower index
+                bh_closest.pop();
+                bh_closest.push(candidate);
+            }
+        }
+
+        let sub_tree = match child_type {
+            NodeType::LeftChild => { node.right_child },
+            NodeType::RightChild => { node.left_child },
+            NodeType::RootNode => { 0 },
+        };
+
+        // The other subtree always needs searching while the heap isn't full, since the worst kept
+        // distance is infinite until then. Points tying with the worst kept one can still replace
+        // it by index. Each node is only on the path once, so its distance is never measured twice.
+        if sub_tree != 0 && search_sub_tree(index, sub_tree, worst_distance(bh_closest, n))? {
+            // Nodes of the other subtree are added above the rest of the path
+            go_down(sub_tree, path);
+        } else if sub_tree != 0 && node.left_child != 0 && node.right_child != 0 {
+            // Nodes of a leaf bucket are measured without a sub tree to prune
+            stats.subtrees_pruned += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move points into the node skeleton generated by a balanced build, appending nodes to tree
+fn fill_nodes<DataType>(tree: &mut Vec<Option<Node<DataType>>>, points: Vec<DataType>, skeleton: Vec<Option<Node<usize>>>) {
+    let mut points: Vec<Option<DataType>> = points.into_iter().map(Some).collect();
+
+    // Index 0 is reserved to signify a missing child, and keep one free slot for insertion
+    tree.push(None);
+    for slot in skeleton {
+        tree.push(slot.and_then(|node| {
+            points[node.point].take().map(|point| Node {
+                point,
+                child_type: node.child_type,
+                parent: node.parent,
+                left_child: node.left_child,
+                right_child: node.right_child,
+                dimension: node.dimension,
+                level: node.level,
+                removed: false,
+            })
+        }));
+    }
+    tree.push(None);
+}
+
+/// Distance from point to the segment from start to start + direction
+fn segment_point_distance<T: Float>(start: &[T], direction: &[T], point: &[T]) -> T {
+    // Project point onto the segment's line and clamp to the segment
+    let (along, length_squared) = start.iter().zip(direction).zip(point).fold((T::zero(), T::zero()), |(along, length_squared), ((&s, &d), &p)| {
+        (along + (p - s) * d, length_squared + d * d)
+    });
+    let t = if length_squared > T::zero() { (along / length_squared).clamp(T::zero(), T::one()) } else { T::zero() };
+
+    start.iter().zip(direction).zip(point).fold(T::zero(), |distance, ((&s, &d), &p)| {
+        let diff = p - (s + t * d);
+        distance + diff * diff
+    }).sqrt()
+}
+
+/// Exact distance from the segment from start to start + direction to the box between corners lo
+/// and hi, using offsets as scratch space
+///
+/// Squared distance to the box along the segment is a convex piecewise quadratic in the position t
+/// on the segment, whose pieces change where the segment crosses a face of the box. Minimizing each
+/// piece exactly keeps the bound admissible for pruning.
+fn segment_box_distance<T: Float>(start: &[T], direction: &[T], lo: &[T], hi: &[T], offsets: &mut Vec<T>) -> T {
+    // Positions where the segment crosses a face plane split it into pieces
+    offsets.clear();
+    offsets.push(T::zero());
+    offsets.push(T::one());
+    for (((&s, &d), &l), &h) in start.iter().zip(direction).zip(lo).zip(hi) {
+        if d == T::zero() { continue; }
+        for &face in [l, h].iter() {
+            let t = (face - s) / d;
+            if t > T::zero() && t < T::one() { offsets.push(t); }
+        }
+    }
+    offsets.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+
+    let mut best = T::infinity();
+    for piece in offsets.windows(2) {
+        let (t0, t1) = (piece[0], piece[1]);
+
+        // Within a piece each axis is below, inside or above the box throughout, so the gap in
+        // each axis is linear in t and the squared distance a quadratic a t^2 + 2 b t + c
+        let middle = (t0 + t1) / (T::one() + T::one());
+        let gap = |s: T, d: T, l: T, h: T| {
+            let x = s + middle * d;
+            if x < l { (l - s, -d) } else if x > h { (s - h, d) } else { (T::zero(), T::zero()) }
+        };
+        let (mut a, mut b) = (T::zero(), T::zero());
+        for (((&s, &d), &l), &h) in start.iter().zip(direction).zip(lo).zip(hi) {
+            let (offset, slope) = gap(s, d, l, h);
+            a = a + slope * slope;
+            b = b + offset * slope;
+        }
+
+        // Evaluate the minimum from the gaps directly, avoiding cancellation in the quadratic
+        let t = if a > T::zero() { (-b / a).max(t0).min(t1) } else { t0 };
+        let distance = start.iter().zip(direction).zip(lo).zip(hi).fold(T::zero(), |distance, (((&s, &d), &l), &h)| {
+            let (offset, slope) = gap(s, d, l, h);
+            distance + (offset + slope * t).powi(2)
+        });
+        best = best.min(distance);
+    }
+
+    best.sqrt()
+}
+
+/// Smallest gap between offset and the dot product of normal with any point in the box between
+/// corners lo and hi, zero if the hyperplane crosses the box
+fn hyperplane_box_gap<T: Float>(normal: &[T], offset: T, lo: &[T], hi: &[T]) -> T {
+    let (mut min, mut max) = (T::zero(), T::zero());
+    for ((&n, &lo), &hi) in normal.iter().zip(lo.iter()).zip(hi.iter()) {
+        // Axes the normal ignores would multiply unbounded sides by zero
+        if n == T::zero() { continue; }
+        let (a, b) = (n * lo, n * hi);
+        min = min + a.min(b);
+        max = max + a.max(b);
+    }
+
+    (min - offset).max(offset - max).max(T::zero())
+}
+
+/// Use dimensionality of the first point when no dimensions are specified
+fn infer_dimensions<DataType: Point<T>, T: Float>(dimensions: usize, points: &[DataType]) -> usize {
+    match points.first() {
+        Some(point) if dimensions == 0 => point.dimensions(),
+        _ => dimensions,
+    }
+}
+
+/// Compare two points in the given dimension
+fn compare_dimension<DataType: Point<T>, T: Float>(a: &DataType, b: &DataType, dimension: usize) -> Ordering {
+    if a.greater(b, dimension) {
+        Ordering::Greater
+    } else if b.greater(a, dimension) {
+        Ordering::Less
+    } else {
+        Ordering::Equal
+    }
+}
+
+/// Point to split points on in dimension, the median point for odd counts and the midpoint of the
+/// two middle points for even counts. Returns `None` if there are no points.
+pub fn median_split<DataType: Point<T> + Clone, T: Float>(points: &[DataType], dimension: usize) -> Option<DataType> {
+    if points.is_empty() { return None; }
+
+    let mut order: Vec<usize> = (0..points.len()).collect();
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |a, b| compare_dimension(&points[*a], &points[*b], dimension));
+    let upper = &points[order[mid]];
+    if points.len() % 2 == 1 { return Some(upper.clone()); }
+
+    // Lower middle point is the greatest of the points before the median
+    let lower = order[..mid].iter()
+                            .map(|&i| &points[i])
+                            .max_by(|a, b| compare_dimension(*a, *b, dimension))
+                            .unwrap();
+    Some(lower.midpoint(upper))
+}
+
+/// Reorder indices so that the median point in the current dimension is at the returned position,
+/// every point before it is strictly less and every point after it is greater or equal. This
+/// matches `go_down`, which sends points equal to the split value to the right.
+fn partition_median<DataType: Point<T>, T: Float>(points: &[DataType], order: &mut [usize], dimension: usize) -> usize {
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |a, b| compare_dimension(&points[*a], &points[*b], dimension));
+
+    // Points left of the median may equal it, move them next to the median
+    let median = order[mid];
+    let mut split = 0;
+    for i in 0..mid {
+        if points[median].greater(&points[order[i]], dimension) {
+            order.swap(i, split);
+            split += 1;
+        }
+    }
+
+    split
+}
+
+/// Recursively build a balanced sub tree, writing nodes that reference points by index into
+/// `nodes` starting at tree index `base` and calling placed after writing each. Returns the deepest
+/// level in the sub tree.
+#[allow(clippy::too_many_arguments)]
+fn build_subtree<DataType: Point<T>, T: Float>(points: &[DataType], order: &mut [usize], nodes: &mut [Option<Node<usize>>],
+                                              base: usize, parent: usize, child_type: NodeType, level: usize, split_dims: &[usize],
+                                              placed: &mut dyn FnMut()) -> usize {
+    if order.is_empty() { return 0; }
+
+    let dimension = split_dims[level % split_dims.len()];
+    let mid = partition_median(points, order, dimension);
+    let (left_order, rest) = order.split_at_mut(mid);
+    let (median, right_order) = rest.split_first_mut().unwrap();
+    let (root_slot, rest) = nodes.split_first_mut().unwrap();
+    let (left_nodes, right_nodes) = rest.split_at_mut(mid);
+
+    *root_slot = Some(Node {
+        point: *median,
+        child_type,
+        parent,
+        left_child: if left_order.is_empty() { 0 } else { base + 1 },
+        right_child: if right_order.is_empty() { 0 } else { base + 1 + mid },
+        dimension,
+        level,
+        removed: false,
+    });
+    placed();
+
+    let left_levels = build_subtree(points, left_order, left_nodes, base + 1, base, NodeType::LeftChild, level + 1, split_dims, placed);
+    let right_levels = build_subtree(points, right_order, right_nodes, base + 1 + mid, base, NodeType::RightChild, level + 1, split_dims, placed);
+    level.max(left_levels).max(right_levels)
+}
+
+/// Recursively rearrange nodes into a balanced sub tree in place, relinking them as if they were
+/// stored starting at tree index `base`. Returns the deepest level in the sub tree.
+fn balance_subtree<DataType: Point<T>, T: Float>(nodes: &mut [Option<Node<DataType>>], base: usize, parent: usize, child_type: NodeType,
+                                                level: usize, split_dims: &[usize]) -> usize {
+    if nodes.is_empty() { return 0; }
+
+    // Partition like `partition_median`, comparing the points held by nodes
+    let dimension = split_dims[level % split_dims.len()];
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| match (a, b) {
+        (Some(a), Some(b)) => compare_dimension(&a.point, &b.point, dimension),
+        _ => Ordering::Equal,
+    });
+    let mut split = 0;
+    for i in 0..mid {
+        let is_less = match (&nodes[mid], &nodes[i]) {
+            (Some(median), Some(node)) => median.point.greater(&node.point, dimension),
+            _ => false,
+        };
+        if is_less {
+            nodes.swap(i, split);
+            split += 1;
+        }
+    }
+
+    // Sub tree root comes first, followed by the left and right sub trees
+    nodes[..=split].rotate_right(1);
+    let (root_slot, rest) = nodes.split_first_mut().unwrap();
+    let (left_nodes, right_nodes) = rest.split_at_mut(split);
+    if let Some(node) = root_slot {
+        node.child_type = child_type;
+        node.parent = parent;
+        node.left_child = if left_nodes.is_empty() { 0 } else { base + 1 };
+        node.right_child = if right_nodes.is_empty() { 0 } else { base + 1 + split };
+        node.dimension = dimension;
+        node.level = level;
+    }
+
+    let left_levels = balance_subtree(left_nodes, base + 1, base, NodeType::LeftChild, level + 1, split_dims);
+    let right_levels = balance_subtree(right_nodes, base + 1 + split, base, NodeType::RightChild, level + 1, split_dims);
+    level.max(left_levels).max(right_levels)
+}
+
+/// Recursively build a balanced sub tree of an index tree over rows, reading each row in the sub
+/// tree once to find the median in the split dimension. Returns the deepest level in the sub tree.
+fn build_index_subtree<DataType: Point<T>, T: Float, F: Fn(usize) -> DataType>(fetch: &F, rows: &mut [usize], nodes: &mut [Option<IndexNode<T>>],
+                                                                              base: usize, level: usize, dimensions: usize) -> Result<usize, KdError> {
+    if rows.is_empty() { return Ok(0); }
+
+    let dimension = level % dimensions;
+    let mut keyed = Vec::with_capacity(rows.len());
+    for &row in rows.iter() {
+        let point = fetch(row);
+        if point.dimensions() != dimensions { return Err(KdError::DimensionError); }
+        check_finite(&point)?;
+        keyed.push((point.coord(dimension), row));
+    }
+
+    // Partition like `partition_median` so points equal to the split go right
+    let mid = keyed.len() / 2;
+    keyed.select_nth_unstable_by(mid, |a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    let median = keyed[mid].0;
+    let mut split = 0;
+    for i in 0..mid {
+        if keyed[i].0 < median {
+            keyed.swap(i, split);
+            split += 1;
+        }
+    }
+    for (row, (_, keyed_row)) in rows.iter_mut().zip(keyed.iter()) { *row = *keyed_row; }
+
+    let (left_rows, rest) = rows.split_at_mut(split);
+    let (median_row, right_rows) = rest.split_first_mut().unwrap();
+    let (root_slot, rest) = nodes.split_first_mut().unwrap();
+    let (left_nodes, right_nodes) = rest.split_at_mut(split);
+
+    *root_slot = Some(IndexNode {
+        row: *median_row,
+        split: median,
+        dimension,
+        left_child: if left_rows.is_empty() { 0 } else { base + 1 },
+        right_child: if right_rows.is_empty() { 0 } else { base + 1 + split },
+    });
+
+    let left_levels = build_index_subtree(fetch, left_rows, left_nodes, base + 1, level + 1, dimensions)?;
+    let right_levels = build_index_subtree(fetch, right_rows, right_nodes, base + 1 + split, level + 1, dimensions)?;
+    Ok(level.max(left_levels).max(right_levels))
+}
+
+/// Number of levels after which randomly chosen split dimensions repeat
+const RANDOM_SPLIT_PERIOD: usize = 64;
+
+/// Split dimension out of dimensions for level of a tree with random splits from seed
+///
+/// Levels are hashed with splitmix64, so the choice only depends on the seed and the level modulo
+/// `RANDOM_SPLIT_PERIOD`.
+fn random_split(seed: u64, level: usize, dimensions: usize) -> usize {
+    let mut z = seed.wrapping_add(((level % RANDOM_SPLIT_PERIOD) as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    (z % dimensions as u64) as usize
+}
+
+/// Sub trees with fewer points than this are built serially, since spawning tasks for them costs
+/// more than it saves
+#[cfg(feature="rayon")]
+const PAR_BUILD_THRESHOLD: usize = 10_000;
+
+/// Parallel version of `build_subtree` that builds left and right sub trees on separate threads
+#[cfg(feature="rayon")]
+#[allow(clippy::too_many_arguments)]
+fn par_build_subtree<DataType: Point<T> + Sync, T: Float>(points: &[DataType], order: &mut [usize], nodes: &mut [Option<Node<usize>>],
+                                                         base: usize, parent: usize, child_type: NodeType, level: usize, split_dims: &[usize]) -> usize {
+    if order.len() < PAR_BUILD_THRESHOLD { return build_subtree(points, order, nodes, base, parent, child_type, level, split_dims, &mut || {}); }
+
+    let dimension = split_dims[level % split_dims.len()];
+    let mid = partition_median(points, order, dimension);
+    let (left_order, rest) = order.split_at_mut(mid);
+    let (median, right_order) = rest.split_first_mut().unwrap();
+    let (root_slot, rest) = nodes.split_first_mut().unwrap();
+    let (left_nodes, right_nodes) = rest.split_at_mut(mid);
+
+    *root_slot = Some(Node {
+        point: *median,
+        child_type,
+        parent,
+        left_child: if left_order.is_empty() { 0 } else { base + 1 },
+        right_child: if right_order.is_empty() { 0 } else { base + 1 + mid },
+        dimension,
+        level,
+        removed: false,
+    });
+
+    let (left_levels, right_levels) = rayon::join(
+        || par_build_subtree(points, left_order, left_nodes, base + 1, base, NodeType::LeftChild, level + 1, split_dims),
+        || par_build_subtree(points, right_order, right_nodes, base + 1 + mid, base, NodeType::RightChild, level + 1, split_dims),
+    );
+    level.max(left_levels).max(right_levels)
+}
+
+impl core::fmt::Display for KdError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        if let KdError::NonFiniteValue { dimension } = *self {
+            return write!(f, "KdTree error: non finite value in dimension {}", dimension);
+        }
 
-impl std::fmt::Display for KdError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let description = match *self {
             KdError::DimensionError => "dimension error",
             KdError::EmptyTree => "no nodes in tree",
             KdError::NodeMissing => "Cant access current node",
             KdError::BinaryHeapError => "Error accessing binary heap",
+            KdError::InvalidInput => "point is invalid for tree metric",
+            KdError::InvalidConfiguration => "incompatible tree options",
+            KdError::NonFiniteValue { .. } => "non finite value",
+            KdError::CapacityExceeded => "node limit of tree exceeded",
         };
         write!(f, "KdTree error: {}", description)
     }
 }
 
+/// Closest functions
+impl<DataType, T> Closest<DataType, T> {
+    /// Pair point with its distance, for results not found at a node of a tree
+    pub fn new(point: DataType, distance: T) -> Self {
+        Closest { point, distance, index: None }
+    }
+
+    /// Index of the node point was found at, indexed like `knn_graph`
+    ///
+    /// Set for points returned by `KdTree` searches and `brute_force`, so results of the two can
+    /// be traced back to the same node with `KdTree::get_point`.
+    pub fn index(&self) -> Option<usize> { self.index }
+}
+
+/// Closest functions for search candidates, which hold the tree index of a node
+impl<T> Closest<usize, T> {
+    /// Pair tree index of a node with its distance, so ties are broken by index like resolved
+    /// results
+    fn at_node(index: usize, distance: T) -> Self {
+        Closest { point: index, distance, index: Some(index - 1) }
+    }
+}
+
+/// Closest points are ordered by distance, with ties broken by index
 impl<DataType, T: Float> Ord for Closest<DataType, T> {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+        self.distance.partial_cmp(&other.distance).unwrap_or(Ordering::Equal).then(self.index.cmp(&other.index))
     }
 }
 
 impl<DataType, T: Float> PartialOrd for Closest<DataType, T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        self.distance.partial_cmp(&other.distance)
+        Some(self.cmp(other))
     }
 }
 
@@ -306,6 +4082,6 @@ impl<DataType, T: Float> Eq for Closest<DataType, T> {}
 
 impl<DataType, T: Float> PartialEq for Closest<DataType, T> {
     fn eq(&self, other: &Self) -> bool {
-        self.distance == other.distance
+        self.cmp(other) == Ordering::Equal
     }
 }
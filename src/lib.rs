@@ -1,6 +1,15 @@
+#![cfg_attr(not(any(feature="std", test)), no_std)]
+extern crate alloc;
+
 pub mod kd_tree;
 use crate::kd_tree::{Point, KdError};
+use alloc::vec::Vec;
+use alloc::vec;
+use alloc::borrow::Cow;
+use num_traits::Float;
 extern crate num_traits;
+#[cfg(feature="rayon")]
+extern crate rayon;
 
 // Include python module if feature is enabled
 #[cfg(feature="default")]
@@ -9,19 +18,27 @@ pub mod py_module;
 extern crate ndarray;
 #[cfg(feature="default")]
 use ndarray::Array1;
+#[cfg(feature="arrayvec")]
+use arrayvec::ArrayVec;
+#[cfg(feature="glam")]
+use glam::{DVec3, Vec3};
+
+/// Squared Euclidean distance between points given as coordinate slices
+fn slice_distance_squared<T: Float>(a: &[T], b: &[T]) -> Result<T, KdError> {
+    if a.len() != b.len() {
+        return Err(KdError::DimensionError);
+    }
+
+    Ok(a.iter().zip(b.iter()).fold(T::zero(), |distance, (&a, &b)| distance + (a - b) * (a - b)))
+}
 
 impl Point<f64> for Vec<f64> {
     fn distance(&self, other: &Self) -> Result<f64, KdError> {
-        if self.len() != other.len() {
-            return Err(KdError::DimensionError);
-        }
+        self.distance_squared(other).map(Float::sqrt)
+    }
 
-        let mut distance = 0f64;
-        for i in 0..self.len() {
-            let diff = self[i] - other[i];
-            distance += diff * diff; 
-        }
-        Ok(distance.sqrt())
+    fn distance_squared(&self, other: &Self) -> Result<f64, KdError> {
+        slice_distance_squared(self, other)
     }
 
     fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
@@ -34,21 +51,29 @@ impl Point<f64> for Vec<f64> {
         plane
     }
 
+    fn coord(&self, cur_dimension: usize) -> f64 { self[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: f64) { self[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> Vec<f64> {
+        self.iter().zip(other.iter()).map(|(a, b)| (a + b) / 2f64).collect()
+    }
+
     fn dimensions(&self) -> usize { self.len() }
+
+    fn project(&self, dims: &[usize]) -> Result<Vec<f64>, KdError> {
+        if dims.iter().any(|&dim| dim >= self.len()) { return Err(KdError::DimensionError); }
+        Ok(dims.iter().map(|&dim| self[dim]).collect())
+    }
 }
 
 impl Point<f32> for Vec<f32> {
     fn distance(&self, other: &Self) -> Result<f32, KdError> {
-        if self.len() != other.len() {
-            return Err(KdError::DimensionError);
-        }
+        self.distance_squared(other).map(Float::sqrt)
+    }
 
-        let mut distance = 0f32;
-        for i in 0..self.len() {
-            let diff = self[i] - other[i];
-            distance += diff * diff; 
-        }
-        Ok(distance.sqrt())
+    fn distance_squared(&self, other: &Self) -> Result<f32, KdError> {
+        slice_distance_squared(self, other)
     }
 
     fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
@@ -61,12 +86,66 @@ impl Point<f32> for Vec<f32> {
         plane
     }
 
+    fn coord(&self, cur_dimension: usize) -> f32 { self[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: f32) { self[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> Vec<f32> {
+        self.iter().zip(other.iter()).map(|(a, b)| (a + b) / 2f32).collect()
+    }
+
+    fn dimensions(&self) -> usize { self.len() }
+
+    fn project(&self, dims: &[usize]) -> Result<Vec<f32>, KdError> {
+        if dims.iter().any(|&dim| dim >= self.len()) { return Err(KdError::DimensionError); }
+        Ok(dims.iter().map(|&dim| self[dim]).collect())
+    }
+}
+
+/// Points that are either borrowed or owned, so borrowed coordinates can be queried without
+/// copying them. Setting a coordinate of a borrowed point copies it first.
+impl Point<f64> for Cow<'_, [f64]> {
+    fn distance(&self, other: &Self) -> Result<f64, KdError> {
+        self.distance_squared(other).map(Float::sqrt)
+    }
+
+    fn distance_squared(&self, other: &Self) -> Result<f64, KdError> {
+        slice_distance_squared(self, other)
+    }
+
+    fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+        self[cur_dimension] > other[cur_dimension]
+    }
+
+    fn split_plane(&self, cur_dimension: usize) -> Self {
+        let mut plane = vec![0f64; self.len()];
+        plane[cur_dimension] = self[cur_dimension];
+        Cow::Owned(plane)
+    }
+
+    fn coord(&self, cur_dimension: usize) -> f64 { self[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: f64) { self.to_mut()[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> Self {
+        Cow::Owned(self.iter().zip(other.iter()).map(|(a, b)| (a + b) / 2f64).collect())
+    }
+
     fn dimensions(&self) -> usize { self.len() }
+
+    fn project(&self, dims: &[usize]) -> Result<Self, KdError> {
+        if dims.iter().any(|&dim| dim >= self.len()) { return Err(KdError::DimensionError); }
+        Ok(Cow::Owned(dims.iter().map(|&dim| self[dim]).collect()))
+    }
 }
 
 #[cfg(feature="default")]
 impl Point<f64> for Array1<f64> {
     fn distance(&self, other: &Self) -> Result<f64, KdError> {
+        self.distance_squared(other).map(f64::sqrt)
+    }
+
+    fn distance_squared(&self, other: &Self) -> Result<f64, KdError> {
         if self.len() != other.len() {
             return Err(KdError::DimensionError);
         }
@@ -74,9 +153,9 @@ impl Point<f64> for Array1<f64> {
         let mut distance = 0f64;
         for i in 0..self.len() {
             let diff = self[i] - other[i];
-            distance += diff * diff; 
+            distance += diff * diff;
         }
-        Ok(distance.sqrt())
+        Ok(distance)
     }
 
     fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
@@ -89,13 +168,324 @@ impl Point<f64> for Array1<f64> {
         plane
     }
 
+    fn coord(&self, cur_dimension: usize) -> f64 { self[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: f64) { self[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> Array1<f64> {
+        self.iter().zip(other.iter()).map(|(a, b)| (a + b) / 2f64).collect()
+    }
+
+    fn dimensions(&self) -> usize { self.len() }
+
+    fn project(&self, dims: &[usize]) -> Result<Array1<f64>, KdError> {
+        if dims.iter().any(|&dim| dim >= self.len()) { return Err(KdError::DimensionError); }
+        Ok(dims.iter().map(|&dim| self[dim]).collect())
+    }
+}
+
+impl<T: Float, const N: usize> Point<T> for [T; N] {
+    const DIMS: Option<usize> = Some(N);
+
+    fn distance(&self, other: &Self) -> Result<T, KdError> {
+        self.distance_squared(other).map(T::sqrt)
+    }
+
+    fn distance_squared(&self, other: &Self) -> Result<T, KdError> {
+        Ok(self.iter().zip(other.iter()).fold(T::zero(), |distance, (a, b)| distance + (*a - *b) * (*a - *b)))
+    }
+
+    fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+        self[cur_dimension] > other[cur_dimension]
+    }
+
+    fn split_plane(&self, cur_dimension: usize) -> [T; N] {
+        let mut plane = [T::zero(); N];
+        plane[cur_dimension] = self[cur_dimension];
+        plane
+    }
+
+    fn coord(&self, cur_dimension: usize) -> T { self[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: T) { self[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> [T; N] {
+        let mut midpoint = *self;
+        for (value, other) in midpoint.iter_mut().zip(other.iter()) {
+            *value = (*value + *other) / (T::one() + T::one());
+        }
+        midpoint
+    }
+
+    fn dimensions(&self) -> usize { N }
+}
+
+/// Points with at most CAP dimensions stored inline, which never allocate
+#[cfg(feature="arrayvec")]
+impl<T: Float, const CAP: usize> Point<T> for ArrayVec<T, CAP> {
+    fn distance(&self, other: &Self) -> Result<T, KdError> {
+        self.distance_squared(other).map(Float::sqrt)
+    }
+
+    fn distance_squared(&self, other: &Self) -> Result<T, KdError> {
+        slice_distance_squared(self, other)
+    }
+
+    fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+        self[cur_dimension] > other[cur_dimension]
+    }
+
+    fn split_plane(&self, cur_dimension: usize) -> ArrayVec<T, CAP> {
+        let mut plane: ArrayVec<T, CAP> = self.iter().map(|_| T::zero()).collect();
+        plane[cur_dimension] = self[cur_dimension];
+        plane
+    }
+
+    fn coord(&self, cur_dimension: usize) -> T { self[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: T) { self[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> ArrayVec<T, CAP> {
+        self.iter().zip(other.iter()).map(|(a, b)| (*a + *b) / (T::one() + T::one())).collect()
+    }
+
     fn dimensions(&self) -> usize { self.len() }
+
+    fn project(&self, dims: &[usize]) -> Result<ArrayVec<T, CAP>, KdError> {
+        if dims.len() > CAP || dims.iter().any(|&dim| dim >= self.len()) { return Err(KdError::DimensionError); }
+        Ok(dims.iter().map(|&dim| self[dim]).collect())
+    }
+}
+
+/// Implement `Point` for a glam vector type with x, y and z components
+#[cfg(feature="glam")]
+macro_rules! impl_glam_point {
+    ($vector:ty, $float:ty) => {
+        impl Point<$float> for $vector {
+            const DIMS: Option<usize> = Some(3);
+
+            fn distance(&self, other: &Self) -> Result<$float, KdError> {
+                Ok(<$vector>::distance(*self, *other))
+            }
+
+            fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+                self.coord(cur_dimension) > other.coord(cur_dimension)
+            }
+
+            fn split_plane(&self, cur_dimension: usize) -> $vector {
+                let mut plane = <$vector>::ZERO;
+                plane.set_coord(cur_dimension, self.coord(cur_dimension));
+                plane
+            }
+
+            fn coord(&self, cur_dimension: usize) -> $float {
+                match cur_dimension {
+                    0 => self.x,
+                    1 => self.y,
+                    2 => self.z,
+                    _ => panic!("index out of bounds: the len is 3 but the index is {}", cur_dimension),
+                }
+            }
+
+            fn set_coord(&mut self, cur_dimension: usize, value: $float) {
+                match cur_dimension {
+                    0 => self.x = value,
+                    1 => self.y = value,
+                    2 => self.z = value,
+                    _ => panic!("index out of bounds: the len is 3 but the index is {}", cur_dimension),
+                }
+            }
+
+            fn midpoint(&self, other: &Self) -> $vector {
+                (*self + *other) / 2.0
+            }
+
+            fn dimensions(&self) -> usize { 3 }
+        }
+    };
+}
+
+#[cfg(feature="glam")]
+impl_glam_point!(Vec3, f32);
+#[cfg(feature="glam")]
+impl_glam_point!(DVec3, f64);
+
+/// Complex sample stored as (re, im), nearest neighbors are by modulus of the difference
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ComplexPoint(pub (f64, f64));
+
+impl Point<f64> for ComplexPoint {
+    const DIMS: Option<usize> = Some(2);
+
+    fn distance(&self, other: &Self) -> Result<f64, KdError> {
+        let (re, im) = ((self.0).0 - (other.0).0, (self.0).1 - (other.0).1);
+        Ok(Float::hypot(re, im))
+    }
+
+    fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+        self.coord(cur_dimension) > other.coord(cur_dimension)
+    }
+
+    fn split_plane(&self, cur_dimension: usize) -> ComplexPoint {
+        match cur_dimension {
+            0 => ComplexPoint(((self.0).0, 0f64)),
+            1 => ComplexPoint((0f64, (self.0).1)),
+            _ => panic!("index out of bounds: the len is 2 but the index is {}", cur_dimension),
+        }
+    }
+
+    fn coord(&self, cur_dimension: usize) -> f64 {
+        match cur_dimension {
+            0 => (self.0).0,
+            1 => (self.0).1,
+            _ => panic!("index out of bounds: the len is 2 but the index is {}", cur_dimension),
+        }
+    }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: f64) {
+        match cur_dimension {
+            0 => (self.0).0 = value,
+            1 => (self.0).1 = value,
+            _ => panic!("index out of bounds: the len is 2 but the index is {}", cur_dimension),
+        }
+    }
+
+    fn midpoint(&self, other: &Self) -> ComplexPoint {
+        ComplexPoint((((self.0).0 + (other.0).0) / 2f64, ((self.0).1 + (other.0).1) / 2f64))
+    }
+
+    fn dimensions(&self) -> usize { 2 }
+}
+
+/// Position (x, y) at time t, nearest neighbors are by distance with time scaled by a weight
+///
+/// Distance is `sqrt(dx^2 + dy^2 + (w * dt)^2)` for the time weight `w` given when the point is
+/// created, which should be the same for all points in a tree. Splits cycle through all three
+/// axes as usual.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SpatioTemporalPoint {
+    coords: [f64; 3],
+    time_weight: f64,
+}
+
+impl SpatioTemporalPoint {
+    /// Create point at (x, y) and time t, weighting time differences by time_weight
+    pub fn new(x: f64, y: f64, t: f64, time_weight: f64) -> Self {
+        SpatioTemporalPoint { coords: [x, y, t], time_weight }
+    }
+
+    /// Coordinates of point as (x, y, t)
+    pub fn coords(&self) -> [f64; 3] { self.coords }
+
+    /// Weight of time differences in distance
+    pub fn time_weight(&self) -> f64 { self.time_weight }
+}
+
+impl Point<f64> for SpatioTemporalPoint {
+    const DIMS: Option<usize> = Some(3);
+
+    fn distance(&self, other: &Self) -> Result<f64, KdError> {
+        let (dx, dy) = (self.coords[0] - other.coords[0], self.coords[1] - other.coords[1]);
+        let dt = self.time_weight * (self.coords[2] - other.coords[2]);
+        Ok(Float::sqrt(dx * dx + dy * dy + dt * dt))
+    }
+
+    fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+        self.coords[cur_dimension] > other.coords[cur_dimension]
+    }
+
+    fn split_plane(&self, cur_dimension: usize) -> SpatioTemporalPoint {
+        let mut plane = SpatioTemporalPoint { coords: [0f64; 3], time_weight: self.time_weight };
+        plane.coords[cur_dimension] = self.coords[cur_dimension];
+        plane
+    }
+
+    fn coord(&self, cur_dimension: usize) -> f64 { self.coords[cur_dimension] }
+
+    fn set_coord(&mut self, cur_dimension: usize, value: f64) { self.coords[cur_dimension] = value; }
+
+    fn midpoint(&self, other: &Self) -> SpatioTemporalPoint {
+        let mut midpoint = *self;
+        for (value, other) in midpoint.coords.iter_mut().zip(other.coords.iter()) {
+            *value = (*value + *other) / 2f64;
+        }
+        midpoint
+    }
+
+    fn dimensions(&self) -> usize { 3 }
+
+    fn dist_to_box(&self, min: &Self, max: &Self) -> Result<f64, KdError> {
+        let gap = |i: usize| (min.coords[i] - self.coords[i]).max(self.coords[i] - max.coords[i]).max(0f64);
+        let (dx, dy, dt) = (gap(0), gap(1), self.time_weight * gap(2));
+        Ok(Float::sqrt(dx * dx + dy * dy + dt * dt))
+    }
+}
+
+/// Single value, so a tree of scalars answers nearest value queries like a balanced search tree
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Scalar(pub f64);
+
+impl Point<f64> for Scalar {
+    const DIMS: Option<usize> = Some(1);
+
+    fn distance(&self, other: &Self) -> Result<f64, KdError> {
+        Ok(Float::abs(self.0 - other.0))
+    }
+
+    fn greater(&self, other: &Self, _cur_dimension: usize) -> bool {
+        self.0 > other.0
+    }
+
+    fn split_plane(&self, _cur_dimension: usize) -> Scalar { *self }
+
+    fn coord(&self, _cur_dimension: usize) -> f64 { self.0 }
+
+    fn set_coord(&mut self, _cur_dimension: usize, value: f64) { self.0 = value; }
+
+    fn midpoint(&self, other: &Self) -> Scalar {
+        Scalar((self.0 + other.0) / 2f64)
+    }
+
+    fn dimensions(&self) -> usize { 1 }
+
+    fn dist_to_box(&self, min: &Self, max: &Self) -> Result<f64, KdError> {
+        Ok((min.0 - self.0).max(self.0 - max.0).max(0f64))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::kd_tree::KdTree;
+    use super::kd_tree::{KdTree, KdTreeBuilder, KdTreeFlat, KdTreeMap, KdError, Metric, SplitStrategy, Point, GrowthPolicy, Normalization, QueryScratch, DistanceFn, Closest, NodeInfo, NodeType, RawNode, median_split};
+    #[cfg(feature="default")]
+    use super::kd_tree::KdTreeRef;
+    use super::{ComplexPoint, Scalar, SpatioTemporalPoint};
     use std::time::{Instant};
+    use std::cell::Cell;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    /// Whether a and b differ by at most eps
+    fn approx_eq<T: num_traits::Float>(a: T, b: T, eps: T) -> bool {
+        (a - b).abs() <= eps
+    }
+
+    /// Assert tree search results have the same distances as brute force results, each within eps
+    ///
+    /// Distances are compared as multisets, so results only differing in the order or choice of
+    /// tied points, or in float rounding, still match.
+    fn assert_knn_matches<DataType, T: num_traits::Float + std::fmt::Debug>(kd_search: &[Closest<DataType, T>], brute_search: &[Closest<DataType, T>], eps: T) {
+        let sorted_distances = |results: &[Closest<DataType, T>]| {
+            let mut distances: Vec<T> = results.iter().map(|closest| closest.distance).collect();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            distances
+        };
+        let (kd_distances, brute_distances) = (sorted_distances(kd_search), sorted_distances(brute_search));
+
+        assert_eq!(kd_distances.len(), brute_distances.len());
+        assert!(kd_distances.iter().zip(brute_distances.iter()).all(|(&a, &b)| approx_eq(a, b, eps)),
+                "returned {:?}, expected {:?}", kd_distances, brute_distances);
+    }
+
     #[test]
     fn test_vecf64() {
         let mut tree = KdTree::<Vec<f64>, f64>::with_capacity(3, 1_000_000);
@@ -127,15 +517,8 @@ mod tests {
             println!("KD-Search finished in {}us", now.elapsed().as_micros());
             
             assert_eq!(search_result.is_ok(), true);
-            if let (Ok(mut kd_search), Ok(mut brute_search)) = (search_result, brute_result) {
-                for _i in 0..kd_search.len() {
-                    if let (Some(kd_closest), Some(brute_closest)) = (kd_search.pop(), brute_search.pop()) {
-                        assert!(kd_closest.distance == brute_closest.distance);
-                        assert!(kd_closest.point[0] == brute_closest.point[0]);
-                        assert!(kd_closest.point[1] == brute_closest.point[1]);
-                        assert!(kd_closest.point[2] == brute_closest.point[2]);
-                    }
-                }
+            if let (Ok(kd_search), Ok(brute_search)) = (search_result, brute_result) {
+                assert_knn_matches(&kd_search.into_sorted_vec(), &brute_search.into_sorted_vec(), 1e-12);
             }
         }
     }
@@ -171,16 +554,2850 @@ mod tests {
             println!("KD-Search finished in {}us", now.elapsed().as_micros());
             
             assert_eq!(search_result.is_ok(), true);
-            if let (Ok(mut kd_search), Ok(mut brute_search)) = (search_result, brute_result) {
-                for _i in 0..kd_search.len() {
-                    if let (Some(kd_closest), Some(brute_closest)) = (kd_search.pop(), brute_search.pop()) {
-                        assert!(kd_closest.distance == brute_closest.distance);
-                        assert!(kd_closest.point[0] == brute_closest.point[0]);
-                        assert!(kd_closest.point[1] == brute_closest.point[1]);
-                        assert!(kd_closest.point[2] == brute_closest.point[2]);
-                    }
-                }
+            if let (Ok(kd_search), Ok(brute_search)) = (search_result, brute_result) {
+                assert_knn_matches(&kd_search.into_sorted_vec(), &brute_search.into_sorted_vec(), 1e-6);
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_point_unchecked() {
+        let mut checked = KdTree::<Vec<f64>, f64>::with_capacity(3, 10_000);
+        let mut unchecked = KdTree::<Vec<f64>, f64>::with_capacity(3, 10_000);
+
+        for _i in 0..10_000 {
+            let point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            assert!(checked.add_point(point.clone()).is_ok());
+            unsafe { unchecked.add_point_unchecked(point); }
+        }
+
+        for _i in 0..100 {
+            let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            let checked_result = checked.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+            let unchecked_result = unchecked.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+
+            assert_eq!(checked_result.len(), unchecked_result.len());
+            for (a, b) in checked_result.iter().zip(unchecked_result.iter()) {
+                assert!(a.distance == b.distance);
+                assert!(a.point == b.point);
+            }
+        }
+    }
+
+    #[test]
+    fn test_build_from_vec() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points).unwrap();
+
+        for _i in 0..100 {
+            let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            let kd_search = tree.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+            let brute_search = tree.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-12);
+        }
+
+        let bad_points = vec![vec![0f64, 0f64, 0f64], vec![0f64, 0f64]];
+        assert!(KdTree::<Vec<f64>, f64>::build_from_vec(3, bad_points).err() == Some(KdError::DimensionError));
+    }
+
+    #[cfg(feature="rayon")]
+    #[test]
+    fn test_par_extend() {
+        let first: Vec<Vec<f64>> = (0..5_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let second: Vec<Vec<f64>> = (0..5_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+
+        let mut serial = KdTree::<Vec<f64>, f64>::new(3);
+        serial.extend(first.clone()).unwrap();
+        serial.extend(second.clone()).unwrap();
+
+        let mut parallel = KdTree::<Vec<f64>, f64>::new(3);
+        parallel.extend(first).unwrap();
+        parallel.par_extend(second).unwrap();
+
+        for _i in 0..100 {
+            let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            let serial_result = serial.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+            let parallel_result = parallel.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+
+            assert_eq!(serial_result.len(), parallel_result.len());
+            for (a, b) in serial_result.iter().zip(parallel_result.iter()) {
+                assert!(a.distance == b.distance);
+                assert!(a.point == b.point);
             }
         }
     }
+
+    #[test]
+    fn test_cosine_validation() {
+        let mut tree = KdTree::<Vec<f64>, f64>::with_metric_validated(3, Metric::Cosine);
+        assert!(tree.add_point(vec![1f64, 0f64, 0f64]).is_ok());
+        assert!(tree.add_point(vec![0f64, 1f64, 0f64]).is_ok());
+        assert!(tree.add_point(vec![2f64, 0f64, 0f64]).err() == Some(KdError::InvalidInput));
+
+        // Cosine distance is one minus the cosine of the angle between points
+        let (point, distance) = tree.find_closest(&vec![0f64, 0.6f64, 0.8f64]).unwrap();
+        assert!(point == vec![0f64, 1f64, 0f64]);
+        assert!((distance - 0.4f64).abs() < 1e-12);
+
+        assert!(tree.find_closest(&vec![0f64, 3f64, 4f64]).err() == Some(KdError::InvalidInput));
+
+        // Unvalidated trees don't check inputs
+        let mut tree = KdTree::<Vec<f64>, f64>::with_metric(3, Metric::Cosine);
+        assert!(tree.add_point(vec![2f64, 0f64, 0f64]).is_ok());
+    }
+
+    #[test]
+    fn test_nearest_on_axis() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+
+        for axis in 0..3 {
+            for _i in 0..100 {
+                let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+                let (_, distance) = tree.nearest_on_axis(&query_point, axis).unwrap();
+                let linear = points.iter().map(|point| (point[axis] - query_point[axis]).abs()).fold(f64::INFINITY, f64::min);
+                assert!(distance == linear);
+            }
+        }
+
+        assert!(tree.nearest_on_axis(&vec![0f64, 0f64, 0f64], 3).err() == Some(KdError::DimensionError));
+        assert!(tree.nearest_on_axis(&vec![0f64, 0f64], 1).err() == Some(KdError::DimensionError));
+        assert!(tree.nearest_on_axis(&vec![0f64, 0f64, 0f64, 0f64], 1).err() == Some(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_default() {
+        let mut tree = KdTree::<Vec<f64>, f64>::default();
+        assert_eq!(tree.get_num_dimensions(), 0);
+
+        assert!(tree.add_point(vec![1f64, 2f64]).is_ok());
+        assert_eq!(tree.get_num_dimensions(), 2);
+        assert!(tree.add_point(vec![3f64, 4f64]).is_ok());
+        assert!(tree.add_point(vec![1f64, 2f64, 3f64]).err() == Some(KdError::DimensionError));
+
+        let (point, _) = tree.find_closest(&vec![2.9f64, 4.1f64]).unwrap();
+        assert!(point == vec![3f64, 4f64]);
+    }
+
+    #[test]
+    fn test_infer_dimensions() {
+        let mut tree = KdTree::<Vec<f64>, f64>::with_capacity(0, 10);
+        assert!(tree.add_point(vec![1f64, 2f64, 3f64]).is_ok());
+        assert_eq!(tree.get_num_dimensions(), 3);
+        assert!(tree.add_point(vec![1f64, 2f64]).err() == Some(KdError::DimensionError));
+        assert!(tree.add_point(vec![4f64, 5f64, 6f64]).is_ok());
+
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(0, vec![vec![1f64, 2f64], vec![3f64, 4f64]]).unwrap();
+        assert_eq!(tree.get_num_dimensions(), 2);
+
+        let bad_points = vec![vec![1f64, 2f64], vec![3f64, 4f64, 5f64]];
+        assert!(KdTree::<Vec<f64>, f64>::build_from_vec(0, bad_points).err() == Some(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_find_n_closest_heap() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points).unwrap();
+        let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+
+        let mut heap = tree.find_n_closest_heap(&query_point, 20).unwrap();
+        let mut last_distance = f64::INFINITY;
+        let mut popped = Vec::new();
+        while let Some(closest) = heap.pop() {
+            assert!(closest.distance <= last_distance);
+            last_distance = closest.distance;
+            popped.push(closest.distance);
+        }
+        assert_eq!(popped.len(), 20);
+
+        let sorted = tree.k_nearest(&query_point, 20).unwrap();
+        popped.reverse();
+        for (closest, distance) in sorted.iter().zip(popped.iter()) {
+            assert!(closest.distance == *distance);
+        }
+    }
+
+    #[test]
+    fn test_active_dims() {
+        let mut tree = KdTree::<Vec<f64>, f64>::with_active_dims(3, vec![true, false, true]).unwrap();
+        let mut projected = KdTree::<Vec<f64>, f64>::new(2);
+        for _i in 0..10_000 {
+            let point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            projected.add_point(vec![point[0], point[2]]).unwrap();
+            tree.add_point(point).unwrap();
+        }
+
+        for _i in 0..100 {
+            let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            let masked_result = tree.k_nearest(&query_point, 10).unwrap();
+            let projected_result = projected.k_nearest(&vec![query_point[0], query_point[2]], 10).unwrap();
+
+            assert_eq!(masked_result.len(), projected_result.len());
+            for (masked, projected) in masked_result.iter().zip(projected_result.iter()) {
+                assert!(masked.distance == projected.distance);
+                assert!(masked.point[0] == projected.point[0]);
+                assert!(masked.point[2] == projected.point[1]);
+            }
+        }
+
+        assert!(KdTree::<Vec<f64>, f64>::with_active_dims(3, vec![false, false, false]).err() == Some(KdError::DimensionError));
+        assert!(KdTree::<Vec<f64>, f64>::with_active_dims(3, vec![true, true]).err() == Some(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_knn_graph() {
+        let points: Vec<Vec<f64>> = (0..1_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+
+        let graph = tree.knn_graph(5).unwrap();
+        assert_eq!(graph.len(), points.len());
+        for (i, neighbors) in graph.iter().enumerate() {
+            assert_eq!(neighbors.len(), 5);
+            assert!(neighbors.iter().all(|neighbor| neighbor.point != i));
+
+            // Brute force over every other point
+            let mut expected: Vec<f64> = points.iter().enumerate()
+                                               .filter(|(j, _)| *j != i)
+                                               .map(|(_, point)| ((point[0] - points[i][0]).powi(2) + (point[1] - points[i][1]).powi(2)).sqrt())
+                                               .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            for (neighbor, distance) in neighbors.iter().zip(expected.iter()) {
+                assert!((neighbor.distance - distance).abs() < 1e-12);
+                assert!(tree.brute_force(&points[neighbor.point], 1).unwrap().pop().unwrap().distance == 0f64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder_from_vec() {
+        let points: Vec<Vec<f64>> = (0..5_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let added: Vec<Vec<f64>> = (0..500).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let trees = vec![
+            KdTree::<Vec<f64>, f64>::builder().bucket_size(16).build_from_vec(points.clone()).unwrap(),
+            KdTree::<Vec<f64>, f64>::builder().split_strategy(SplitStrategy::Order(vec![2, 0, 2, 1])).build_from_vec(points).unwrap(),
+        ];
+
+        for mut tree in trees {
+            // Points added to a built tree split the buckets they fall in
+            for round in 0..2 {
+                if round == 1 { tree.extend(added.clone()).unwrap(); }
+
+                for _i in 0..100 {
+                    let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+                    let kd_search = tree.k_nearest(&query_point, 10).unwrap();
+                    let brute_search = tree.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+
+                    assert_eq!(kd_search.len(), brute_search.len());
+                    for (kd_closest, brute_closest) in kd_search.iter().zip(brute_search.iter()) {
+                        assert!(kd_closest.distance == brute_closest.distance);
+                        assert!(kd_closest.point == brute_closest.point);
+                    }
+                }
+            }
+        }
+
+        assert!(KdTree::<Vec<f64>, f64>::builder().bucket_size(0).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).split_strategy(SplitStrategy::Order(vec![])).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).split_strategy(SplitStrategy::Order(vec![0, 2])).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).active_dims(vec![false, true]).split_strategy(SplitStrategy::Order(vec![0])).build().err() == Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(3).capacity(10).build().unwrap();
+        assert!(tree.add_point(vec![1f64, 2f64, 3f64]).is_ok());
+        assert_eq!(tree.get_num_dimensions(), 3);
+
+        let mut tree = KdTreeBuilder::<Vec<f64>, f64>::new().capacity(0).build().unwrap();
+        assert!(tree.add_point(vec![1f64, 2f64]).is_ok());
+        assert_eq!(tree.get_num_dimensions(), 2);
+
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).metric(Metric::Cosine).validate_inputs(true).build().unwrap();
+        assert!(tree.add_point(vec![2f64, 0f64]).err() == Some(KdError::InvalidInput));
+
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).active_dims(vec![false, true]).build().unwrap();
+        tree.add_point(vec![0f64, 0f64]).unwrap();
+        tree.add_point(vec![5f64, 1f64]).unwrap();
+        let (point, distance) = tree.find_closest(&vec![10f64, 0.1f64]).unwrap();
+        assert!(point == vec![0f64, 0f64]);
+        assert!((distance - 0.1f64).abs() < 1e-12);
+
+        // Weighted axes count more, so the closest point is the one nearer along lighter axes
+        let points = vec![vec![1f64, 0f64], vec![0f64, 1.5f64]];
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).weights(vec![4.0, 1.0]).build().unwrap();
+        tree.extend(points.clone()).unwrap();
+        let (point, distance) = tree.find_closest(&vec![0f64, 0f64]).unwrap();
+        assert!(point == points[1] && (distance - 1.5).abs() < 1e-12);
+        let tree = KdTree::<Vec<f64>, f64>::builder().weights(vec![4.0, 1.0]).build_from_vec(points).unwrap();
+        assert!(tree.find_closest(&vec![0.3f64, 0.5f64]).unwrap().0 == vec![0f64, 1.5f64]);
+
+        // Incompatible options
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(3).active_dims(vec![true, false]).build().err() == Some(KdError::DimensionError));
+        assert!(KdTree::<Vec<f64>, f64>::builder().active_dims(vec![true, false]).build().err() == Some(KdError::DimensionError));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).active_dims(vec![true, false]).metric(Metric::Cosine).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(3).weights(vec![1.0, 2.0]).build().err() == Some(KdError::DimensionError));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).weights(vec![1.0, 0.0]).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).weights(vec![1.0, f64::NAN]).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().dimensions(2).weights(vec![1.0, 2.0]).metric(Metric::Cosine).build().err() == Some(KdError::InvalidConfiguration));
+    }
+
+    thread_local! {
+        static COMPARISONS: Cell<usize> = const { Cell::new(0) };
+        static DISTANCES: Cell<usize> = const { Cell::new(0) };
+        static PLANE_DISTANCES: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Point that counts how often the search compares and measures it
+    #[derive(Clone, PartialEq)]
+    struct CountingPoint {
+        coords: Vec<f64>,
+        plane: bool,
+    }
+
+    impl Point<f64> for CountingPoint {
+        fn distance(&self, other: &Self) -> Result<f64, KdError> {
+            if self.plane {
+                PLANE_DISTANCES.with(|count| count.set(count.get() + 1));
+            } else {
+                DISTANCES.with(|count| count.set(count.get() + 1));
+            }
+            self.coords.distance(&other.coords)
+        }
+
+        fn greater(&self, other: &Self, cur_dimension: usize) -> bool {
+            COMPARISONS.with(|count| count.set(count.get() + 1));
+            self.coords.greater(&other.coords, cur_dimension)
+        }
+
+        fn split_plane(&self, cur_dimension: usize) -> Self {
+            CountingPoint { coords: self.coords.split_plane(cur_dimension), plane: true }
+        }
+
+        fn coord(&self, cur_dimension: usize) -> f64 { self.coords[cur_dimension] }
+
+        fn set_coord(&mut self, cur_dimension: usize, value: f64) { self.coords[cur_dimension] = value; }
+
+        fn midpoint(&self, other: &Self) -> Self {
+            CountingPoint { coords: self.coords.midpoint(&other.coords), plane: false }
+        }
+
+        fn dimensions(&self) -> usize { self.coords.len() }
+    }
+
+    #[test]
+    fn test_search_path() {
+        let points: Vec<CountingPoint> = (0..10_000).map(|_| CountingPoint { coords: vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()], plane: false }).collect();
+        let tree = KdTree::<CountingPoint, f64>::build_from_vec(3, points).unwrap();
+
+        for _i in 0..100 {
+            let query_point = CountingPoint { coords: vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()], plane: false };
+            COMPARISONS.with(|count| count.set(0));
+            DISTANCES.with(|count| count.set(0));
+            let kd_search = tree.k_nearest(&query_point, 10).unwrap();
+            let comparisons = COMPARISONS.with(|count| count.get());
+            let distances = DISTANCES.with(|count| count.get());
+
+            // Each visited node is compared once going down and checked once coming back up
+            assert_eq!(comparisons, distances);
+            assert!(distances < 1_000);
+
+            let brute_search = tree.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_complex_point() {
+        let mut tree = KdTree::<ComplexPoint, f64>::new(2);
+        for _i in 0..10_000 {
+            tree.add_point(ComplexPoint((rand::random::<f64>() - 0.5f64, rand::random::<f64>() - 0.5f64))).unwrap();
+        }
+
+        for _i in 0..100 {
+            let query_point = ComplexPoint((rand::random::<f64>() - 0.5f64, rand::random::<f64>() - 0.5f64));
+            let kd_search = tree.k_nearest(&query_point, 5).unwrap();
+            let brute_search = tree.brute_force(&query_point, 5).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-12);
+        }
+
+        assert!((ComplexPoint((0f64, 0f64)).distance(&ComplexPoint((3f64, -4f64))).unwrap() - 5f64).abs() < 1e-12);
+
+        // Dimensions past im panic instead of aliasing it
+        let mut point = ComplexPoint((1f64, 2f64));
+        point.set_coord(1, 3f64);
+        assert_eq!((point.coord(0), point.coord(1)), (1f64, 3f64));
+        assert_eq!(point.split_plane(1), ComplexPoint((0f64, 3f64)));
+        assert!(std::panic::catch_unwind(|| point.coord(5)).is_err());
+        assert!(std::panic::catch_unwind(|| point.split_plane(2)).is_err());
+        assert!(std::panic::catch_unwind(move || point.set_coord(2, 0f64)).is_err());
+    }
+
+    #[test]
+    fn test_count_within_radius() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+
+        for _i in 0..100 {
+            let query_point = vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            let expected = points.iter().filter(|point| point.distance(&query_point).unwrap() <= 0.1f64).count();
+            assert_eq!(tree.count_within_radius(&query_point, 0.1f64).unwrap(), expected);
+        }
+    }
+
+    #[cfg(feature="rayon")]
+    #[test]
+    fn test_par_query_map() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points).unwrap();
+        let queries: Vec<Vec<f64>> = (0..1_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+
+        let results = tree.par_query_map(&queries, |tree, query| {
+            let (_, distance) = tree.find_closest(query).unwrap();
+            (distance, tree.count_within_radius(query, 0.1f64).unwrap())
+        });
+
+        assert_eq!(results.len(), queries.len());
+        for (query, (distance, count)) in queries.iter().zip(results.iter()) {
+            assert!(tree.find_closest(query).unwrap().1 == *distance);
+            assert_eq!(tree.count_within_radius(query, 0.1f64).unwrap(), *count);
+        }
+    }
+
+    #[test]
+    fn test_search_stats() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let balanced = KdTree::<Vec<f64>, f64>::build_from_vec(2, points).unwrap();
+        let query_point = vec![rand::random::<f64>(), rand::random::<f64>()];
+        let (closest, stats) = balanced.find_n_closest_instrumented(&query_point, 5).unwrap();
+        assert_eq!(closest.len(), 5);
+        assert!(stats.nodes_visited < balanced.len() / 10);
+        assert!(stats.subtrees_pruned > 0);
+        assert!(stats.max_depth_reached <= 14);
+
+        // Inserting sorted points builds a single chain, which a query past the end walks entirely
+        let mut degenerate = KdTree::<Vec<f64>, f64>::new(2);
+        for i in 0..1_000 {
+            degenerate.add_point(vec![i as f64, i as f64]).unwrap();
+        }
+        let (_, stats) = degenerate.find_n_closest_instrumented(&vec![1_000f64, 1_000f64], 5).unwrap();
+        assert!(stats.nodes_visited > degenerate.len() * 9 / 10);
+        assert_eq!(stats.max_depth_reached, 999);
+    }
+
+    #[test]
+    fn test_find_closest_slice() {
+        let points: Vec<Vec<f64>> = (0..10_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points).unwrap();
+
+        for _i in 0..100 {
+            let coords = [rand::random::<f64>(), rand::random::<f64>(), rand::random::<f64>()];
+            let slice_search = tree.find_closest_slice(&coords, 10).unwrap().into_sorted_vec();
+            let vec_search = tree.find_n_closest(&coords.to_vec(), 10).unwrap().into_sorted_vec();
+
+            assert_eq!(slice_search.len(), vec_search.len());
+            for (slice_closest, vec_closest) in slice_search.iter().zip(vec_search.iter()) {
+                assert!(slice_closest.distance == vec_closest.distance);
+                assert!(slice_closest.point == vec_closest.point);
+            }
+        }
+
+        assert!(tree.find_closest_slice(&[0f64, 0f64], 1).err() == Some(KdError::DimensionError));
+
+        // Slices are validated like points
+        assert!(tree.find_closest_slice(&[0f64, f64::NAN, 0f64], 1).err() == Some(KdError::NonFiniteValue { dimension: 1 }));
+        let mut cosine = KdTree::<Vec<f64>, f64>::with_metric_validated(3, Metric::Cosine);
+        cosine.add_point(vec![1f64, 0f64, 0f64]).unwrap();
+        assert!(cosine.find_closest_slice(&[2f64, 0f64, 0f64], 1).err() == Some(KdError::InvalidInput));
+        assert!(cosine.find_closest_slice(&[0f64, 1f64, 0f64], 1).is_ok());
+    }
+
+    #[test]
+    fn test_remove_within_radius() {
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        for _i in 0..1_000 {
+            tree.add_point(vec![rand::random::<f64>(), rand::random::<f64>()]).unwrap();
+        }
+        // Cluster around a point far from the rest
+        for _i in 0..100 {
+            tree.add_point(vec![10f64 + rand::random::<f64>() * 0.1f64, 10f64 + rand::random::<f64>() * 0.1f64]).unwrap();
+        }
+
+        let center = vec![10.05f64, 10.05f64];
+        assert_eq!(tree.remove_within_radius(&center, 1f64).unwrap(), 100);
+        assert_eq!(tree.len(), 1_000);
+        assert_eq!(tree.count_within_radius(&center, 1f64).unwrap(), 0);
+
+        let (point, _) = tree.find_closest(&center).unwrap();
+        assert!(point[0] < 1f64 && point[1] < 1f64);
+        let brute_search = tree.brute_force(&center, 10).unwrap().into_sorted_vec();
+        assert_knn_matches(&tree.k_nearest(&center, 10).unwrap(), &brute_search, 1e-12);
+
+        // Removing most points rebuilds the tree
+        assert!(tree.remove_within_radius(&vec![0f64, 0f64], 1.2f64).unwrap() > 500);
+        assert_eq!(tree.count_within_radius(&vec![0f64, 0f64], 1.2f64).unwrap(), 0);
+        let remaining = tree.len();
+        assert_eq!(tree.remove_within_radius(&vec![0f64, 0f64], 100f64).unwrap(), remaining);
+        assert!(tree.is_empty());
+        assert!(tree.find_closest(&vec![0f64, 0f64]).err() == Some(KdError::EmptyTree));
+    }
+
+    #[test]
+    fn test_to_vec() {
+        let points: Vec<Vec<f64>> = (0..1_000).map(|_| vec![rand::random::<f64>(), rand::random::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+
+        // Points added one at a time are stored in insertion order
+        let all_points = tree.to_vec();
+        assert_eq!(all_points.len(), tree.len());
+        assert!(all_points == points);
+
+        tree.add_point(vec![5f64, 5f64]).unwrap();
+        tree.remove_within_radius(&vec![5f64, 5f64], 0.5f64).unwrap();
+        let all_points = tree.to_vec();
+        assert_eq!(all_points.len(), tree.len());
+        assert!(all_points == points);
+    }
+
+    #[test]
+    fn test_growth_policy() {
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).capacity(10).growth_policy(GrowthPolicy::Exact).build().unwrap();
+        assert_eq!(tree.capacity(), 10);
+        for i in 0..15 {
+            tree.add_point(vec![i as f64, rand::random::<f64>()]).unwrap();
+        }
+        assert_eq!(tree.capacity(), 15);
+
+        tree.reserve_exact(5);
+        assert_eq!(tree.capacity(), 20);
+        for i in 0..5 {
+            tree.add_point(vec![i as f64, rand::random::<f64>()]).unwrap();
+        }
+        assert_eq!(tree.capacity(), 20);
+
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).capacity(10).growth_policy(GrowthPolicy::Fixed(8)).build().unwrap();
+        for i in 0..11 {
+            tree.add_point(vec![i as f64, rand::random::<f64>()]).unwrap();
+        }
+        assert_eq!(tree.capacity(), 18);
+
+        let mut tree = KdTree::<Vec<f64>, f64>::with_capacity(2, 10);
+        for i in 0..11 {
+            tree.add_point(vec![i as f64, rand::random::<f64>()]).unwrap();
+        }
+        assert!(tree.capacity() >= 20);
+        assert_eq!(tree.len(), 11);
+    }
+
+    #[test]
+    fn test_n_closest_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(125);
+
+        for trial in 0..300 {
+            let dimensions = rng.gen_range(1, 5);
+            let num_points = rng.gen_range(1, 300);
+            // Coarse coordinates create many ties and points lying on split planes
+            let coarse = trial % 2 == 0;
+            let random_point = |rng: &mut StdRng| -> Vec<f64> {
+                (0..dimensions).map(|_| if coarse { rng.gen_range(0, 5) as f64 } else { rng.gen::<f64>() }).collect()
+            };
+
+            let points: Vec<Vec<f64>> = (0..num_points).map(|_| random_point(&mut rng)).collect();
+            let tree = if trial % 3 == 0 {
+                KdTree::<Vec<f64>, f64>::build_from_vec(dimensions, points.clone()).unwrap()
+            } else {
+                let mut tree = KdTree::<Vec<f64>, f64>::new(dimensions);
+                tree.extend(points.clone()).unwrap();
+                tree
+            };
+
+            for _i in 0..10 {
+                let query_point = random_point(&mut rng);
+                let n = rng.gen_range(1, num_points + 5);
+                let kd_distances: Vec<f64> = tree.k_nearest(&query_point, n).unwrap().iter().map(|closest| closest.distance).collect();
+
+                let mut brute_distances: Vec<f64> = points.iter().map(|point| point.distance(&query_point).unwrap()).collect();
+                brute_distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                brute_distances.truncate(n);
+
+                assert_eq!(kd_distances.len(), n.min(num_points));
+                assert!(kd_distances.iter().zip(brute_distances.iter()).all(|(&a, &b)| approx_eq(a, b, 1e-12)),
+                        "trial {} returned {:?}, expected {:?}", trial, kd_distances, brute_distances);
+            }
+        }
+    }
+
+    #[test]
+    fn test_normalization() {
+        let mut rng = StdRng::seed_from_u64(126);
+        // Second axis spans a range 1000 times smaller than the first
+        let random_point = |rng: &mut StdRng| -> Vec<f64> { vec![rng.gen::<f64>() * 1000.0, rng.gen::<f64>()] };
+        let points: Vec<Vec<f64>> = (0..2000).map(|_| random_point(&mut rng)).collect();
+        let raw = KdTree::<Vec<f64>, f64>::build_from_vec(2, points.clone()).unwrap();
+        let normalized = KdTree::<Vec<f64>, f64>::builder().normalization(Normalization::MinMax).build_from_vec(points.clone()).unwrap();
+
+        // Distance with both axes scaled by their range in the data
+        let range = |i: usize| {
+            let values = points.iter().map(|point| point[i]);
+            values.clone().fold(f64::NEG_INFINITY, f64::max) - values.fold(f64::INFINITY, f64::min)
+        };
+        let (range_x, range_y) = (range(0), range(1));
+        let scaled_distance = |a: &Vec<f64>, b: &Vec<f64>| (((a[0] - b[0]) / range_x).powi(2) + ((a[1] - b[1]) / range_y).powi(2)).sqrt();
+
+        let (mut raw_total, mut normalized_total) = (0.0, 0.0);
+        for _i in 0..200 {
+            let query_point = random_point(&mut rng);
+            let best = points.iter().map(|point| scaled_distance(point, &query_point)).fold(f64::INFINITY, f64::min);
+
+            // Points come back in original coordinates with distances in normalized space
+            let (point, distance) = normalized.find_closest(&query_point).unwrap();
+            assert!(points.contains(&point));
+            assert!((distance - best).abs() < 1e-12);
+            assert!((scaled_distance(&point, &query_point) - distance).abs() < 1e-12);
+            normalized_total += distance;
+
+            let (point, _) = raw.find_closest(&query_point).unwrap();
+            raw_total += scaled_distance(&point, &query_point);
+        }
+        // Raw neighbors mostly ignore the small axis
+        assert!(normalized_total * 2.0 < raw_total);
+
+        assert_eq!(KdTree::<Vec<f64>, f64>::builder().normalization(Normalization::ZScore).build().err(), Some(KdError::InvalidConfiguration));
+        assert_eq!(KdTree::<Vec<f64>, f64>::builder().normalization(Normalization::ZScore).metric(Metric::Cosine).build_from_vec(points.clone()).err(),
+                   Some(KdError::InvalidConfiguration));
+        assert_eq!(KdTree::<Vec<f64>, f64>::builder().normalization(Normalization::MinMax).weights(vec![1.0, 2.0]).build_from_vec(points).err(),
+                   Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_all_pairs_within() {
+        let mut rng = StdRng::seed_from_u64(127);
+        let points: Vec<Vec<f64>> = (0..500).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+
+        let radius = 0.05;
+        let mut brute_pairs = Vec::new();
+        for i in 0..points.len() {
+            for j in (i + 1)..points.len() {
+                let distance = points[i].distance(&points[j]).unwrap();
+                if distance <= radius { brute_pairs.push((i, j, distance)); }
+            }
+        }
+
+        let pairs = tree.all_pairs_within(radius).unwrap();
+        assert!(!pairs.is_empty());
+        assert_eq!(pairs, brute_pairs);
+
+        #[cfg(feature="rayon")]
+        assert_eq!(tree.par_all_pairs_within(radius).unwrap(), brute_pairs);
+    }
+
+    #[test]
+    fn test_find_n_closest_into() {
+        let mut rng = StdRng::seed_from_u64(128);
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend((0..10000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()])).unwrap();
+
+        let mut scratch = QueryScratch::new();
+        let mut out = Vec::new();
+        for i in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            // Vary n so buffers are reused with both more and fewer results than before
+            let n = 1 + (i * 7) % 20;
+            tree.find_n_closest_into(&query_point, n, &mut scratch, &mut out).unwrap();
+
+            let expected = tree.k_nearest(&query_point, n).unwrap();
+            assert_eq!(out.len(), n);
+            for (closest, expected) in out.iter().zip(expected.iter()) {
+                assert_eq!(closest.point, expected.point);
+                assert_eq!(closest.distance, expected.distance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_midpoint() {
+        assert_eq!(vec![0f64, 2f64, -4f64].midpoint(&vec![1f64, 4f64, 4f64]), vec![0.5f64, 3f64, 0f64]);
+        assert_eq!(vec![1f32, 1f32].midpoint(&vec![2f32, -1f32]), vec![1.5f32, 0f32]);
+        assert_eq!(ComplexPoint((1.0, -2.0)).midpoint(&ComplexPoint((3.0, 2.0))), ComplexPoint((2.0, 0.0)));
+
+        // Odd counts split on the median point, even counts halfway between the middle two
+        let points = vec![vec![5.0, 0.0], vec![1.0, 1.0], vec![3.0, 2.0], vec![9.0, 3.0]];
+        assert_eq!(median_split(&points, 0), Some(vec![4.0, 1.0]));
+        assert_eq!(median_split(&points[..3], 0), Some(vec![3.0, 2.0]));
+        assert_eq!(median_split(&points, 1), Some(vec![2.0, 1.5]));
+        assert_eq!(median_split::<Vec<f64>, f64>(&[], 0), None);
+    }
+
+    #[test]
+    fn test_would_affect_knn() {
+        let mut rng = StdRng::seed_from_u64(131);
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend((0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()])).unwrap();
+
+        let query_point = vec![0.5, 0.5];
+        let k = 50;
+        let mut affected = 0;
+        for _i in 0..200 {
+            let worst = tree.k_nearest(&query_point, k).unwrap().last().unwrap().distance;
+            let new_point = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let affects = tree.would_affect_knn(&query_point, worst, &new_point);
+            if affects { affected += 1; }
+
+            // Inserting point changes neighbors exactly when it was predicted to
+            let mut updated = tree.k_nearest(&query_point, k).unwrap();
+            tree.add_point(new_point.clone()).unwrap();
+            let after = tree.k_nearest(&query_point, k).unwrap();
+            assert_eq!(affects, after.iter().any(|closest| closest.point == new_point));
+            if !affects {
+                assert!(updated.drain(..).zip(after.iter()).all(|(before, after)| before.point == after.point));
+            }
+        }
+        assert!(affected > 0);
+
+        assert!(!tree.would_affect_knn(&query_point, 1.0, &vec![0.5, 0.5, 0.5]));
+    }
+
+    #[test]
+    fn test_find_closest_into_slice() {
+        let mut rng = StdRng::seed_from_u64(132);
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend((0..10000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()])).unwrap();
+
+        let mut out_coords = [0f64; 3];
+        let mut out_dist = 0f64;
+        for _i in 0..100 {
+            let query = [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            tree.find_closest_into_slice(&query, &mut out_coords, &mut out_dist).unwrap();
+
+            let (point, distance) = tree.find_closest(&query.to_vec()).unwrap();
+            assert_eq!(out_coords.to_vec(), point);
+            assert_eq!(out_dist, distance);
+        }
+
+        assert_eq!(tree.find_closest_into_slice(&[0.5, 0.5], &mut out_coords, &mut out_dist), Err(KdError::DimensionError));
+        assert_eq!(tree.find_closest_into_slice(&[0.5, 0.5, 0.5], &mut [0f64; 2], &mut out_dist), Err(KdError::DimensionError));
+    }
+
+    /// Euclidean distance with each axis scaled by a weight
+    struct ScaledEuclidean(Vec<f64>);
+
+    impl DistanceFn<f64> for ScaledEuclidean {
+        fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+            a.iter().zip(b.iter()).zip(self.0.iter()).map(|((a, b), weight)| (weight * (a - b)).powi(2)).sum::<f64>().sqrt()
+        }
+
+        fn axis_lower_bound(&self, a: f64, b: f64, dim: usize) -> f64 {
+            self.0[dim] * (a - b).abs()
+        }
+    }
+
+    #[test]
+    fn test_distance_fn() {
+        let mut rng = StdRng::seed_from_u64(133);
+        let weights = vec![1.0, 4.0, 0.25];
+        let points: Vec<Vec<f64>> = (0..10000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(3).distance_fn(Box::new(ScaledEuclidean(weights.clone()))).build().unwrap();
+        tree.extend(points.clone()).unwrap();
+
+        let metric = ScaledEuclidean(weights);
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let kd_distances: Vec<f64> = tree.k_nearest(&query_point, 10).unwrap().iter().map(|closest| closest.distance).collect();
+
+            let mut brute_distances: Vec<f64> = points.iter().map(|point| metric.distance(point, &query_point)).collect();
+            brute_distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            brute_distances.truncate(10);
+            assert_eq!(kd_distances, brute_distances);
+
+            let slice_distances: Vec<f64> = tree.find_closest_slice(&query_point, 10).unwrap().into_sorted_vec().iter().map(|closest| closest.distance).collect();
+            assert_eq!(slice_distances, brute_distances);
+        }
+
+        let invalid = KdTree::<Vec<f64>, f64>::builder().dimensions(3).metric(Metric::Cosine).distance_fn(Box::new(metric)).build();
+        assert_eq!(invalid.err(), Some(KdError::InvalidConfiguration));
+        let invalid = KdTree::<Vec<f64>, f64>::builder().dimensions(3).weights(vec![1.0; 3]).distance_fn(Box::new(ScaledEuclidean(vec![1.0; 3]))).build();
+        assert_eq!(invalid.err(), Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_sorted_by_distance() {
+        let mut rng = StdRng::seed_from_u64(134);
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend((0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()])).unwrap();
+        tree.remove_within_radius(&vec![0.5, 0.5], 0.1).unwrap();
+
+        let query_point = vec![0.25, 0.75];
+        let sorted = tree.sorted_by_distance(&query_point).unwrap();
+        assert_eq!(sorted.len(), tree.len());
+        assert!(sorted.windows(2).all(|pair| pair[0].distance <= pair[1].distance));
+
+        let (point, distance) = tree.find_closest(&query_point).unwrap();
+        assert_eq!(sorted[0].point, point);
+        assert_eq!(sorted[0].distance, distance);
+
+        assert!(KdTree::<Vec<f64>, f64>::new(2).sorted_by_distance(&query_point).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_non_finite_value() {
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.add_point(vec![0.0, 1.0, 2.0]).unwrap();
+        assert_eq!(tree.add_point(vec![0.0, 1.0, f64::NAN]), Err(KdError::NonFiniteValue { dimension: 2 }));
+        assert_eq!(tree.add_point(vec![f64::INFINITY, 1.0, 2.0]), Err(KdError::NonFiniteValue { dimension: 0 }));
+        assert_eq!(tree.len(), 1);
+
+        let points = vec![vec![0.0, 1.0, 2.0], vec![0.0, f64::NAN, 2.0]];
+        assert_eq!(KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).err(), Some(KdError::NonFiniteValue { dimension: 1 }));
+        assert_eq!(KdTree::<Vec<f64>, f64>::builder().build_from_vec(points.clone()).err(), Some(KdError::NonFiniteValue { dimension: 1 }));
+        #[cfg(feature="rayon")]
+        assert_eq!(KdTree::<Vec<f64>, f64>::par_build_from_vec(3, points).err(), Some(KdError::NonFiniteValue { dimension: 1 }));
+
+        assert!(!vec![1f32, f32::NEG_INFINITY].is_finite());
+        assert!(ComplexPoint((1.0, 2.0)).is_finite());
+    }
+
+    #[test]
+    fn test_tombstone_ratio() {
+        let mut rng = StdRng::seed_from_u64(136);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+        assert_eq!(tree.tombstone_ratio(), 0.0);
+
+        for point in points.iter().step_by(2) {
+            assert!(tree.remove_point(point).unwrap());
+        }
+        assert!(!tree.remove_point(&points[0]).unwrap());
+        assert_eq!(tree.num_tombstones(), 500);
+        assert_eq!(tree.tombstone_ratio(), 0.5);
+        assert_eq!(tree.len(), 500);
+        assert_eq!(tree.to_vec(), points.iter().skip(1).step_by(2).cloned().collect::<Vec<_>>());
+
+        // Crossing half rebuilds the tree without removed nodes
+        assert!(tree.remove_point(&points[1]).unwrap());
+        assert_eq!(tree.num_tombstones(), 0);
+        assert_eq!(tree.len(), 499);
+
+        assert_eq!(KdTree::<Vec<f64>, f64>::new(2).tombstone_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_build_index() {
+        let mut rng = StdRng::seed_from_u64(138);
+        // Coarse coordinates put many points on split planes
+        let points: Vec<Vec<f64>> = (0..5000).map(|_| vec![rng.gen_range(0, 20) as f64, rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let fetch = |row: usize| points[row].clone();
+        let index = KdTree::<Vec<f64>, f64>::builder().build_index(points.len(), fetch).unwrap();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        assert_eq!(index.len(), points.len());
+        assert_eq!(index.get_num_dimensions(), 3);
+
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>() * 20.0, rng.gen::<f64>(), rng.gen::<f64>()];
+            let from_index = index.find_n_closest(&query_point, 10, fetch).unwrap();
+            let from_tree = tree.k_nearest(&query_point, 10).unwrap();
+            for (row, closest) in from_index.iter().zip(from_tree.iter()) {
+                assert_eq!(row.distance, closest.distance);
+                assert_eq!(points[row.point].distance(&query_point).unwrap(), row.distance);
+            }
+        }
+
+        assert_eq!(index.find_n_closest(&vec![0.0, 0.0], 1, fetch).err(), Some(KdError::DimensionError));
+        assert_eq!(index.find_n_closest(&vec![0.0, f64::NAN, 0.0], 1, fetch).err(), Some(KdError::NonFiniteValue { dimension: 1 }));
+        assert_eq!(KdTree::<Vec<f64>, f64>::builder().metric(Metric::Cosine).build_index(points.len(), fetch).err(), Some(KdError::InvalidConfiguration));
+
+        // Options an index tree can't honor are rejected instead of ignored
+        let builders = vec![
+            KdTree::<Vec<f64>, f64>::builder().weights(vec![1.0, 2.0, 1.0]),
+            KdTree::<Vec<f64>, f64>::builder().split_strategy(SplitStrategy::Order(vec![2, 1, 0])),
+            KdTree::<Vec<f64>, f64>::builder().bucket_size(8),
+            KdTree::<Vec<f64>, f64>::builder().split_tolerance(0.1),
+            KdTree::<Vec<f64>, f64>::builder().max_nodes(10000),
+            KdTree::<Vec<f64>, f64>::builder().growth_policy(GrowthPolicy::Exact),
+            KdTree::<Vec<f64>, f64>::builder().auto_rebalance(2.0),
+        ];
+        for builder in builders {
+            assert_eq!(builder.build_index(points.len(), fetch).err(), Some(KdError::InvalidConfiguration));
+        }
+    }
+
+    #[test]
+    fn test_distance_matrix() {
+        let mut rng = StdRng::seed_from_u64(139);
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend((0..10000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()])).unwrap();
+        let queries: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+
+        let k = 8;
+        let matrix = tree.distance_matrix(&queries, k).unwrap();
+        assert_eq!(matrix.len(), queries.len());
+        for (query_point, row) in queries.iter().zip(matrix.iter()) {
+            assert_eq!(row.len(), k);
+            for (i, &distance) in row.iter().enumerate() {
+                assert_eq!(distance, tree.kth_nearest_distance(query_point, i + 1).unwrap());
+            }
+        }
+
+        #[cfg(feature="rayon")]
+        assert_eq!(tree.par_distance_matrix(&queries, k).unwrap(), matrix);
+
+        assert_eq!(tree.kth_nearest_distance(&queries[0], 0), Err(KdError::BinaryHeapError));
+        assert_eq!(tree.kth_nearest_distance(&queries[0], 10001), Err(KdError::BinaryHeapError));
+    }
+
+    #[test]
+    fn test_closest_index() {
+        let mut rng = StdRng::seed_from_u64(140);
+        let points: Vec<Vec<f64>> = (0..5000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.clone()).unwrap();
+
+        for _i in 0..50 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let kd_search = tree.k_nearest(&query_point, 10).unwrap();
+            let brute_search = tree.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+            for (kd_closest, brute_closest) in kd_search.iter().zip(brute_search.iter()) {
+                assert_eq!(kd_closest.index(), brute_closest.index());
+                let index = kd_closest.index().unwrap();
+                assert_eq!(tree.get_point(index), Some(&kd_closest.point));
+                assert_eq!(points[index], kd_closest.point);
+            }
+        }
+
+        tree.remove_point(&points[0]).unwrap();
+        assert_eq!(tree.get_point(0), None);
+        assert_eq!(tree.get_point(1), Some(&points[1]));
+        assert_eq!(tree.get_point(points.len()), None);
+
+        // Results built outside a tree have no index
+        let closest = Closest::new(points[1].clone(), 0.5);
+        assert!(closest.point == points[1] && closest.distance == 0.5 && closest.index().is_none());
+    }
+
+    #[test]
+    fn test_grid_histogram() {
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        // Points at cell centers of a 3 by 2 grid, with cell (x, y) holding x + y points
+        for x in 0..3 {
+            for y in 0..2 {
+                for _i in 0..(x + y) {
+                    tree.add_point(vec![x as f64 + 0.5, 10.0 + y as f64 * 2.0 + 1.0]).unwrap();
+                }
+            }
+        }
+        // Points outside the grid
+        tree.add_point(vec![-0.5, 11.0]).unwrap();
+        tree.add_point(vec![1.5, 14.5]).unwrap();
+
+        let counts = tree.grid_histogram(&vec![0.0, 10.0], &vec![1.0, 2.0], &[3, 2]).unwrap();
+        assert_eq!(counts, vec![0, 1, 1, 2, 2, 3]);
+
+        assert_eq!(tree.grid_histogram(&vec![0.0, 10.0], &vec![1.0, 2.0], &[3]), Err(KdError::DimensionError));
+        assert_eq!(tree.grid_histogram(&vec![0.0, 10.0], &vec![1.0, 0.0], &[3, 2]), Err(KdError::InvalidInput));
+    }
+
+    #[test]
+    fn test_nearest() {
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        assert_eq!(tree.nearest(&vec![0.5, 0.5]).unwrap(), None);
+        assert_eq!(tree.nearest(&vec![0.5, 0.5, 0.5]), Err(KdError::DimensionError));
+
+        let mut rng = StdRng::seed_from_u64(142);
+        tree.extend((0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()])).unwrap();
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            assert_eq!(tree.nearest(&query_point).unwrap(), Some(tree.find_closest(&query_point).unwrap()));
+        }
+
+        assert_eq!(tree.nearest(&vec![0.5]), Err(KdError::DimensionError));
+        assert_eq!(tree.nearest(&vec![0.5, f64::NAN]), Err(KdError::NonFiniteValue { dimension: 1 }));
+
+        // Removing every point empties the tree again
+        tree.remove_within_radius(&vec![0.5, 0.5], 1.0).unwrap();
+        assert_eq!(tree.nearest(&vec![0.5, 0.5]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_query_then_insert() {
+        let mut rng = StdRng::seed_from_u64(143);
+        let points: Vec<Vec<f64>> = (0..500).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::default();
+
+        for (i, point) in points.iter().enumerate() {
+            let nearest = tree.query_then_insert(point.clone()).unwrap();
+            // Nearest is found among the points streamed in before
+            let expected = points[..i].iter().map(|other| other.distance(point).unwrap()).fold(None, |best: Option<f64>, distance| {
+                Some(best.map_or(distance, |best| best.min(distance)))
+            });
+            assert_eq!(nearest.map(|(_, distance)| distance), expected);
+            assert_eq!(tree.len(), i + 1);
+        }
+
+        assert_eq!(tree.query_then_insert(vec![0.5]), Err(KdError::DimensionError));
+        assert_eq!(tree.len(), points.len());
+    }
+
+    #[test]
+    fn test_find_n_closest_dedup() {
+        let mut rng = StdRng::seed_from_u64(144);
+        let locations: Vec<Vec<f64>> = (0..200).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        // Every location is stored several times
+        for _i in 0..5 {
+            tree.extend(locations.clone()).unwrap();
+        }
+
+        for _i in 0..50 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let deduped = tree.find_n_closest_dedup(&query_point, 10).unwrap().into_sorted_vec();
+            assert_eq!(deduped.len(), 10);
+            for (i, closest) in deduped.iter().enumerate() {
+                assert!(deduped[..i].iter().all(|other| other.point != closest.point));
+            }
+
+            let mut expected: Vec<f64> = locations.iter().map(|point| point.distance(&query_point).unwrap()).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.truncate(10);
+            assert_eq!(deduped.iter().map(|closest| closest.distance).collect::<Vec<f64>>(), expected);
+
+            // Without deduplication the nearest location fills the results
+            let duplicated = tree.k_nearest(&query_point, 5).unwrap();
+            assert!(duplicated.iter().all(|closest| closest.point == deduped[0].point));
+        }
+    }
+
+    #[test]
+    fn test_set_coord() {
+        let mut point = vec![1f64, 2f64, 3f64];
+        point.set_coord(1, -4f64);
+        assert_eq!(point.coord(1), -4f64);
+        assert_eq!(point, vec![1f64, -4f64, 3f64]);
+
+        let mut point = vec![1f32, 2f32];
+        point.set_coord(0, 0.5f32);
+        assert_eq!(point.coord(0), 0.5f32);
+
+        #[cfg(feature="default")]
+        {
+            let mut point = super::Array1::from(vec![1f64, 2f64]);
+            point.set_coord(1, 7f64);
+            assert_eq!(point.coord(1), 7f64);
+        }
+
+        let mut point = ComplexPoint((1.0, 2.0));
+        point.set_coord(0, 3.0);
+        point.set_coord(1, -1.0);
+        assert_eq!(point, ComplexPoint((3.0, -1.0)));
+    }
+
+    #[cfg(all(feature="rayon", feature="std"))]
+    #[test]
+    fn test_par_build_balanced() {
+        let mut rng = StdRng::seed_from_u64(146);
+        // Enough points that the top levels are built in parallel and the rest serially
+        let points: Vec<Vec<f64>> = (0..50000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let (tree, elapsed) = KdTree::<Vec<f64>, f64>::par_build_balanced(3, points.clone()).unwrap();
+        let serial = KdTree::<Vec<f64>, f64>::build_from_vec(3, points).unwrap();
+        assert!(elapsed.as_nanos() > 0);
+        assert_eq!(tree.len(), serial.len());
+
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let parallel_result = tree.k_nearest(&query_point, 10).unwrap();
+            let serial_result = serial.k_nearest(&query_point, 10).unwrap();
+            for (a, b) in parallel_result.iter().zip(serial_result.iter()) {
+                assert_eq!(a.point, b.point);
+                assert_eq!(a.distance, b.distance);
+            }
+        }
+    }
+
+    #[test]
+    fn test_balance_in_place() {
+        let mut rng = StdRng::seed_from_u64(147);
+        // Sorted insertion builds a single chain
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        let mut points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        points.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+        tree.extend(points.iter().cloned()).unwrap();
+        for point in points.iter().step_by(5) {
+            tree.remove_point(point).unwrap();
+        }
+
+        let capacity = tree.capacity();
+        tree.balance_in_place();
+        assert_eq!(tree.capacity(), capacity);
+        assert_eq!(tree.num_tombstones(), 0);
+        assert_eq!(tree.len(), 1600);
+
+        let fresh = KdTree::<Vec<f64>, f64>::build_from_vec(2, tree.to_vec()).unwrap();
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let (balanced_result, balanced_stats) = tree.find_n_closest_instrumented(&query_point, 10).unwrap();
+            let (fresh_result, fresh_stats) = fresh.find_n_closest_instrumented(&query_point, 10).unwrap();
+            assert_eq!(balanced_stats, fresh_stats);
+            for (a, b) in balanced_result.iter().zip(fresh_result.iter()) {
+                assert_eq!(a.point, b.point);
+                assert_eq!(a.distance, b.distance);
+            }
+        }
+
+        // Tree keeps working after balancing
+        tree.add_point(vec![0.5, 0.5]).unwrap();
+        assert_eq!(tree.find_closest(&vec![0.5, 0.5]).unwrap().1, 0.0);
+    }
+
+    #[cfg(feature="default")]
+    #[test]
+    fn test_find_n_closest_view() {
+        use ndarray::{Array1, Array2, Axis, s};
+
+        let mut rng = StdRng::seed_from_u64(148);
+        let mut tree = KdTree::<Array1<f64>, f64>::new(3);
+        tree.extend((0..5000).map(|_| Array1::from(vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]))).unwrap();
+        let queries = Array2::from_shape_fn((50, 3), |_| rng.gen::<f64>());
+
+        for query_point in queries.axis_iter(Axis(0)) {
+            let from_view = tree.find_n_closest_view(query_point, 10).unwrap().into_sorted_vec();
+            let from_owned = tree.find_n_closest(&query_point.to_owned(), 10).unwrap().into_sorted_vec();
+            assert_eq!(from_view.len(), 10);
+            for (a, b) in from_view.iter().zip(from_owned.iter()) {
+                assert_eq!(a.point, b.point);
+                assert_eq!(a.distance, b.distance);
+            }
+        }
+
+        // Columns of a row major array aren't contiguous
+        let column = queries.slice(s![..3, 1]);
+        assert!(column.as_slice().is_none());
+        let from_view = tree.find_n_closest_view(column, 5).unwrap().into_sorted_vec();
+        let from_owned = tree.find_n_closest(&column.to_owned(), 5).unwrap().into_sorted_vec();
+        assert!(from_view.iter().zip(from_owned.iter()).all(|(a, b)| a.point == b.point));
+
+        assert_eq!(tree.find_n_closest_view(queries.slice(s![0, ..2]), 1).err(), Some(KdError::DimensionError));
+        let non_finite = Array1::from(vec![0.5, 0.5, f64::INFINITY]);
+        assert_eq!(tree.find_n_closest_view(non_finite.view(), 1).err(), Some(KdError::NonFiniteValue { dimension: 2 }));
+    }
+
+    #[test]
+    fn test_subtree_bounds() {
+        let mut rng = StdRng::seed_from_u64(149);
+        // Tight clusters spread far apart leave most of each split's half space empty
+        let centers: Vec<Vec<f64>> = (0..20).map(|_| vec![rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0]).collect();
+        let points: Vec<Vec<f64>> = (0..10000).map(|i| centers[i % centers.len()].iter().map(|c| c + rng.gen::<f64>() * 0.5).collect()).collect();
+        let plain = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        let mut bounded = KdTree::<Vec<f64>, f64>::builder().subtree_bounds(true).build_from_vec(points.clone()).unwrap();
+
+        let (mut plain_visited, mut bounded_visited) = (0, 0);
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0];
+            let (plain_result, plain_stats) = plain.find_n_closest_instrumented(&query_point, 10).unwrap();
+            let (bounded_result, bounded_stats) = bounded.find_n_closest_instrumented(&query_point, 10).unwrap();
+            plain_visited += plain_stats.nodes_visited;
+            bounded_visited += bounded_stats.nodes_visited;
+            for (a, b) in plain_result.iter().zip(bounded_result.iter()) {
+                assert_eq!(a.point, b.point);
+                assert_eq!(a.distance, b.distance);
+            }
+        }
+        assert!(bounded_visited < plain_visited, "bounded visited {}, plain visited {}", bounded_visited, plain_visited);
+
+        // Bounds follow points added and removed afterwards
+        bounded.extend((0..1000).map(|_| vec![rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0])).unwrap();
+        bounded.remove_within_radius(&centers[0], 5.0).unwrap();
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0, rng.gen::<f64>() * 100.0];
+            let kd_search = bounded.k_nearest(&query_point, 10).unwrap();
+            let brute_search = bounded.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+            for (kd_closest, brute_closest) in kd_search.iter().zip(brute_search.iter()) {
+                assert_eq!(kd_closest.point, brute_closest.point);
+            }
+        }
+
+        let invalid = KdTree::<Vec<f64>, f64>::builder().dimensions(3).subtree_bounds(true).distance_fn(Box::new(ScaledEuclidean(vec![1.0, 1.0, 1.0]))).build();
+        assert_eq!(invalid.err(), Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_find_n_closest_masked() {
+        let mut rng = StdRng::seed_from_u64(151);
+        let points: Vec<Vec<f64>> = (0..5000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        let active = [true, false, true];
+
+        for _i in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let masked: Vec<f64> = tree.find_n_closest_masked(&query_point, &active, 10).unwrap().into_sorted_vec().iter().map(|closest| closest.distance).collect();
+
+            // Brute force over points projected onto the active dimensions
+            let mut expected: Vec<f64> = points.iter().map(|point| vec![point[0], point[2]].distance(&vec![query_point[0], query_point[2]]).unwrap()).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.truncate(10);
+            assert_eq!(masked, expected);
+        }
+
+        let query_point = vec![0.5, 0.5, 0.5];
+        assert_eq!(tree.find_n_closest_masked(&query_point, &[true, false], 1).err(), Some(KdError::DimensionError));
+        assert_eq!(tree.find_n_closest_masked(&query_point, &[false, false, false], 1).err(), Some(KdError::DimensionError));
+        let cosine = KdTree::<Vec<f64>, f64>::with_metric(3, Metric::Cosine);
+        assert_eq!(cosine.find_n_closest_masked(&query_point, &active, 1).err(), Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_flat_tree() {
+        let mut rng = StdRng::seed_from_u64(152);
+        let points: Vec<Vec<f64>> = (0..5000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        let balanced = KdTreeFlat::build_from_vec(3, points.clone()).unwrap();
+        let mut inserted = KdTreeFlat::new(3);
+        for point in points.iter() {
+            inserted.add_point(point).unwrap();
+        }
+        assert_eq!(balanced.len(), points.len());
+        assert_eq!(inserted.len(), points.len());
+
+        for _i in 0..100 {
+            let query_point = [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let expected = tree.k_nearest(&query_point.to_vec(), 10).unwrap();
+            for flat in [&balanced, &inserted].iter() {
+                let result = flat.find_n_closest(&query_point, 10).unwrap();
+                assert_eq!(result.len(), expected.len());
+                for (a, b) in result.iter().zip(expected.iter()) {
+                    assert_eq!(a.point, b.point);
+                    assert_eq!(a.distance, b.distance);
+                    assert_eq!(flat.point(a.index().unwrap()), &a.point[..]);
+                }
+            }
+        }
+
+        // Points added one at a time are stored in insertion order
+        assert_eq!(inserted.point(10), &points[10][..]);
+        assert_eq!(inserted.add_point(&[0.5, 0.5]), Err(KdError::DimensionError));
+        assert_eq!(inserted.add_point(&[0.5, f64::NAN, 0.5]), Err(KdError::NonFiniteValue { dimension: 1 }));
+        assert_eq!(inserted.find_n_closest(&[0.5, 0.5], 1).err(), Some(KdError::DimensionError));
+        assert_eq!(inserted.find_n_closest(&[0.5, 0.5, f64::INFINITY], 1).err(), Some(KdError::NonFiniteValue { dimension: 2 }));
+        assert_eq!(KdTreeFlat::<f64>::new(3).find_n_closest(&[0.5, 0.5, 0.5], 1).err(), Some(KdError::EmptyTree));
+    }
+
+    #[test]
+    fn test_knn_self_distances() {
+        let mut rng = StdRng::seed_from_u64(153);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+        tree.remove_point(&points[0]).unwrap();
+
+        let k = 5;
+        let distances = tree.knn_self_distances(k).unwrap();
+        assert_eq!(distances.len(), points.len() - 1);
+        for (i, distance) in (1..points.len()).zip(distances.iter()) {
+            // Brute force over every other remaining point
+            let mut expected: Vec<f64> = (1..points.len()).filter(|&j| j != i).map(|j| points[j].distance(&points[i]).unwrap()).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(*distance, expected[k - 1]);
+        }
+
+        #[cfg(feature="rayon")]
+        assert_eq!(tree.par_knn_self_distances(k).unwrap(), distances);
+
+        assert_eq!(tree.knn_self_distances(0), Err(KdError::BinaryHeapError));
+        assert_eq!(tree.knn_self_distances(points.len() - 1), Err(KdError::BinaryHeapError));
+    }
+
+    #[test]
+    fn test_array_point() {
+        let mut rng = StdRng::seed_from_u64(154);
+        let points: Vec<[f64; 3]> = (0..5000).map(|_| [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut array_tree = KdTree::<[f64; 3], f64>::new(3);
+        array_tree.extend(points.iter().cloned()).unwrap();
+        let vec_tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.iter().map(|point| point.to_vec()).collect()).unwrap();
+
+        for _i in 0..100 {
+            let query_point = [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let array_result = array_tree.k_nearest(&query_point, 10).unwrap();
+            let vec_result = vec_tree.k_nearest(&query_point.to_vec(), 10).unwrap();
+            for (a, b) in array_result.iter().zip(vec_result.iter()) {
+                assert_eq!(a.point.to_vec(), b.point);
+                assert_eq!(a.distance, b.distance);
+            }
+        }
+
+        assert_eq!([1f32, 2f32].midpoint(&[3f32, -2f32]), [2f32, 0f32]);
+        assert_eq!([1f64, 2f64, 3f64].split_plane(1), [0f64, 2f64, 0f64]);
+        // Points are stored inline rather than behind a pointer to a separate allocation
+        assert!(std::mem::size_of::<[f64; 3]>() <= std::mem::size_of::<Vec<f64>>());
+    }
+
+    #[cfg(feature="default")]
+    #[test]
+    fn test_array_point_matches_array1() {
+        use ndarray::Array1;
+
+        // Python trees of 3 dimensions are backed by [f64; 3] points, others by Array1
+        let mut rng = StdRng::seed_from_u64(1154);
+        let points: Vec<[f64; 3]> = (0..2000).map(|_| [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut fixed_tree = KdTree::<[f64; 3], f64>::new(3);
+        let mut dynamic_tree = KdTree::<Array1<f64>, f64>::new(3);
+        for point in points.iter() {
+            fixed_tree.add_point(*point).unwrap();
+            dynamic_tree.add_point(Array1::from(point.to_vec())).unwrap();
+        }
+
+        for _i in 0..100 {
+            let query_point = [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let fixed_result = fixed_tree.k_nearest(&query_point, 10).unwrap();
+            let dynamic_result = dynamic_tree.k_nearest(&Array1::from(query_point.to_vec()), 10).unwrap();
+            assert_eq!(fixed_result.len(), dynamic_result.len());
+            for (a, b) in fixed_result.iter().zip(dynamic_result.iter()) {
+                assert_eq!(a.point.to_vec(), b.point.to_vec());
+                assert_eq!(a.distance, b.distance);
+            }
+        }
+
+        // Array1 keeps its coordinates in a separate allocation on top of its inline header
+        assert!(std::mem::size_of::<[f64; 3]>() < std::mem::size_of::<Array1<f64>>());
+    }
+
+    #[test]
+    fn test_replace_with() {
+        let mut rng = StdRng::seed_from_u64(155);
+        let old_points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let new_points: Vec<Vec<f64>> = (0..500).map(|_| vec![rng.gen::<f64>() + 10.0, rng.gen::<f64>() + 10.0]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(old_points.clone()).unwrap();
+        tree.remove_point(&old_points[0]).unwrap();
+        let capacity = tree.capacity();
+
+        tree.replace_with(new_points.clone()).unwrap();
+        assert_eq!(tree.len(), new_points.len());
+        assert_eq!(tree.num_tombstones(), 0);
+        assert_eq!(tree.capacity(), capacity);
+        for point in old_points.iter() {
+            assert!(tree.find_closest(point).unwrap().1 > 1.0);
+        }
+        for point in new_points.iter() {
+            assert_eq!(tree.find_closest(point).unwrap().1, 0.0);
+        }
+
+        // Rejected points leave the tree untouched
+        assert_eq!(tree.replace_with(vec![vec![0.0, 0.0], vec![0.0]]), Err(KdError::DimensionError));
+        assert_eq!(tree.replace_with(vec![vec![0.0, f64::NAN]]), Err(KdError::NonFiniteValue { dimension: 1 }));
+        assert_eq!(tree.len(), new_points.len());
+
+        // Inserting after replacement still works
+        tree.add_point(vec![0.5, 0.5]).unwrap();
+        assert_eq!(tree.find_closest(&vec![0.5, 0.5]).unwrap().1, 0.0);
+    }
+
+    #[test]
+    fn test_peek_nearest() {
+        let mut rng = StdRng::seed_from_u64(156);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        assert_eq!(tree.peek_nearest(&points[0], |point, _| point[0]), Ok(None));
+        tree.extend(points.clone()).unwrap();
+
+        for _ in 0..100 {
+            let query = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let (closest, distance) = tree.find_closest(&query).unwrap();
+            assert_eq!(tree.peek_nearest(&query, |point, _| point[1]), Ok(Some(closest[1])));
+            assert_eq!(tree.peek_nearest(&query, |_, distance| distance), Ok(Some(distance)));
+        }
+
+        assert_eq!(tree.peek_nearest(&vec![0.0], |point, _| point[0]), Err(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_add_points_maintained() {
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).auto_rebalance(2.0).build().unwrap();
+        let mut unmaintained = KdTree::<Vec<f64>, f64>::new(2);
+
+        // Sorted batches degrade a tree built one point at a time into a list
+        for batch in 0..20 {
+            let points: Vec<Vec<f64>> = (0..50).map(|i| vec![(batch * 50 + i) as f64, 0.0]).collect();
+            tree.add_points_maintained(points.clone()).unwrap();
+            unmaintained.extend(points).unwrap();
+            assert!(tree.depth() as f64 <= 2.0 * ((tree.len() + 1) as f64).log2());
+        }
+        assert_eq!(tree.len(), 1000);
+        assert_eq!(unmaintained.depth(), 999);
+        assert!(tree.depth() < 100);
+        for i in 0..1000 {
+            assert_eq!(tree.find_closest(&vec![i as f64, 0.0]).unwrap().1, 0.0);
+        }
+
+        // Trees without a threshold are never rebalanced
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.add_points_maintained((0..100).map(|i| vec![i as f64, 0.0]).collect()).unwrap();
+        assert_eq!(tree.depth(), 99);
+
+        assert!(KdTree::<Vec<f64>, f64>::builder().auto_rebalance(0.5).build().is_err());
+        assert!(KdTree::<Vec<f64>, f64>::builder().auto_rebalance(f64::NAN).build().is_err());
+    }
+
+    #[test]
+    fn test_nearest_to_segment() {
+        let mut rng = StdRng::seed_from_u64(158);
+        let points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.clone()).unwrap();
+        tree.remove_point(&points[0]).unwrap();
+
+        let segment_distance = |a: &Vec<f64>, b: &Vec<f64>, p: &Vec<f64>| {
+            let along: f64 = (0..3).map(|i| (p[i] - a[i]) * (b[i] - a[i])).sum();
+            let length_squared: f64 = (0..3).map(|i| (b[i] - a[i]).powi(2)).sum();
+            let t = if length_squared > 0.0 { (along / length_squared).clamp(0.0, 1.0) } else { 0.0 };
+            (0..3).map(|i| (p[i] - (a[i] + t * (b[i] - a[i]))).powi(2)).sum::<f64>().sqrt()
+        };
+
+        for trial in 0..200 {
+            // Include segments reaching outside the points and degenerate ones
+            let a = vec![rng.gen::<f64>() * 2.0 - 0.5, rng.gen::<f64>() * 2.0 - 0.5, rng.gen::<f64>() * 2.0 - 0.5];
+            let b = if trial % 10 == 0 { a.clone() } else { vec![rng.gen::<f64>() * 2.0 - 0.5, rng.gen::<f64>() * 2.0 - 0.5, rng.gen::<f64>() * 2.0 - 0.5] };
+            let expected = points[1..].iter().map(|p| segment_distance(&a, &b, p)).fold(f64::INFINITY, f64::min);
+
+            let (point, distance) = tree.nearest_to_segment(&a, &b).unwrap();
+            assert!((distance - expected).abs() < 1e-12);
+            assert!((segment_distance(&a, &b, &point) - expected).abs() < 1e-12);
+        }
+
+        assert_eq!(tree.nearest_to_segment(&vec![0.0; 3], &vec![0.0; 2]), Err(KdError::DimensionError));
+        assert_eq!(KdTree::<Vec<f64>, f64>::new(3).nearest_to_segment(&vec![0.0; 3], &vec![1.0; 3]), Err(KdError::EmptyTree));
+        let cosine = KdTree::<Vec<f64>, f64>::with_metric(3, Metric::Cosine);
+        assert_eq!(cosine.nearest_to_segment(&vec![0.0; 3], &vec![1.0; 3]), Err(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_within_annulus() {
+        let mut rng = StdRng::seed_from_u64(159);
+        let points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+
+        for _ in 0..50 {
+            let center = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let r_min = rng.gen::<f64>() * 0.3;
+            let r_max = r_min + rng.gen::<f64>() * 0.2;
+            let mut expected: Vec<f64> = points.iter().map(|point| point.distance(&center).unwrap()).filter(|&distance| distance >= r_min && distance <= r_max).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let found = tree.within_annulus(&center, r_min, r_max).unwrap();
+            assert_eq!(found.iter().map(|closest| closest.distance).collect::<Vec<f64>>(), expected);
+            for closest in found.iter() {
+                assert_eq!(closest.point, points[closest.index().unwrap()]);
+            }
+        }
+
+        assert!(tree.within_annulus(&vec![0.5, 0.5], 0.3, 0.2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_points_csv() {
+        let mut rng = StdRng::seed_from_u64(160);
+        let points: Vec<Vec<f64>> = (0..100).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>() * 1e-20, -rng.gen::<f64>() * 1e20]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.clone()).unwrap();
+        tree.remove_point(&points[0]).unwrap();
+
+        let mut csv = Vec::new();
+        tree.export_points_csv(&mut csv).unwrap();
+        assert_eq!(csv.iter().filter(|&&byte| byte == b'\n').count(), points.len() - 1);
+
+        let imported = KdTree::<Vec<f64>, f64>::import_points_csv(&csv[..]).unwrap();
+        assert_eq!(imported.get_num_dimensions(), 3);
+        let mut expected = points[1..].to_vec();
+        let mut found = imported.to_vec();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(found, expected);
+
+        let f32_tree = KdTree::<Vec<f32>, f32>::import_points_csv(&b"1.5,2\n\n-3,4e-2\n"[..]).unwrap();
+        assert_eq!(f32_tree.len(), 2);
+        assert!(KdTree::<Vec<f64>, f64>::import_points_csv(&b"1,2\n3,x\n"[..]).is_err());
+        assert!(KdTree::<Vec<f64>, f64>::import_points_csv(&b"1,2\n3\n"[..]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature="testing")]
+    fn test_verify_against_brute_force() {
+        let mut rng = StdRng::seed_from_u64(161);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let queries: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        assert_eq!(tree.verify_against_brute_force(&queries, 10), Ok(true));
+
+        // Overstating the distance to split planes prunes sub trees that hold closer points
+        struct Overpruning;
+        impl DistanceFn<f64> for Overpruning {
+            fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+                a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt()
+            }
+
+            fn axis_lower_bound(&self, a: f64, b: f64, _dim: usize) -> f64 {
+                100.0 * (a - b).abs()
+            }
+        }
+        let corrupted = KdTree::<Vec<f64>, f64>::builder().distance_fn(Box::new(Overpruning)).build_from_vec(points).unwrap();
+        assert_eq!(corrupted.verify_against_brute_force(&queries, 10), Ok(false));
+    }
+
+    /// Allocator counting allocations made by the current thread, so tests running in parallel
+    /// don't affect each other's counts
+    #[cfg(feature="arrayvec")]
+    struct CountingAllocator;
+
+    #[cfg(feature="arrayvec")]
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    #[cfg(feature="arrayvec")]
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+            std::alloc::System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            std::alloc::System.dealloc(ptr, layout)
+        }
+    }
+
+    #[cfg(feature="arrayvec")]
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    #[test]
+    #[cfg(feature="arrayvec")]
+    fn test_arrayvec_point() {
+        use arrayvec::ArrayVec;
+
+        let mut rng = StdRng::seed_from_u64(162);
+        let coords: Vec<[f64; 3]> = (0..1000).map(|_| [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<ArrayVec<f64, 4>, f64>::with_capacity(3, coords.len());
+
+        // Points live on the stack and storage for the nodes is already reserved
+        let before = ALLOCATIONS.with(|allocations| allocations.get());
+        for coords in coords.iter() {
+            let point: ArrayVec<f64, 4> = coords.iter().cloned().collect();
+            tree.add_point(point).unwrap();
+        }
+        assert_eq!(ALLOCATIONS.with(|allocations| allocations.get()), before);
+
+        let vec_tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, coords.iter().map(|coords| coords.to_vec()).collect()).unwrap();
+        for _ in 0..100 {
+            let query = [rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let (point, distance) = tree.find_closest(&query.iter().cloned().collect()).unwrap();
+            let (expected, expected_distance) = vec_tree.find_closest(&query.to_vec()).unwrap();
+            assert_eq!(point.as_slice(), expected.as_slice());
+            assert_eq!(distance, expected_distance);
+        }
+
+        let f32_point: ArrayVec<f32, 2> = [1.0f32, 2.0].iter().cloned().collect();
+        assert_eq!(f32_point.distance(&[4.0f32, 6.0].iter().cloned().collect()), Ok(5.0));
+        assert_eq!(f32_point.distance_squared(&[4.0f32, 6.0].iter().cloned().collect()), Ok(25.0));
+        assert_eq!(f32_point.distance_squared(&[4.0f32].iter().cloned().collect()), Err(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_depth_after_removal() {
+        // Sorted points build a chain, so the last points form the deepest branch
+        let points: Vec<Vec<f64>> = (0..100).map(|i| vec![i as f64, i as f64]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+        assert_eq!(tree.depth(), 99);
+
+        // Removing nodes that still have children keeps the depth
+        for point in points[50..99].iter() {
+            assert!(tree.remove_point(point).unwrap());
+        }
+        assert_eq!(tree.depth(), 99);
+
+        // Removing the leaf unlinks it along with the removed nodes above it
+        assert!(tree.remove_point(&points[99]).unwrap());
+        assert_eq!(tree.depth(), 49);
+        assert_eq!(tree.num_tombstones(), 50);
+        let (_, stats) = tree.find_n_closest_instrumented(&vec![99.0, 99.0], 1).unwrap();
+        assert_eq!(stats.max_depth_reached, 49);
+
+        // Queries and insertion still see the remaining points only
+        assert_eq!(tree.find_closest(&vec![99.0, 99.0]).unwrap().0, points[49]);
+        tree.add_point(vec![75.0, 75.0]).unwrap();
+        assert_eq!(tree.depth(), 50);
+        assert_eq!(tree.find_closest(&vec![99.0, 99.0]).unwrap().0, vec![75.0, 75.0]);
+        tree.rebuild();
+        assert_eq!(tree.len(), 51);
+    }
+
+    #[test]
+    fn test_k_nearest_array() {
+        fn check<const K: usize>(tree: &KdTree<Vec<f64>, f64>, query: &Vec<f64>) {
+            let expected = tree.k_nearest(query, K).unwrap();
+            let (nearest, count) = tree.k_nearest_array::<K>(query).unwrap();
+            assert_eq!(count, expected.len());
+            for (closest, expected) in nearest.iter().zip(expected.iter()) {
+                let closest = closest.as_ref().unwrap();
+                assert_eq!(closest.distance, expected.distance);
+                assert_eq!(closest.index(), expected.index());
+            }
+            assert!(nearest[count..].iter().all(|closest| closest.is_none()));
+        }
+
+        let mut rng = StdRng::seed_from_u64(164);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points).unwrap();
+        for _ in 0..100 {
+            let query = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            check::<1>(&tree, &query);
+            check::<3>(&tree, &query);
+            check::<8>(&tree, &query);
+        }
+
+        // Trees with fewer than K points fill only part of the array
+        let small = KdTree::<Vec<f64>, f64>::build_from_vec(2, vec![vec![0.0, 0.0], vec![1.0, 1.0]]).unwrap();
+        check::<8>(&small, &vec![0.2, 0.2]);
+        assert_eq!(small.k_nearest_array::<8>(&vec![0.2, 0.2]).unwrap().1, 2);
+
+        // Queries are validated like those of k_nearest
+        assert!(small.k_nearest_array::<2>(&vec![0.2, 0.2, 0.2]).err() == Some(KdError::DimensionError));
+        assert!(small.k_nearest_array::<2>(&vec![0.2, f64::NAN]).err() == Some(KdError::NonFiniteValue { dimension: 1 }));
+    }
+
+    #[test]
+    fn test_spatio_temporal_point() {
+        // One point is close in space but far in time, the other the reverse
+        let build = |time_weight: f64| {
+            let mut tree = KdTree::<SpatioTemporalPoint, f64>::new(3);
+            tree.add_point(SpatioTemporalPoint::new(1.0, 0.0, 10.0, time_weight)).unwrap();
+            tree.add_point(SpatioTemporalPoint::new(5.0, 0.0, 0.0, time_weight)).unwrap();
+            tree
+        };
+
+        let query = SpatioTemporalPoint::new(0.0, 0.0, 0.0, 1.0);
+        let (nearest, distance) = build(1.0).find_closest(&query).unwrap();
+        assert_eq!(nearest.coords(), [5.0, 0.0, 0.0]);
+        assert_eq!(distance, 5.0);
+
+        let query = SpatioTemporalPoint::new(0.0, 0.0, 0.0, 0.1);
+        let (nearest, distance) = build(0.1).find_closest(&query).unwrap();
+        assert_eq!(nearest.coords(), [1.0, 0.0, 10.0]);
+        assert!((distance - 2f64.sqrt()).abs() < 1e-12);
+
+        // Search matches brute force with a weighted time axis
+        let mut rng = StdRng::seed_from_u64(165);
+        let mut tree = KdTree::<SpatioTemporalPoint, f64>::new(3);
+        for _ in 0..1000 {
+            tree.add_point(SpatioTemporalPoint::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>() * 100.0, 0.01)).unwrap();
+        }
+        for _ in 0..50 {
+            let query = SpatioTemporalPoint::new(rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>() * 100.0, 0.01);
+            let found: Vec<f64> = tree.k_nearest(&query, 5).unwrap().iter().map(|closest| closest.distance).collect();
+            let expected: Vec<f64> = tree.brute_force(&query, 5).unwrap().into_sorted_vec().iter().map(|closest| closest.distance).collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_find_all_closest() {
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        for point in [vec![1.0, 0.0], vec![5.0, 5.0], vec![0.0, -1.0], vec![2.0, 2.0], vec![1.0, 0.0]].iter() {
+            tree.add_point(point.clone()).unwrap();
+        }
+
+        // Two points on the axes, one of them stored twice, are all exactly one away
+        assert_eq!(tree.find_all_closest(&vec![0.0, 0.0]).unwrap(), vec![vec![1.0, 0.0], vec![0.0, -1.0], vec![1.0, 0.0]]);
+        assert_eq!(tree.find_all_closest(&vec![4.0, 4.0]).unwrap(), vec![vec![5.0, 5.0]]);
+        assert_eq!(tree.find_all_closest(&vec![0.0]), Err(KdError::DimensionError));
+
+        // Points mirrored across the diagonal through the query tie
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.add_point(vec![0.1, 0.7]).unwrap();
+        tree.add_point(vec![0.7, 0.1]).unwrap();
+        tree.add_point(vec![0.9, 0.9]).unwrap();
+        assert_eq!(tree.find_all_closest(&vec![0.3, 0.3]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_kd_tree_map() {
+        let mut rng = StdRng::seed_from_u64(167);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let pairs: Vec<(Vec<f64>, String)> = points.iter().enumerate().map(|(i, point)| (point.clone(), format!("point {}", i))).collect();
+        let mut map = KdTreeMap::<Vec<f64>, String, f64>::build_from_pairs(0, pairs).unwrap();
+        map.insert(vec![2.0, 2.0], String::from("inserted")).unwrap();
+        assert_eq!(map.len(), points.len() + 1);
+
+        let label = |point: &Vec<f64>| match points.iter().position(|other| other == point) {
+            Some(i) => format!("point {}", i),
+            None => String::from("inserted"),
+        };
+        for _ in 0..100 {
+            let query = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let (point, value, _) = map.find_closest(&query).unwrap();
+            assert_eq!(*value, label(&point));
+            for (closest, value) in map.k_nearest(&query, 5).unwrap() {
+                assert_eq!(*value, label(&closest.point));
+                assert_eq!(map.get(closest.index().unwrap()), Some((&closest.point, value)));
+            }
+        }
+        assert_eq!(*map.find_closest(&vec![3.0, 3.0]).unwrap().1, "inserted");
+
+        let mismatched = vec![(vec![0.0, 0.0], 0), (vec![0.0], 1)];
+        assert!(KdTreeMap::<Vec<f64>, i32, f64>::build_from_pairs(0, mismatched).is_err());
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut rng = StdRng::seed_from_u64(168);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::with_capacity(2, 2000);
+        tree.extend(points.clone()).unwrap();
+        // Points inserted last only have children inserted after them, so removing them leaves
+        // no removed node that is needed to reach other points
+        for point in points[900..].iter() {
+            tree.remove_point(point).unwrap();
+        }
+        assert_eq!(tree.validate(), Ok(()));
+        let tombstones = tree.num_tombstones();
+
+        tree.compact();
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.len(), 900);
+        assert_eq!(tombstones, 100);
+        assert_eq!(tree.num_tombstones(), 0);
+        assert!(tree.capacity() < 2000);
+        assert!(tree.capacity() >= tree.len() + tree.num_tombstones());
+
+        let mut expected = points[..900].to_vec();
+        let mut found = tree.to_vec();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        found.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(found, expected);
+        for _ in 0..100 {
+            let query = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let found: Vec<f64> = tree.k_nearest(&query, 5).unwrap().iter().map(|closest| closest.distance).collect();
+            let expected: Vec<f64> = tree.brute_force(&query, 5).unwrap().into_sorted_vec().iter().map(|closest| closest.distance).collect();
+            assert_eq!(found, expected);
+        }
+
+        // Inserting after compaction keeps the structure valid
+        tree.extend(points[900..].iter().cloned()).unwrap();
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.len(), 1000);
+
+        // Removing every point leaves an empty tree
+        for point in points.iter() {
+            tree.remove_point(point).unwrap();
+        }
+        tree.compact();
+        assert_eq!(tree.validate(), Ok(()));
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_point_dims() {
+        assert_eq!(<[f64; 3] as Point<f64>>::DIMS, Some(3));
+        assert_eq!(<[f32; 2] as Point<f32>>::DIMS, Some(2));
+        assert_eq!(<Vec<f64> as Point<f64>>::DIMS, None);
+        assert_eq!(<ComplexPoint as Point<f64>>::DIMS, Some(2));
+        assert_eq!(<SpatioTemporalPoint as Point<f64>>::DIMS, Some(3));
+
+        // Compile time dimensionality agrees with the runtime one
+        assert_eq!(<[f64; 3] as Point<f64>>::DIMS, Some([0f64; 3].dimensions()));
+    }
+
+    #[test]
+    fn test_count_in_range() {
+        let mut rng = StdRng::seed_from_u64(170);
+        let mut points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        // Points on the box boundary are counted
+        points.push(vec![0.25, 0.25, 0.25]);
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.clone()).unwrap();
+        tree.remove_point(&points[0]).unwrap();
+
+        let min = vec![0.25, 0.25, 0.25];
+        let max = vec![0.75, 0.75, 0.75];
+        let mut boxes = vec![(min, max)];
+        for _ in 0..50 {
+            let (a, b) = (vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()], vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]);
+            boxes.push(((0..3).map(|i| a[i].min(b[i])).collect(), (0..3).map(|i| a[i].max(b[i])).collect()));
+        }
+
+        for (min, max) in boxes.iter() {
+            let expected = points[1..].iter().filter(|point| (0..3).all(|i| min[i] <= point[i] && point[i] <= max[i])).count();
+            assert_eq!(tree.count_in_range(min, max).unwrap(), expected);
+        }
+
+        // Empty boxes count nothing
+        assert_eq!(tree.count_in_range(&vec![0.75; 3], &vec![0.25; 3]).unwrap(), 0);
+        assert_eq!(tree.count_in_range(&vec![0.0; 3], &vec![1.0; 2]), Err(KdError::DimensionError));
+    }
+
+    #[test]
+    #[cfg(feature="glam")]
+    fn test_glam_point() {
+        use glam::{DVec3, Vec3};
+
+        let mut rng = StdRng::seed_from_u64(171);
+        let points: Vec<Vec3> = (0..2000).map(|_| Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>())).collect();
+        let mut tree = KdTree::<Vec3, f32>::new(3);
+        tree.extend(points.iter().cloned()).unwrap();
+        for _ in 0..100 {
+            let query = Vec3::new(rng.gen::<f32>(), rng.gen::<f32>(), rng.gen::<f32>());
+            let found: Vec<f32> = tree.k_nearest(&query, 5).unwrap().iter().map(|closest| closest.distance).collect();
+            let mut expected: Vec<f32> = points.iter().map(|point| Vec3::distance(*point, query)).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(found, expected[..5].to_vec());
+        }
+
+        let tree = KdTree::<DVec3, f64>::build_from_vec(3, points.iter().map(|point| point.as_dvec3()).collect()).unwrap();
+        let (closest, distance) = tree.find_closest(&DVec3::new(2.0, 2.0, 2.0)).unwrap();
+        let expected = points.iter().map(|point| DVec3::distance(point.as_dvec3(), DVec3::new(2.0, 2.0, 2.0))).fold(f64::INFINITY, f64::min);
+        assert_eq!(distance, expected);
+        assert_eq!(DVec3::distance(closest, DVec3::new(2.0, 2.0, 2.0)), expected);
+
+        // Each axis is addressed by its own dimension, and dimensions past the last one panic
+        let mut point = Vec3::new(1.0, 2.0, 3.0);
+        point.set_coord(2, 4.0);
+        assert_eq!((0..3).map(|i| point.coord(i)).collect::<Vec<f32>>(), vec![1.0, 2.0, 4.0]);
+        assert!(std::panic::catch_unwind(|| point.coord(3)).is_err());
+        assert!(std::panic::catch_unwind(move || point.set_coord(3, 5.0)).is_err());
+    }
+
+    #[test]
+    fn test_point_defaults() {
+        /// Point implementing only the required methods
+        #[derive(Clone, Debug, PartialEq)]
+        struct Minimal {
+            x: f64,
+            y: f64,
+        }
+
+        impl Point<f64> for Minimal {
+            fn coord(&self, cur_dimension: usize) -> f64 {
+                if cur_dimension == 0 { self.x } else { self.y }
+            }
+
+            fn set_coord(&mut self, cur_dimension: usize, value: f64) {
+                if cur_dimension == 0 { self.x = value } else { self.y = value }
+            }
+
+            fn dimensions(&self) -> usize { 2 }
+        }
+
+        let a = Minimal { x: 1.0, y: 2.0 };
+        let b = Minimal { x: 4.0, y: 6.0 };
+        assert_eq!(a.distance(&b), Ok(5.0));
+        assert!(b.greater(&a, 1));
+        assert_eq!(a.split_plane(1), Minimal { x: 0.0, y: 2.0 });
+        assert_eq!(a.midpoint(&b), Minimal { x: 2.5, y: 4.0 });
+
+        let mut rng = StdRng::seed_from_u64(174);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Minimal, f64>::new(2);
+        tree.extend(points.iter().map(|point| Minimal { x: point[0], y: point[1] })).unwrap();
+        let vec_tree = KdTree::<Vec<f64>, f64>::build_from_vec(2, points).unwrap();
+        for _ in 0..100 {
+            let query = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+            let (closest, distance) = tree.find_closest(&Minimal { x: query[0], y: query[1] }).unwrap();
+            let (expected, expected_distance) = vec_tree.find_closest(&query).unwrap();
+            assert_eq!(vec![closest.x, closest.y], expected);
+            assert_eq!(distance, expected_distance);
+        }
+    }
+
+    #[test]
+    fn test_build_presorted() {
+        let mut rng = StdRng::seed_from_u64(175);
+        let mut points: Vec<Vec<f64>> = (0..1001).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        points.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+
+        // Distinct coordinates make every median unique, so both builds place the same points
+        let presorted = KdTree::<Vec<f64>, f64>::build_presorted(3, points.clone(), 0).unwrap();
+        let balanced = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        assert_eq!(presorted.validate(), Ok(()));
+        assert_eq!(presorted.to_vec(), balanced.to_vec());
+        assert_eq!(presorted.depth(), balanced.depth());
+
+        // Points equal to the median go right of it
+        let repeated: Vec<Vec<f64>> = (0..100).map(|i| vec![(i / 10) as f64, rng.gen::<f64>()]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_presorted(2, repeated.clone(), 0).unwrap();
+        assert_eq!(tree.validate(), Ok(()));
+        for point in repeated.iter() {
+            assert_eq!(tree.find_closest(point).unwrap().1, 0.0);
+        }
+
+        // Other axes build as usual, unsorted points are rejected
+        points.sort_by(|a, b| a[1].partial_cmp(&b[1]).unwrap());
+        assert_eq!(KdTree::<Vec<f64>, f64>::build_presorted(3, points.clone(), 1).unwrap().to_vec(), balanced.to_vec());
+        assert_eq!(KdTree::<Vec<f64>, f64>::build_presorted(3, points.clone(), 0).err(), Some(KdError::InvalidInput));
+        assert_eq!(KdTree::<Vec<f64>, f64>::build_presorted(3, points, 3).err(), Some(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_find_within_radius_capped() {
+        let mut rng = StdRng::seed_from_u64(176);
+        let points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+
+        for _ in 0..50 {
+            let query = vec![rng.gen::<f64>(), rng.gen::<f64>()];
+
+            // Radius captures many points, the cap keeps only the closest
+            let found = tree.find_within_radius_capped(&query, 0.5, 10).unwrap();
+            assert!(tree.count_within_radius(&query, 0.5).unwrap() > 10);
+            let expected: Vec<f64> = tree.k_nearest(&query, 10).unwrap().iter().map(|closest| closest.distance).collect();
+            assert_eq!(found.iter().map(|closest| closest.distance).collect::<Vec<f64>>(), expected);
+
+            // Radius smaller than the cap allows returns every point within it
+            let found = tree.find_within_radius_capped(&query, 0.02, 1000).unwrap();
+            let mut expected: Vec<f64> = points.iter().map(|point| point.distance(&query).unwrap()).filter(|&distance| distance <= 0.02).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(found.iter().map(|closest| closest.distance).collect::<Vec<f64>>(), expected);
+        }
+
+        assert!(tree.find_within_radius_capped(&vec![0.5, 0.5], 1.0, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dist_to_box() {
+        let (min, max) = (vec![0f64, 0.0], vec![2f64, 1.0]);
+        // Inside, on an edge, beside a face and beyond a corner
+        assert_eq!(vec![1f64, 0.5].dist_to_box(&min, &max), Ok(0.0));
+        assert_eq!(vec![2f64, 0.5].dist_to_box(&min, &max), Ok(0.0));
+        assert_eq!(vec![1f64, 3.0].dist_to_box(&min, &max), Ok(2.0));
+        assert_eq!(vec![-3f64, 5.0].dist_to_box(&min, &max), Ok(5.0));
+        assert_eq!(vec![1f64].dist_to_box(&min, &max), Err(KdError::DimensionError));
+
+        assert_eq!([5.0f32, 5.0, 0.5].dist_to_box(&[0.0, 0.0, 0.0], &[2.0, 1.0, 1.0]), Ok(5.0));
+        assert_eq!(ComplexPoint((4.0, -4.0)).dist_to_box(&ComplexPoint((0.0, 0.0)), &ComplexPoint((1.0, 1.0))), Ok(5.0));
+
+        // Weighted time axis scales the time gap
+        let point = SpatioTemporalPoint::new(0.0, 3.0, 10.0, 0.5);
+        let (min, max) = (SpatioTemporalPoint::new(0.0, 0.0, 0.0, 0.5), SpatioTemporalPoint::new(1.0, 0.0, 2.0, 0.5));
+        assert_eq!(point.dist_to_box(&min, &max), Ok(5.0));
+
+        // Never more than the distance to a point in the box
+        let mut rng = StdRng::seed_from_u64(177);
+        for _ in 0..100 {
+            let query: Vec<f64> = vec![rng.gen::<f64>() * 4.0 - 1.0, rng.gen::<f64>() * 4.0 - 1.0];
+            let inside = vec![rng.gen::<f64>() * 2.0, rng.gen::<f64>()];
+            assert!(query.dist_to_box(&vec![0.0, 0.0], &vec![2.0, 1.0]).unwrap() <= query.distance(&inside).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_repair() {
+        let mut rng = StdRng::seed_from_u64(178);
+        let points: Vec<Vec<f64>> = (0..100).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.clone()).unwrap();
+        assert_eq!(tree.repair(), 0);
+
+        // The last point added is a leaf, so dropping it only leaves its parent's link dangling
+        tree.clear_slot(points.len());
+        assert_eq!(tree.validate(), Err(KdError::NodeMissing));
+        assert_eq!(tree.repair(), 1);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.repair(), 0);
+        assert_eq!(tree.len(), points.len() - 1);
+
+        for point in points[..points.len() - 1].iter() {
+            assert_eq!(tree.find_closest(point).unwrap().1, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_find_exact() {
+        let mut rng = StdRng::seed_from_u64(179);
+        let points: Vec<CountingPoint> = (0..10_000).map(|_| CountingPoint { coords: vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()], plane: false }).collect();
+        let mut tree = KdTree::<CountingPoint, f64>::new(3);
+        tree.extend(points.iter().cloned()).unwrap();
+        tree.remove_point(&points[0]).unwrap();
+
+        for (i, point) in points.iter().enumerate().skip(1).step_by(97) {
+            COMPARISONS.with(|count| count.set(0));
+            DISTANCES.with(|count| count.set(0));
+            assert_eq!(tree.find_exact(point).unwrap(), Some(i));
+            let exact_comparisons = COMPARISONS.with(|count| count.get());
+            assert_eq!(DISTANCES.with(|count| count.get()), 0);
+            assert!(exact_comparisons <= tree.depth());
+
+            // A nearest search keeps going after reaching the match
+            COMPARISONS.with(|count| count.set(0));
+            DISTANCES.with(|count| count.set(0));
+            assert_eq!(tree.find_closest(point).unwrap().1, 0.0);
+            assert!(COMPARISONS.with(|count| count.get()) > exact_comparisons);
+            assert!(DISTANCES.with(|count| count.get()) > 0);
+        }
+
+        assert_eq!(tree.find_exact(&points[0]).unwrap(), None);
+        let mut missing = points[1].clone();
+        missing.coords[2] += 1e-9;
+        assert_eq!(tree.find_exact(&missing).unwrap(), None);
+
+        // Duplicates of a split value are found right of it
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        for i in 0..20 {
+            tree.add_point(vec![1.0, i as f64]).unwrap();
+        }
+        assert_eq!(tree.find_exact(&vec![1.0, 13.0]).unwrap(), Some(13));
+    }
+
+    #[test]
+    fn test_split_tolerance() {
+        // Axis bound rounded up slightly, like one computed in lower precision
+        struct RoundedUp;
+        impl DistanceFn<f64> for RoundedUp {
+            fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+                a.iter().zip(b.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt()
+            }
+
+            fn axis_lower_bound(&self, a: f64, b: f64, _dim: usize) -> f64 {
+                (a - b).abs() + 1e-6
+            }
+        }
+
+        // Root splits on x = 0.5 with points on the plane to its right and one just left of it
+        let points = [vec![0.5, 5.0], vec![0.5, 0.0], vec![0.5, 1.0], vec![0.5, 2.0], vec![0.5 - 3e-7, 0.0]];
+        let query = vec![0.5 - 1e-9, 0.0];
+
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).distance_fn(Box::new(RoundedUp)).build().unwrap();
+        tree.extend(points.iter().cloned()).unwrap();
+        let (closest, _) = tree.find_closest(&query).unwrap();
+        assert_eq!(closest, points[4]);
+
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).distance_fn(Box::new(RoundedUp)).split_tolerance(1e-6).build().unwrap();
+        tree.extend(points.iter().cloned()).unwrap();
+        let (closest, distance) = tree.find_closest(&query).unwrap();
+        assert_eq!(closest, points[1]);
+        assert!((distance - 1e-9).abs() < 1e-15);
+        assert_eq!(tree.find_n_closest(&query, 2).unwrap().into_sorted_vec()[1].point, points[4]);
+
+        assert!(KdTree::<Vec<f64>, f64>::builder().split_tolerance(-1.0).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().split_tolerance(f64::NAN).build().err() == Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_into_points() {
+        let mut rng = StdRng::seed_from_u64(181);
+        let points: Vec<Vec<f64>> = (0..500).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.iter().cloned()).unwrap();
+        tree.remove_point(&points[10]).unwrap();
+
+        let mut expected = points.clone();
+        expected.remove(10);
+        assert_eq!(tree.to_vec(), expected);
+        assert_eq!(tree.into_points(), expected);
+
+        assert!(KdTree::<Vec<f64>, f64>::new(2).into_points().is_empty());
+    }
+
+    #[test]
+    fn test_find_n_closest_excl_query() {
+        let mut rng = StdRng::seed_from_u64(182);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.iter().cloned()).unwrap();
+        let member = points[123].clone();
+        tree.add_point(member.clone()).unwrap();
+        tree.add_point(member.clone()).unwrap();
+
+        // Copies of the member fill the first slots of a plain search
+        let plain = tree.find_n_closest(&member, 5).unwrap().into_sorted_vec();
+        assert!(plain[..3].iter().all(|closest| closest.distance == 0.0));
+
+        let mut expected: Vec<f64> = points.iter().enumerate()
+                                           .filter(|&(i, _)| i != 123)
+                                           .map(|(_, point)| point.iter().zip(member.iter()).map(|(a, b)| (a - b).powi(2)).sum::<f64>().sqrt())
+                                           .collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let neighbors = tree.find_n_closest_excl_query(&member, 5).unwrap().into_sorted_vec();
+        assert_eq!(neighbors.len(), 5);
+        for (neighbor, distance) in neighbors.iter().zip(expected.iter()) {
+            assert!(neighbor.point != member);
+            assert!((neighbor.distance - distance).abs() < 1e-12);
+        }
+
+        // Only other points are returned when there aren't enough
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(vec![member.clone(), member.clone(), points[0].clone()]).unwrap();
+        let neighbors = tree.find_n_closest_excl_query(&member, 2).unwrap().into_sorted_vec();
+        assert_eq!(neighbors.len(), 1);
+        assert_eq!(neighbors[0].point, points[0]);
+    }
+
+    #[test]
+    fn test_max_nodes() {
+        let mut rng = StdRng::seed_from_u64(183);
+        let points: Vec<Vec<f64>> = (0..20).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).capacity(100).max_nodes(10).build().unwrap();
+        assert!(tree.capacity() <= 10);
+        for point in points[..10].iter() {
+            tree.add_point(point.clone()).unwrap();
+        }
+        assert_eq!(tree.add_point(points[10].clone()), Err(KdError::CapacityExceeded));
+        assert_eq!(tree.len(), 10);
+        assert!(tree.capacity() <= 10);
+
+        // Removed points keep their node until the tree is rebuilt
+        tree.remove_point(&points[9]).unwrap();
+        assert_eq!(tree.add_point(points[10].clone()), Err(KdError::CapacityExceeded));
+        tree.rebuild();
+        tree.add_point(points[10].clone()).unwrap();
+
+        // Storage grows one node at a time up to the limit
+        let mut tree = KdTree::<Vec<f64>, f64>::builder().dimensions(2).capacity(1).max_nodes(3).build().unwrap();
+        tree.extend(points[..3].iter().cloned()).unwrap();
+        assert!(tree.capacity() <= 3);
+        assert_eq!(tree.extend(points[3..].iter().cloned()), Err(KdError::CapacityExceeded));
+
+        let builder = || KdTree::<Vec<f64>, f64>::builder().max_nodes(10);
+        assert_eq!(builder().build_from_vec(points[..10].to_vec()).unwrap().len(), 10);
+        assert!(builder().build_from_vec(points[..11].to_vec()).err() == Some(KdError::CapacityExceeded));
+
+        let mut tree = builder().build_from_vec(points[..5].to_vec()).unwrap();
+        assert_eq!(tree.replace_with(points[..11].to_vec()), Err(KdError::CapacityExceeded));
+        assert_eq!(tree.len(), 5);
+        tree.replace_with(points[..10].to_vec()).unwrap();
+    }
+
+    #[test]
+    fn test_scalar_point() {
+        let mut rng = StdRng::seed_from_u64(184);
+        let mut values: Vec<f64> = (0..5000).map(|_| rng.gen_range(-1000.0, 1000.0)).collect();
+        let tree = KdTree::<Scalar, f64>::build_from_vec(1, values.iter().map(|&value| Scalar(value)).collect()).unwrap();
+        assert!(tree.depth() <= 13);
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for _ in 0..1000 {
+            let query = rng.gen_range(-1100.0, 1100.0);
+            // Nearest value is one of the two neighbors of the insertion point
+            let position = values.partition_point(|&value| value < query);
+            let expected = values[position.saturating_sub(1)..(position + 1).min(values.len())].iter()
+                                                                                               .map(|value| (value - query).abs())
+                                                                                               .fold(f64::INFINITY, f64::min);
+
+            let (closest, distance) = tree.find_closest(&Scalar(query)).unwrap();
+            assert_eq!(distance, expected);
+            assert_eq!((closest.0 - query).abs(), expected);
+        }
+    }
+
+    #[test]
+    fn test_remove_nearest() {
+        let mut rng = StdRng::seed_from_u64(186);
+        let points: Vec<Vec<f64>> = (0..300).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let query = vec![0.5, 0.5];
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.iter().cloned()).unwrap();
+
+        let mut expected = points.clone();
+        expected.sort_by(|a, b| a.distance(&query).unwrap().partial_cmp(&b.distance(&query).unwrap()).unwrap());
+
+        // Points come out nearest first, passing through rebuilds as the tree empties
+        for (i, point) in expected.iter().enumerate() {
+            let (removed, distance) = tree.remove_nearest(&query).unwrap().unwrap();
+            assert_eq!(&removed, point);
+            assert_eq!(distance, point.distance(&query).unwrap());
+            assert_eq!(tree.len(), points.len() - i - 1);
+            assert_eq!(tree.count_within_radius(&query, distance).unwrap(), 0);
+        }
+
+        assert!(tree.is_empty());
+        assert_eq!(tree.remove_nearest(&query), Ok(None));
+        assert_eq!(tree.remove_nearest(&vec![0.5]), Err(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_distance_squared() {
+        let mut rng = StdRng::seed_from_u64(187);
+        let points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        for (a, b) in points.iter().zip(points.iter().skip(1)) {
+            assert!(approx_eq(a.distance(b).unwrap().powi(2), a.distance_squared(b).unwrap(), 1e-12));
+            let (a, b) = ([a[0], a[1], a[2]], [b[0], b[1], b[2]]);
+            assert!(approx_eq(a.distance(&b).unwrap().powi(2), a.distance_squared(&b).unwrap(), 1e-12));
+        }
+        assert_eq!(vec![0f64, 0f64].distance_squared(&vec![3f64, 4f64]), Ok(25f64));
+        assert_eq!(vec![0f64].distance_squared(&vec![3f64, 4f64]), Err(KdError::DimensionError));
+        assert_eq!(ComplexPoint((0f64, 0f64)).distance_squared(&ComplexPoint((3f64, 4f64))), Ok(25f64));
+
+        // Searching on squared distances reports the same distances as the point type's distance
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        for query_point in points.iter().take(100) {
+            let kd_search = tree.k_nearest(query_point, 10).unwrap();
+            let mut expected: Vec<f64> = points.iter().map(|point| point.distance(query_point).unwrap()).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            let distances: Vec<f64> = kd_search.iter().map(|closest| closest.distance).collect();
+            assert_eq!(distances, expected[..10].to_vec());
+            assert_eq!(tree.find_closest(query_point).unwrap().1, 0f64);
+
+            let brute_search = tree.brute_force(query_point, 10).unwrap().into_sorted_vec();
+            assert!(brute_search.iter().zip(distances.iter()).all(|(closest, distance)| closest.distance == *distance));
+        }
+    }
+
+    #[test]
+    fn test_find_n_closest_in_box() {
+        let mut rng = StdRng::seed_from_u64(188);
+        let points: Vec<CountingPoint> = (0..20_000).map(|_| CountingPoint { coords: vec![rng.gen::<f64>(), rng.gen::<f64>()], plane: false }).collect();
+        let tree = KdTree::<CountingPoint, f64>::build_from_vec(2, points.clone()).unwrap();
+        let corner = |x: f64, y: f64| CountingPoint { coords: vec![x, y], plane: false };
+        let (min, max) = (corner(0.5, 0.5), corner(0.6, 0.6));
+        let in_box = |point: &CountingPoint| point.coords.iter().all(|&value| (0.5..=0.6).contains(&value));
+
+        for query_point in [corner(0.55, 0.55), corner(0.3, 0.45), corner(0.9, 0.1)].iter() {
+            DISTANCES.with(|count| count.set(0));
+            let boxed = tree.find_n_closest_in_box(query_point, 10, &min, &max).unwrap().into_sorted_vec();
+            let boxed_distances = DISTANCES.with(|count| count.get());
+
+            let mut expected: Vec<f64> = points.iter().filter(|point| in_box(point)).map(|point| point.coords.distance(&query_point.coords).unwrap()).collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(boxed.len(), 10);
+            for (closest, distance) in boxed.iter().zip(expected.iter()) {
+                assert!(in_box(&closest.point));
+                assert!(approx_eq(closest.distance, *distance, 1e-12));
+            }
+
+            // Without the box, enough neighbors have to be found to include the ten inside it
+            let mut sorted = points.clone();
+            sorted.sort_by(|a, b| a.coords.distance(&query_point.coords).unwrap().partial_cmp(&b.coords.distance(&query_point.coords).unwrap()).unwrap());
+            let needed = sorted.iter().enumerate().filter(|(_, point)| in_box(point)).nth(9).unwrap().0 + 1;
+            DISTANCES.with(|count| count.set(0));
+            let filtered: Vec<f64> = tree.k_nearest(query_point, needed).unwrap().into_iter().filter(|closest| in_box(&closest.point)).map(|closest| closest.distance).collect();
+            let unrestricted_distances = DISTANCES.with(|count| count.get());
+            assert_eq!(filtered.len(), 10);
+            if !in_box(query_point) { assert!(boxed_distances * 10 < unrestricted_distances); }
+        }
+
+        // Boxes holding fewer points return all of them
+        let (min, max) = (corner(0.5, 0.5), corner(0.51, 0.51));
+        let count = tree.count_in_range(&min, &max).unwrap();
+        assert!(count < 10);
+        assert_eq!(tree.find_n_closest_in_box(&corner(0.0, 0.0), 10, &min, &max).unwrap().len(), count);
+        assert_eq!(tree.find_n_closest_in_box(&corner(0.5, 0.5), 10, &corner(0.6, 0.6), &corner(0.5, 0.5)).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_nodes() {
+        let mut rng = StdRng::seed_from_u64(189);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::build_from_vec(2, points.clone()).unwrap();
+        tree.remove_point(&points[0]).unwrap();
+        let nodes: Vec<NodeInfo<Vec<f64>>> = tree.nodes().collect();
+
+        assert_eq!(nodes[0].level, 0);
+        assert_eq!(nodes[0].index, 0);
+        assert!(nodes.iter().filter(|node| !node.removed).all(|node| tree.get_point(node.index) == Some(node.point)));
+        assert_eq!(nodes.iter().filter(|node| node.removed).count(), 1);
+        assert_eq!(nodes.iter().filter(|node| !node.removed).count(), tree.len());
+        assert_eq!(nodes.iter().filter(|node| !node.removed).map(|node| node.level).max(), Some(tree.depth()));
+
+        // Children sit one level down on the correct side of the split
+        let by_index = |index: usize| nodes.iter().find(|node| node.index == index).unwrap();
+        for node in nodes.iter() {
+            let split = node.point[node.dimension];
+            if let Some(left) = node.left {
+                assert_eq!(by_index(left).level, node.level + 1);
+                assert!(by_index(left).point[node.dimension] < split);
+            }
+            if let Some(right) = node.right {
+                assert_eq!(by_index(right).level, node.level + 1);
+                assert!(by_index(right).point[node.dimension] >= split);
+            }
+        }
+
+        // Counting points in a box by walking the nodes matches the tree's own count
+        let (min, max) = (vec![0.2, 0.3], vec![0.6, 0.5]);
+        let mut count = 0;
+        let mut stack = vec![nodes[0].index];
+        while let Some(index) = stack.pop() {
+            let node = by_index(index);
+            if !node.removed && (0..2).all(|i| min[i] <= node.point[i] && node.point[i] <= max[i]) { count += 1; }
+            let split = node.point[node.dimension];
+            if min[node.dimension] < split { stack.extend(node.left); }
+            if max[node.dimension] >= split { stack.extend(node.right); }
+        }
+        assert_eq!(count, tree.count_in_range(&min, &max).unwrap());
+    }
+
+    #[test]
+    fn test_k_nearest_to_hyperplane() {
+        let mut rng = StdRng::seed_from_u64(190);
+        let points: Vec<Vec<f64>> = (0..5000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.iter().cloned()).unwrap();
+
+        let hyperplanes = [(vec![1.0, 1.0, 1.0], 1.5), (vec![0.0, 2.0, 0.0], 0.5), (vec![-0.3, 0.7, 0.2], 0.1), (vec![1.0, 0.0, 0.0], -1.0)];
+        for (normal, offset) in hyperplanes.iter() {
+            let norm = normal.iter().map(|n| n * n).sum::<f64>().sqrt();
+            let mut expected: Vec<f64> = points.iter()
+                                               .map(|point| (point.iter().zip(normal.iter()).map(|(x, n)| x * n).sum::<f64>() - offset).abs() / norm)
+                                               .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+            let closest = tree.k_nearest_to_hyperplane(normal, *offset, 20).unwrap();
+            assert_eq!(closest.len(), 20);
+            for (closest, distance) in closest.iter().zip(expected.iter()) {
+                assert!(approx_eq(closest.distance, *distance, 1e-12));
+                assert_eq!(tree.get_point(closest.index().unwrap()), Some(&closest.point));
+            }
+        }
+
+        assert_eq!(tree.k_nearest_to_hyperplane(&vec![0.0, 0.0, 0.0], 1.0, 5).err(), Some(KdError::InvalidInput));
+        assert_eq!(tree.k_nearest_to_hyperplane(&vec![1.0, 0.0], 1.0, 5).err(), Some(KdError::DimensionError));
+        assert!(tree.k_nearest_to_hyperplane(&vec![1.0, 0.0, 0.0], 1.0, 0).unwrap().is_empty());
+        assert_eq!(tree.k_nearest_to_hyperplane(&vec![1.0, 0.0, 0.0], 1.0, 10_000).unwrap().len(), points.len());
+    }
+
+    #[test]
+    fn test_random_splits() {
+        // Each point lies beyond every earlier point along the dimension that level splits on
+        // when cycling x, y, and inside a band along the other, so cycling chains every point
+        let mut rng = StdRng::seed_from_u64(191);
+        let n = 2000;
+        let points: Vec<Vec<f64>> = (0..n).map(|k| {
+            let extreme = if (k / 2) % 2 == 0 { k as f64 } else { (10 * n - k) as f64 };
+            let band = rng.gen_range(2.0 * n as f64, 8.0 * n as f64);
+            if k % 2 == 0 { vec![extreme, band] } else { vec![band, extreme] }
+        }).collect();
+
+        let mut cycling = KdTree::<Vec<f64>, f64>::new(2);
+        cycling.extend(points.iter().cloned()).unwrap();
+        assert_eq!(cycling.depth(), n - 1);
+
+        let mut random = KdTree::<Vec<f64>, f64>::builder().dimensions(2).random_splits(7).build().unwrap();
+        random.extend(points.iter().cloned()).unwrap();
+        assert!(random.depth() < 200, "depth {}", random.depth());
+        assert_eq!(random.validate(), Ok(()));
+
+        // Structure only depends on the seed and the points
+        let mut same = KdTree::<Vec<f64>, f64>::builder().dimensions(2).split_strategy(SplitStrategy::Random(7)).build().unwrap();
+        same.extend(points.iter().cloned()).unwrap();
+        assert_eq!(same.depth(), random.depth());
+        assert!(same.nodes().zip(random.nodes()).all(|(a, b)| a.dimension == b.dimension && a.left == b.left && a.right == b.right));
+
+        for query_point in points.iter().step_by(50) {
+            assert_knn_matches(&random.k_nearest(query_point, 5).unwrap(), &random.brute_force(query_point, 5).unwrap().into_sorted_vec(), 1e-12);
+        }
+
+        // Balanced builds and rebuilds follow the same split dimensions
+        let mut built = KdTree::<Vec<f64>, f64>::builder().random_splits(7).build_from_vec(points.clone()).unwrap();
+        assert_eq!(built.validate(), Ok(()));
+        let cycle = KdTree::<Vec<f64>, f64>::builder().random_splits(7).split_strategy(SplitStrategy::Cycle).build_from_vec(points.clone()).unwrap();
+        assert!(cycle.nodes().all(|node| node.dimension == node.level % 2));
+        built.add_point(vec![0.0, 0.0]).unwrap();
+        built.rebuild();
+        assert_eq!(built.validate(), Ok(()));
+        assert!(KdTree::<Vec<f64>, f64>::builder().random_splits(7).build_index(10, |i| vec![i as f64]).is_err());
+    }
+
+    #[test]
+    fn test_find_closest_in_window() {
+        let mut rng = StdRng::seed_from_u64(192);
+        let points: Vec<Vec<f64>> = (0..5000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen_range(0.0, 100.0)]).collect();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.clone()).unwrap();
+        let spatial_distance = |a: &Vec<f64>, b: &Vec<f64>| ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt();
+
+        for _ in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen_range(0.0, 100.0)];
+            let t_min = rng.gen_range(0.0, 90.0);
+            let t_max = t_min + rng.gen_range(0.0, 10.0);
+
+            let expected = points.iter()
+                                 .filter(|point| t_min <= point[2] && point[2] <= t_max)
+                                 .map(|point| spatial_distance(point, &query_point))
+                                 .fold(f64::INFINITY, f64::min);
+            let (closest, distance) = tree.find_closest_in_window(&query_point, 2, t_min, t_max).unwrap().unwrap();
+            assert!(t_min <= closest[2] && closest[2] <= t_max);
+            assert_eq!(distance, spatial_distance(&closest, &query_point));
+            assert!(approx_eq(distance, expected, 1e-12));
+        }
+
+        // Spatially nearest point is outside the window and is excluded
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(vec![vec![0.0, 0.0, 5.0], vec![1.0, 0.0, 20.0], vec![3.0, 0.0, 15.0], vec![0.1, 0.0, 30.0]]).unwrap();
+        let query_point = vec![0.0, 0.0, 0.0];
+        assert_eq!(tree.find_closest(&query_point).unwrap().0, vec![0.0, 0.0, 5.0]);
+        assert_eq!(tree.find_closest_in_window(&query_point, 2, 10.0, 25.0), Ok(Some((vec![1.0, 0.0, 20.0], 1.0))));
+        assert_eq!(tree.find_closest_in_window(&query_point, 2, 40.0, 50.0), Ok(None));
+        assert_eq!(tree.find_closest_in_window(&query_point, 3, 10.0, 25.0), Err(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_search_distance_calls() {
+        let mut rng = StdRng::seed_from_u64(193);
+        let counting_point = |coords: Vec<f64>| CountingPoint { coords, plane: false };
+        let points: Vec<CountingPoint> = (0..10_000).map(|_| counting_point(vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()])).collect();
+        let tree = KdTree::<CountingPoint, f64>::build_from_vec(3, points).unwrap();
+
+        for _ in 0..100 {
+            let query_point = counting_point(vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]);
+            DISTANCES.with(|count| count.set(0));
+            PLANE_DISTANCES.with(|count| count.set(0));
+            let (_, stats) = tree.find_n_closest_instrumented(&query_point, 10).unwrap();
+
+            // Every visited node is measured exactly once, and split planes at most once per node
+            assert_eq!(DISTANCES.with(|count| count.get()), stats.nodes_visited);
+            assert!(PLANE_DISTANCES.with(|count| count.get()) <= stats.nodes_visited);
+        }
+
+        // Nodes of a chain have no sub tree on the far side of their split to measure the plane to
+        let mut chain = KdTree::<CountingPoint, f64>::new(1);
+        chain.extend((0..200).map(|i| counting_point(vec![i as f64]))).unwrap();
+        DISTANCES.with(|count| count.set(0));
+        PLANE_DISTANCES.with(|count| count.set(0));
+        let (closest, stats) = chain.find_n_closest_instrumented(&counting_point(vec![500.0]), 3).unwrap();
+        assert_eq!(closest[0].point.coords, vec![199.0]);
+        assert_eq!(DISTANCES.with(|count| count.get()), stats.nodes_visited);
+        assert_eq!(PLANE_DISTANCES.with(|count| count.get()), 0);
+    }
+
+    #[test]
+    fn test_f32_array_point() {
+        let mut rng = StdRng::seed_from_u64(194);
+        let random_point = |rng: &mut StdRng| {
+            let mut point = [0f32; 13];
+            for value in point.iter_mut() { *value = rng.gen::<f32>(); }
+            point
+        };
+        let points: Vec<[f32; 13]> = (0..2000).map(|_| random_point(&mut rng)).collect();
+        let tree = KdTree::<[f32; 13], f32>::build_from_vec(13, points).unwrap();
+        assert_eq!(<[f32; 13] as Point<f32>>::DIMS, Some(13));
+
+        for _ in 0..50 {
+            let query_point = random_point(&mut rng);
+            let kd_search = tree.k_nearest(&query_point, 5).unwrap();
+            let brute_search = tree.brute_force(&query_point, 5).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_build_from_vec_with_progress() {
+        let mut rng = StdRng::seed_from_u64(195);
+        let points: Vec<Vec<f64>> = (0..5_000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+
+        let mut reports = Vec::new();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec_with_progress(3, points.clone(), |done, total| reports.push((done, total))).unwrap();
+
+        // Progress is reported in increasing steps ending with every point placed
+        assert!(reports.len() > 1 && reports.len() <= 101);
+        assert!(reports.iter().all(|&(_, total)| total == points.len()));
+        assert!(reports.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(reports.last(), Some(&(points.len(), points.len())));
+
+        for _ in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let kd_search = tree.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+            let brute_search = tree.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-12);
+        }
+
+        let mut reports = Vec::new();
+        let tree = KdTree::<Vec<f64>, f64>::build_from_vec_with_progress(3, Vec::new(), |done, total| reports.push((done, total))).unwrap();
+        assert_eq!(tree.len(), 0);
+        assert_eq!(reports, vec![(0, 0)]);
+
+        let bad_points = vec![vec![0f64, 0f64, 0f64], vec![0f64, 0f64]];
+        assert!(KdTree::<Vec<f64>, f64>::build_from_vec_with_progress(3, bad_points, |_, _| {}).err() == Some(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_worst_distance() {
+        let mut rng = StdRng::seed_from_u64(196);
+        let points: Vec<Vec<f64>> = (0..12).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points.iter().cloned()).unwrap();
+
+        // Until the heap holds n points anything can still be kept, so nothing is pruned
+        assert_eq!(tree.worst_distance(&std::collections::BinaryHeap::<Closest<Vec<f64>, f64>>::new(), 3), f64::INFINITY);
+        assert_eq!(tree.worst_distance(&std::collections::BinaryHeap::<Closest<Vec<f64>, f64>>::new(), 0), f64::NEG_INFINITY);
+        let query_point = vec![0.5, 0.5];
+        let closest = tree.find_n_closest(&query_point, 3).unwrap();
+        let farthest = closest.iter().fold(0f64, |farthest, closest| farthest.max(closest.distance));
+        assert_eq!(tree.worst_distance(&closest, 3), farthest);
+        assert_eq!(tree.worst_distance(&closest, 4), f64::INFINITY);
+
+        // Searches that keep the heap from filling still visit every sub tree
+        for n in 0..=points.len() + 2 {
+            for _ in 0..20 {
+                let query_point = vec![rng.gen_range(-1.0, 2.0), rng.gen_range(-1.0, 2.0)];
+                let kd_search = tree.find_n_closest(&query_point, n).unwrap().into_sorted_vec();
+                let brute_search = tree.brute_force(&query_point, n).unwrap().into_sorted_vec();
+                assert_eq!(kd_search.len(), n.min(points.len()));
+                assert_knn_matches(&kd_search, &brute_search, 1e-12);
+            }
+        }
+        assert_eq!(tree.kth_nearest_distance(&query_point, 0), Err(KdError::BinaryHeapError));
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let mut rng = StdRng::seed_from_u64(197);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.iter().cloned()).unwrap();
+        for point in points.iter().take(100) { tree.remove_point(point).unwrap(); }
+        let queries: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let expected: Vec<Vec<Closest<Vec<f64>, f64>>> = queries.iter().map(|query_point| tree.k_nearest(query_point, 5).unwrap()).collect();
+        let len = tree.len();
+
+        // Parts survive a round trip through a custom format
+        let (num_dimensions, max_levels, nodes) = tree.into_parts();
+        assert_eq!(num_dimensions, 3);
+        assert!(nodes[0].is_none());
+        assert_eq!(nodes[1].as_ref().map(|node| node.child_type), Some(NodeType::RootNode));
+        // Point, child type code, parent, left child, right child, dimension, level and removed flag
+        type StoredNode = (Vec<f64>, u8, usize, usize, usize, usize, usize, bool);
+        let stored: Vec<Option<StoredNode>> = nodes.into_iter().map(|slot| slot.map(|node| {
+            let child_type = match node.child_type { NodeType::RootNode => 0, NodeType::LeftChild => 1, NodeType::RightChild => 2 };
+            (node.point, child_type, node.parent, node.left_child, node.right_child, node.dimension, node.level, node.removed)
+        })).collect();
+        let nodes: Vec<Option<RawNode<Vec<f64>>>> = stored.into_iter().map(|slot| slot.map(|(point, child_type, parent, left_child, right_child, dimension, level, removed)| RawNode {
+            point,
+            child_type: [NodeType::RootNode, NodeType::LeftChild, NodeType::RightChild][child_type as usize],
+            parent, left_child, right_child, dimension, level, removed,
+        })).collect();
+
+        let mut tree = KdTree::<Vec<f64>, f64>::from_parts(num_dimensions, max_levels, nodes.clone()).unwrap();
+        assert_eq!(tree.len(), len);
+        assert!(tree.validate().is_ok());
+        for (query_point, expected) in queries.iter().zip(expected.iter()) {
+            assert_knn_matches(&tree.k_nearest(query_point, 5).unwrap(), expected, 0.0);
+        }
+        tree.add_point(vec![0.5, 0.5, 0.5]).unwrap();
+        assert_eq!(tree.find_closest(&vec![0.5, 0.5, 0.5]).unwrap().1, 0.0);
+
+        // Inconsistent structures are rejected
+        let mut unlinked = nodes.clone();
+        let child = unlinked[1].as_ref().unwrap().left_child;
+        unlinked[1].as_mut().unwrap().left_child = 0;
+        assert!(KdTree::<Vec<f64>, f64>::from_parts(3, max_levels, unlinked).err() == Some(KdError::NodeMissing));
+        let mut swapped = nodes.clone();
+        swapped[child].as_mut().unwrap().child_type = NodeType::RightChild;
+        assert!(KdTree::<Vec<f64>, f64>::from_parts(3, max_levels, swapped).err() == Some(KdError::NodeMissing));
+        let mut moved = nodes.clone();
+        moved[child].as_mut().unwrap().point[0] = 2.0;
+        assert!(KdTree::<Vec<f64>, f64>::from_parts(3, max_levels, moved).err() == Some(KdError::NodeMissing));
+        let mut dangling = nodes.clone();
+        dangling[child].as_mut().unwrap().parent = nodes.len() + 10;
+        assert!(KdTree::<Vec<f64>, f64>::from_parts(3, max_levels, dangling).err() == Some(KdError::NodeMissing));
+        assert!(KdTree::<Vec<f64>, f64>::from_parts(3, 0, nodes.clone()).err() == Some(KdError::NodeMissing));
+        assert!(KdTree::<Vec<f64>, f64>::from_parts(2, max_levels, nodes).err() == Some(KdError::DimensionError));
+
+        let empty = KdTree::<Vec<f64>, f64>::from_parts(3, 0, Vec::new()).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn test_nearest_within() {
+        let mut rng = StdRng::seed_from_u64(198);
+        let counting_point = |coords: Vec<f64>| CountingPoint { coords, plane: false };
+        let points: Vec<CountingPoint> = (0..10_000).map(|_| counting_point(vec![rng.gen::<f64>(), rng.gen::<f64>()])).collect();
+        let tree = KdTree::<CountingPoint, f64>::build_from_vec(2, points).unwrap();
+
+        for _ in 0..100 {
+            let query_point = counting_point(vec![rng.gen::<f64>(), rng.gen::<f64>()]);
+            let (closest, distance) = tree.find_closest(&query_point).unwrap();
+            for &max_dist in [distance * 2.0, distance * (1.0 + 1e-9)].iter() {
+                let (within, within_distance) = tree.nearest_within(&query_point, max_dist).unwrap().unwrap();
+                assert!(within == closest && approx_eq(within_distance, distance, 1e-12));
+            }
+            assert!(tree.nearest_within(&query_point, distance * 0.99).unwrap().is_none());
+        }
+
+        // Nothing near the query is found without visiting most of the tree
+        DISTANCES.with(|count| count.set(0));
+        assert!(tree.nearest_within(&counting_point(vec![5.0, 5.0]), 1.0).unwrap().is_none());
+        assert!(DISTANCES.with(|count| count.get()) < 100);
+    }
+
+    #[test]
+    fn test_bulk_remove_indices() {
+        let mut rng = StdRng::seed_from_u64(199);
+        let points: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<Vec<f64>, f64>::new(3);
+        tree.extend(points.iter().cloned()).unwrap();
+
+        // Points added one at a time are indexed in insertion order
+        let mut indices: Vec<usize> = (0..points.len()).step_by(2).collect();
+        indices.push(0);
+        indices.push(points.len());
+        indices.push(usize::MAX);
+        assert_eq!(tree.bulk_remove_indices(&indices), points.len() / 2);
+        assert_eq!(tree.bulk_remove_indices(&indices), 0);
+        assert_eq!(tree.len(), points.len() / 2);
+        assert!(tree.validate().is_ok());
+
+        for (i, point) in points.iter().enumerate() {
+            let (_, distance) = tree.find_closest(point).unwrap();
+            assert_eq!(distance == 0.0, i % 2 == 1);
+        }
+        for _ in 0..100 {
+            let query_point = vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()];
+            let kd_search = tree.find_n_closest(&query_point, 10).unwrap().into_sorted_vec();
+            let brute_search = tree.brute_force(&query_point, 10).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_project() {
+        let point = vec![0.0, 1.0, 2.0, 3.0, 4.0];
+        assert_eq!(point.project(&[0, 2, 4]), Ok(vec![0.0, 2.0, 4.0]));
+        assert_eq!(point.project(&[4, 0]), Ok(vec![4.0, 0.0]));
+        assert_eq!(point.project(&[5]), Err(KdError::DimensionError));
+        #[cfg(feature="default")]
+        assert_eq!(super::Array1::from(point.clone()).project(&[0, 2, 4]), Ok(super::Array1::from(vec![0.0, 2.0, 4.0])));
+        assert_eq!(vec![0f32, 1.0, 2.0, 3.0, 4.0].project(&[0, 2, 4]), Ok(vec![0f32, 2.0, 4.0]));
+
+        // Fixed size points can only reorder their dimensions
+        let array = [0.0, 1.0, 2.0, 3.0, 4.0];
+        assert_eq!(array.project(&[0, 2, 4]), Err(KdError::DimensionError));
+        assert_eq!(array.project(&[4, 3, 2, 1, 0]), Ok([4.0, 3.0, 2.0, 1.0, 0.0]));
+
+        // A tree over projected points finds the same neighbors as one over the chosen dimensions
+        let mut rng = StdRng::seed_from_u64(200);
+        let points: Vec<Vec<f64>> = (0..1000).map(|_| (0..5).map(|_| rng.gen::<f64>()).collect()).collect();
+        let dims = [0, 2, 4];
+        let projected = KdTree::<Vec<f64>, f64>::build_from_vec(3, points.iter().map(|point| point.project(&dims).unwrap()).collect()).unwrap();
+        let mut masked = KdTree::<Vec<f64>, f64>::with_active_dims(5, vec![true, false, true, false, true]).unwrap();
+        masked.extend(points.iter().cloned()).unwrap();
+        for _ in 0..50 {
+            let query_point: Vec<f64> = (0..5).map(|_| rng.gen::<f64>()).collect();
+            let kd_search = projected.k_nearest(&query_point.project(&dims).unwrap(), 5).unwrap();
+            let masked_search = masked.k_nearest(&query_point, 5).unwrap();
+            assert_knn_matches(&kd_search, &masked_search, 1e-12);
+        }
+    }
+
+    #[cfg(feature="std")]
+    #[test]
+    fn test_query_cache() {
+        let mut rng = StdRng::seed_from_u64(201);
+        let counting_point = |coords: Vec<f64>| CountingPoint { coords, plane: false };
+        let mut tree = KdTree::<CountingPoint, f64>::builder().dimensions(2).query_cache(2, 1e-3).build().unwrap();
+        tree.extend((0..1000).map(|_| counting_point(vec![rng.gen::<f64>(), rng.gen::<f64>()]))).unwrap();
+        let comparisons = || COMPARISONS.with(|count| count.replace(0));
+
+        // Queries rounding to the same grid cell are answered without searching the tree
+        let query_point = counting_point(vec![0.3, 0.7]);
+        comparisons();
+        let (closest, distance) = tree.find_closest(&query_point).unwrap();
+        assert!(comparisons() > 0);
+        let (cached, cached_distance) = tree.find_closest(&query_point).unwrap();
+        assert!(comparisons() == 0 && cached == closest && approx_eq(cached_distance, distance, 1e-12));
+        let nearby = counting_point(vec![0.30001, 0.70001]);
+        let (cached, cached_distance) = tree.find_closest(&nearby).unwrap();
+        assert!(comparisons() == 0 && cached == closest);
+        assert_eq!(cached_distance, closest.distance(&nearby).unwrap());
+
+        // Least recently used queries are forgotten once the cache is full
+        tree.find_closest(&counting_point(vec![0.1, 0.1])).unwrap();
+        tree.find_closest(&query_point).unwrap();
+        tree.find_closest(&counting_point(vec![0.9, 0.9])).unwrap();
+        comparisons();
+        tree.find_closest(&query_point).unwrap();
+        assert_eq!(comparisons(), 0);
+        tree.find_closest(&counting_point(vec![0.1, 0.1])).unwrap();
+        assert!(comparisons() > 0);
+
+        // Changing points of the tree clears the cache
+        tree.add_point(query_point.clone()).unwrap();
+        assert_eq!(tree.find_closest(&query_point).unwrap().1, 0.0);
+        tree.remove_point(&query_point).unwrap();
+        comparisons();
+        let (uncached, uncached_distance) = tree.find_closest(&query_point).unwrap();
+        assert!(comparisons() > 0 && uncached == closest && uncached_distance == distance);
+
+        // Queries are validated before the cache is consulted
+        let mut cosine = KdTree::<Vec<f64>, f64>::builder().dimensions(2).metric(Metric::Cosine).validate_inputs(true).query_cache(2, 1e-3).build().unwrap();
+        cosine.extend(vec![vec![1.0, 0.0], vec![0.0, 1.0]]).unwrap();
+        cosine.find_closest(&vec![1.0, 0.0]).unwrap();
+        assert!(cosine.find_closest(&vec![1.0002, 0.0]).err() == Some(KdError::InvalidInput));
+        assert!(cosine.find_closest(&vec![1.0, 0.0, 0.0]).err() == Some(KdError::DimensionError));
+        assert!(cosine.find_closest(&vec![f64::NAN, 0.0]).is_err());
+
+        // Ties are found by searching the tree, even when a coarse cache answers find_closest wrongly
+        let coarse = KdTree::<Vec<f64>, f64>::builder().query_cache(2, 10.0).build_from_vec(vec![vec![0.0, 0.0], vec![1.0, 0.0], vec![3.0, 0.0]]).unwrap();
+        assert_eq!(coarse.find_closest(&vec![0.9, 0.0]).unwrap().0, vec![1.0, 0.0]);
+        assert_eq!(coarse.find_closest(&vec![0.1, 0.0]).unwrap().0, vec![1.0, 0.0]);
+        assert_eq!(coarse.find_all_closest(&vec![0.1, 0.0]).unwrap(), vec![vec![0.0, 0.0]]);
+
+        assert!(KdTree::<Vec<f64>, f64>::builder().query_cache(0, 1e-3).build().err() == Some(KdError::InvalidConfiguration));
+        assert!(KdTree::<Vec<f64>, f64>::builder().query_cache(8, 0.0).build().err() == Some(KdError::InvalidConfiguration));
+    }
+
+    #[test]
+    fn test_cow_point() {
+        use std::borrow::Cow;
+
+        let mut rng = StdRng::seed_from_u64(202);
+        let coords: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        // Borrowed queries have to live as long as the points borrowed by the tree
+        let queries: Vec<Vec<f64>> = (0..50).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let tree = KdTree::<Cow<[f64]>, f64>::build_from_vec(3, coords.iter().map(|coords| Cow::Borrowed(coords.as_slice())).collect()).unwrap();
+        let mut vec_tree = KdTree::<Vec<f64>, f64>::new(3);
+        vec_tree.extend(coords.iter().cloned()).unwrap();
+
+        for (i, query_coords) in queries.iter().enumerate() {
+            let expected = vec_tree.k_nearest(query_coords, 5).unwrap();
+            let query_point: Cow<[f64]> = if i % 2 == 0 { Cow::Borrowed(query_coords.as_slice()) } else { Cow::Owned(query_coords.clone()) };
+            let kd_search = tree.k_nearest(&query_point, 5).unwrap();
+            assert!(kd_search.iter().all(|closest| matches!(closest.point, Cow::Borrowed(_))));
+            assert_eq!(kd_search.len(), expected.len());
+            assert!(kd_search.iter().zip(expected.iter()).all(|(closest, expected)| approx_eq(closest.distance, expected.distance, 1e-12)));
+        }
+
+        // Setting a coordinate of a borrowed point only changes a copy
+        let mut point: Cow<[f64]> = Cow::Borrowed(coords[0].as_slice());
+        point.set_coord(1, 5.0);
+        assert_eq!(point.coord(1), 5.0);
+        assert!(coords[0][1] != 5.0 && matches!(point, Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_find_n_closest_lenient() {
+        /// Point whose distance can't be measured once it is corrupt
+        #[derive(Clone)]
+        struct FlakyPoint {
+            coords: Vec<f64>,
+            corrupt: bool,
+        }
+
+        impl Point<f64> for FlakyPoint {
+            fn distance(&self, other: &Self) -> Result<f64, KdError> {
+                if self.corrupt || other.corrupt { return Err(KdError::InvalidInput); }
+                self.coords.distance(&other.coords)
+            }
+
+            fn coord(&self, cur_dimension: usize) -> f64 { self.coords[cur_dimension] }
+
+            fn set_coord(&mut self, cur_dimension: usize, value: f64) { self.coords[cur_dimension] = value; }
+
+            fn dimensions(&self) -> usize { self.coords.len() }
+        }
+
+        let mut rng = StdRng::seed_from_u64(203);
+        let coords: Vec<Vec<f64>> = (0..2000).map(|_| vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<FlakyPoint, f64>::new(3);
+        tree.extend(coords.iter().map(|coords| FlakyPoint { coords: coords.clone(), corrupt: false })).unwrap();
+        let mut clean = KdTree::<Vec<f64>, f64>::new(3);
+        clean.extend(coords.iter().cloned()).unwrap();
+
+        // Corrupt point right by the query, so every search for its neighbors measures it
+        let query_coords = vec![0.5, 0.5, 0.5];
+        tree.add_point(FlakyPoint { coords: vec![0.5, 0.5, 0.501], corrupt: true }).unwrap();
+        let query_point = FlakyPoint { coords: query_coords.clone(), corrupt: false };
+        assert!(tree.find_n_closest(&query_point, 10).err() == Some(KdError::InvalidInput));
+
+        let (closest, skipped) = tree.find_n_closest_lenient(&query_point, 10).unwrap();
+        assert_eq!(skipped, 1);
+        let closest = closest.into_sorted_vec();
+        assert!(closest.iter().all(|closest| !closest.point.corrupt));
+        let expected = clean.k_nearest(&query_coords, 10).unwrap();
+        assert_eq!(closest.len(), expected.len());
+        assert!(closest.iter().zip(expected.iter()).all(|(closest, expected)| approx_eq(closest.distance, expected.distance, 1e-12)));
+
+        assert!(tree.find_n_closest_lenient(&FlakyPoint { coords: vec![0.5, 0.5], corrupt: false }, 10).err() == Some(KdError::DimensionError));
+
+        // Short point splitting on a dimension it lacks is skipped rather than indexed
+        let flaky = |coords: Vec<f64>| FlakyPoint { coords, corrupt: false };
+        let mut tree = KdTree::<FlakyPoint, f64>::new(3);
+        tree.add_point(flaky(vec![0.5, 0.5, 0.5])).unwrap();
+        tree.add_point(flaky(vec![0.9, 0.5, 0.5])).unwrap();
+        unsafe { tree.add_point_unchecked(flaky(vec![0.9, 0.1])); }
+        tree.add_point(flaky(vec![0.8, 0.8, 0.8])).unwrap();
+        let (closest, skipped) = tree.find_n_closest_lenient(&flaky(vec![0.9, 0.9, 0.9]), 2).unwrap();
+        assert_eq!(skipped, 1);
+        let closest = closest.into_sorted_vec();
+        assert!(closest.len() == 2 && closest[0].point.coords == vec![0.8, 0.8, 0.8] && closest[1].point.coords == vec![0.9, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_reserved_capacity() {
+        // Storage is reserved without filling it, so only the reserved slot and the root's exist
+        let capacity = 5_000_000;
+        let tree = KdTree::<[f64; 2], f64>::with_capacity(2, capacity);
+        assert_eq!(tree.len(), 0);
+        assert!(tree.capacity() >= capacity);
+        let (_, _, nodes) = tree.into_parts();
+        assert_eq!(nodes.len(), 2);
+        assert!(nodes.iter().all(|node| node.is_none()));
+
+        let mut rng = StdRng::seed_from_u64(204);
+        let points: Vec<[f64; 2]> = (0..1000).map(|_| [rng.gen::<f64>(), rng.gen::<f64>()]).collect();
+        let mut tree = KdTree::<[f64; 2], f64>::with_capacity(2, capacity);
+        tree.extend(points.iter().cloned()).unwrap();
+        assert_eq!(tree.len(), points.len());
+        assert!(tree.capacity() >= capacity);
+        assert!(tree.validate().is_ok());
+        for _ in 0..100 {
+            let query_point = [rng.gen::<f64>(), rng.gen::<f64>()];
+            let kd_search = tree.find_n_closest(&query_point, 5).unwrap().into_sorted_vec();
+            let brute_search = tree.brute_force(&query_point, 5).unwrap().into_sorted_vec();
+            assert_knn_matches(&kd_search, &brute_search, 1e-12);
+        }
+
+        // Points added after a balanced build use the slot it keeps free, then grow storage
+        let mut tree = KdTree::<[f64; 2], f64>::build_from_vec(2, points.clone()).unwrap();
+        tree.extend((0..10).map(|i| [i as f64, 0.5])).unwrap();
+        assert_eq!(tree.len(), points.len() + 10);
+        assert!(tree.validate().is_ok());
+        assert_eq!(tree.find_closest(&[9.0, 0.5]).unwrap().1, 0.0);
+    }
+
+    #[cfg(feature="default")]
+    #[test]
+    fn test_kd_tree_ref() {
+        use ndarray::{Array1, Array2, Axis};
+
+        let mut rng = StdRng::seed_from_u64(205);
+        let rows = Array2::from_shape_fn((2000, 3), |_| rng.gen::<f64>());
+        let tree = KdTreeRef::new(rows.view()).unwrap();
+        assert_eq!(tree.len(), 2000);
+        assert_eq!(tree.get_num_dimensions(), 3);
+        let mut owned = KdTree::<Array1<f64>, f64>::new(3);
+        owned.extend(rows.axis_iter(Axis(0)).map(|row| row.to_owned())).unwrap();
+
+        // Rows of a transposed array aren't contiguous, so they are copied as they are read
+        let columns = Array2::from_shape_fn((3, 2000), |(i, j)| rows[[j, i]]);
+        let transposed = KdTreeRef::new(columns.t()).unwrap();
+
+        for _ in 0..100 {
+            let query_point = Array1::from(vec![rng.gen::<f64>(), rng.gen::<f64>(), rng.gen::<f64>()]);
+            let expected = owned.k_nearest(&query_point, 10).unwrap();
+            for closest in [tree.find_n_closest(query_point.view(), 10).unwrap(), transposed.find_n_closest(query_point.view(), 10).unwrap()].iter() {
+                assert_eq!(closest.len(), expected.len());
+                for (closest, expected) in closest.iter().zip(expected.iter()) {
+                    assert!(approx_eq(closest.distance, expected.distance, 1e-12));
+                    assert!(approx_eq((&rows.row(closest.point) - &query_point).mapv(|diff| diff * diff).sum().sqrt(), closest.distance, 1e-12));
+                }
+            }
+        }
+
+        let bad_query = Array1::from(vec![0.5, 0.5]);
+        assert!(tree.find_n_closest(bad_query.view(), 10).err() == Some(KdError::DimensionError));
+    }
+
+    #[test]
+    fn test_ties_broken_by_index() {
+        // Every point but the first and last is at distance 1 from the origin
+        let points = vec![vec![0.0, 0.0], vec![0.0, 1.0], vec![-1.0, 0.0], vec![1.0, 0.0], vec![0.0, -1.0], vec![2.0, 2.0]];
+        let mut tree = KdTree::<Vec<f64>, f64>::new(2);
+        tree.extend(points).unwrap();
+
+        for (query_point, expected) in [(vec![0.0, 0.0], vec![0, 1, 2]), (vec![2.0, 2.0], vec![5, 1])].iter() {
+            let closest = tree.find_n_closest(query_point, expected.len()).unwrap().into_sorted_vec();
+            assert_eq!(closest.iter().map(|closest| closest.index().unwrap()).collect::<Vec<usize>>(), *expected);
+        }
+    }
 }
@@ -1,20 +1,97 @@
 extern crate pyo3;
-use crate::kd_tree::{KdTree, KdError};
+use crate::kd_tree::{KdTree, KdError, Closest, Point};
 
 use numpy::{PyArray1, PyArray2};
 use pyo3::prelude::*;
 use pyo3::{PyResult, exceptions, Python};
-use ndarray::{Array1, Axis, Array2};
+use ndarray::{Array1, ArrayView1, Axis, Array2};
+use std::collections::BinaryHeap;
 
 impl From<KdError> for PyErr {
     fn from(err: KdError) -> PyErr {
-        PyErr::new::<exceptions::TypeError, _>(err.to_string())
+        match err {
+            KdError::EmptyTree => PyErr::new::<exceptions::ValueError, _>("tree is empty"),
+            KdError::DimensionError => PyErr::new::<exceptions::ValueError, _>("point dimensionality doesn't match tree"),
+            _ => PyErr::new::<exceptions::TypeError, _>(err.to_string()),
+        }
+    }
+}
+
+/// Point types a Python tree can be backed by
+trait PyPoint: Point<f64> + Clone {
+    /// Create point from coordinates with the tree's dimensionality
+    fn from_array(coords: Array1<f64>) -> Self;
+
+    /// Create point from borrowed coordinates with the tree's dimensionality
+    fn from_view(coords: ArrayView1<f64>) -> Self;
+
+    /// Find n closest points to borrowed query coordinates
+    fn find_n_closest(tree: &KdTree<Self, f64>, query_point: ArrayView1<f64>, n: usize) -> Result<BinaryHeap<Closest<Self, f64>>, KdError> {
+        if query_point.len() != tree.get_num_dimensions() { return Err(KdError::DimensionError); }
+        tree.find_n_closest(&Self::from_view(query_point), n)
+    }
+}
+
+impl PyPoint for Array1<f64> {
+    fn from_array(coords: Array1<f64>) -> Self { coords }
+
+    fn from_view(coords: ArrayView1<f64>) -> Self { coords.to_owned() }
+
+    fn find_n_closest(tree: &KdTree<Self, f64>, query_point: ArrayView1<f64>, n: usize) -> Result<BinaryHeap<Closest<Self, f64>>, KdError> {
+        tree.find_n_closest_view(query_point, n)
+    }
+}
+
+impl<const N: usize> PyPoint for [f64; N] {
+    fn from_array(coords: Array1<f64>) -> Self { Self::from_view(coords.view()) }
+
+    fn from_view(coords: ArrayView1<f64>) -> Self {
+        let mut point = [0f64; N];
+        for (value, coord) in point.iter_mut().zip(coords.iter()) {
+            *value = *coord;
+        }
+        point
+    }
+}
+
+/// Tree backing a Python tree
+///
+/// Common small dimensionalities store points in fixed size arrays, which keeps each point inline
+/// in its node instead of in a separate heap allocation.
+enum Backing {
+    Dynamic(KdTree<Array1<f64>, f64>),
+    Fixed2(KdTree<[f64; 2], f64>),
+    Fixed3(KdTree<[f64; 3], f64>),
+    Fixed4(KdTree<[f64; 4], f64>),
+}
+
+/// Run body with tree bound to whichever tree backs a Python tree
+macro_rules! with_tree {
+    ($backing:expr, $tree:ident => $body:expr) => {
+        match $backing {
+            Backing::Dynamic($tree) => $body,
+            Backing::Fixed2($tree) => $body,
+            Backing::Fixed3($tree) => $body,
+            Backing::Fixed4($tree) => $body,
+        }
+    };
+}
+
+impl Backing {
+    /// Create the tree backing points with dimensions, with storage for capacity points
+    fn with_capacity(dimensions: usize, capacity: usize) -> Self {
+        match dimensions {
+            2 => Backing::Fixed2(KdTree::with_capacity(2, capacity)),
+            3 => Backing::Fixed3(KdTree::with_capacity(3, capacity)),
+            4 => Backing::Fixed4(KdTree::with_capacity(4, capacity)),
+            _ => Backing::Dynamic(KdTree::with_capacity(dimensions, capacity)),
+        }
     }
 }
 
 #[pyclass]
 pub struct Tree {
-    tree: KdTree<Array1<f64>, f64>,
+    tree: Backing,
 }
 
 #[pymethods]
@@ -22,63 +99,169 @@ impl Tree {
     #[new]
     fn new(dimensions: usize, num_nodes: usize) -> Self {
         Tree {
-            tree: KdTree::with_capacity(dimensions, num_nodes),
+            tree: Backing::with_capacity(dimensions, num_nodes),
         }
     }
 
     #[new]
     fn create_tree(points: &PyArray2<f64>) -> PyResult<Self> {
         let shape = points.shape();
-        let mut tree = Tree { tree: KdTree::with_capacity(shape[1], shape[0]) };
+        let mut tree = Tree { tree: Backing::with_capacity(shape[1], shape[0]) };
         match tree.add_points(points) {
             Ok(()) => { Ok(tree) },
             Err(e) => { Err(e) },
         }
     }
 
-    fn add_point(&mut self, point: &PyArray1<f64>) -> PyResult<()> {
-        match self.tree.add_point(point.to_owned_array()) {
-            Ok(()) => { Ok(()) },
-            Err(e) => { Err(PyErr::from(e)) },
-        }
+    fn reserve(&mut self, n: usize) {
+        // Presize storage so adding n more points one at a time doesn't reallocate
+        with_tree!(&mut self.tree, tree => tree.reserve_exact(n));
+    }
+
+    fn add_point(&mut self, point: &PyAny) -> PyResult<()> {
+        let point = self.to_point(point)?;
+        with_tree!(&mut self.tree, tree => tree.add_point(PyPoint::from_array(point))?);
+
+        Ok(())
     }
 
     fn add_points(&mut self, points: &PyArray2<f64>) -> PyResult<()> {
-        for point in points.to_owned_array().axis_iter(Axis(0)) {
-            self.tree.add_point(point.to_owned())?;
+        // Rows are copied into fixed size points without checking their length
+        let dimensions = self.num_dimensions();
+        if dimensions != 0 && points.shape()[1] != dimensions { return Err(PyErr::from(KdError::DimensionError)); }
+
+        // Iterate rows of a view so C and Fortran ordered arrays are read in place, only copying
+        // each row into the point stored by the tree
+        for point in points.as_array().axis_iter(Axis(0)) {
+            with_tree!(&mut self.tree, tree => tree.add_point(PyPoint::from_view(point))?);
         }
 
         Ok(())
     }
 
-    fn find_closest(&self, query_point: &PyArray1<f64>) -> PyResult<(Py<PyArray1<f64>>, f64)> {
-        match self.tree.find_closest(&query_point.as_array().to_owned()) {
-            Ok((point, distance)) => {
-                let gil = Python::acquire_gil();
-                Ok((PyArray1::from_owned_array(gil.python(), point).to_owned(), distance)) 
-            },
-            Err(e) => { Err(PyErr::from(e)) },
-        }
+    fn find_closest(&self, query_point: &PyAny) -> PyResult<(Py<PyArray1<f64>>, f64)> {
+        let query_point = self.to_point(query_point)?;
+        let (point, distance) = with_tree!(&self.tree, tree => {
+            let (point, distance) = tree.find_closest(&PyPoint::from_array(query_point))?;
+            (Array1::from_shape_fn(point.dimensions(), |j| point.coord(j)), distance)
+        });
+
+        let gil = Python::acquire_gil();
+        Ok((PyArray1::from_owned_array(gil.python(), point).to_owned(), distance))
     }
 
     fn find_n_closest(&self, query_point: &PyArray1<f64>, n: usize) -> PyResult<(Py<PyArray2<f64>>, Py<PyArray1<f64>>)> {
-        match self.tree.find_n_closest(&query_point.as_array().to_owned(), n) {
-            Ok(pairs) => {
-                let gil = Python::acquire_gil();
-                let mut closest_points = Array2::<f64>::zeros((pairs.len(), self.tree.get_num_dimensions()));
-                let mut distances = Array1::<f64>::zeros(pairs.len());
-                for (i, pair) in pairs.iter().enumerate() {
-                    let mut cur_point = closest_points.index_axis_mut(Axis(0), i);
-                    for (j, val) in pair.point.iter().enumerate() {
-                        cur_point[j] = *val;
-                    }
-                    distances[i] = pair.distance;
+        let dimensions = self.num_dimensions();
+        let (closest_points, distances) = with_tree!(&self.tree, tree => {
+            let pairs = PyPoint::find_n_closest(tree, query_point.as_array(), n)?;
+            let mut closest_points = Array2::<f64>::zeros((pairs.len(), dimensions));
+            let mut distances = Array1::<f64>::zeros(pairs.len());
+            for (i, pair) in pairs.iter().enumerate() {
+                let mut cur_point = closest_points.index_axis_mut(Axis(0), i);
+                for (j, value) in cur_point.iter_mut().enumerate() {
+                    *value = pair.point.coord(j);
+                }
+                distances[i] = pair.distance;
+            }
+            (closest_points, distances)
+        });
+
+        let gil = Python::acquire_gil();
+        Ok((PyArray2::from_owned_array(gil.python(), closest_points).to_owned(), PyArray1::from_owned_array(gil.python(), distances).to_owned()))
+    }
+
+    #[args(sort = "true")]
+    fn query(&self, queries: &PyArray2<f64>, k: usize, sort: bool) -> PyResult<(Py<PyArray2<i64>>, Py<PyArray2<f64>>)> {
+        let queries = queries.as_array();
+        let mut indices = Array2::<i64>::zeros((queries.nrows(), k));
+        let mut distances = Array2::<f64>::zeros((queries.nrows(), k));
+        for (i, query_point) in queries.axis_iter(Axis(0)).enumerate() {
+            let neighbors = with_tree!(&self.tree, tree => {
+                // Search breaks ties by index, so sorting puts results nearest first in a
+                // reproducible order
+                let found = PyPoint::find_n_closest(tree, query_point, k)?;
+                let found = if sort { found.into_sorted_vec() } else { found.into_vec() };
+
+                // Points found in a tree always know their node, so a missing index is a missing node
+                found.iter()
+                    .map(|closest| closest.index().map(|index| (index, closest.distance)).ok_or(KdError::NodeMissing))
+                    .collect::<Result<Vec<(usize, f64)>, KdError>>()?
+            });
+            if neighbors.len() < k {
+                return Err(PyErr::new::<exceptions::ValueError, _>("k must not exceed the number of points in tree"));
+            }
+
+            for (j, (index, distance)) in neighbors.into_iter().enumerate() {
+                indices[[i, j]] = index as i64;
+                distances[[i, j]] = distance;
+            }
+        }
+
+        let gil = Python::acquire_gil();
+        Ok((PyArray2::from_owned_array(gil.python(), indices).to_owned(), PyArray2::from_owned_array(gil.python(), distances).to_owned()))
+    }
+
+    fn to_array(&self) -> PyResult<Py<PyArray2<f64>>> {
+        // Points added one at a time are stored in insertion order
+        let dimensions = self.num_dimensions();
+        let points = with_tree!(&self.tree, tree => {
+            let mut points = Array2::<f64>::zeros((tree.len(), dimensions));
+            for (mut row, point) in points.axis_iter_mut(Axis(0)).zip(tree.iter()) {
+                for (j, value) in row.iter_mut().enumerate() {
+                    *value = point.coord(j);
                 }
+            }
+            points
+        });
 
-                Ok((PyArray2::from_owned_array(gil.python(), closest_points).to_owned(), PyArray1::from_owned_array(gil.python(), distances).to_owned()))
-            },
-            Err(e) => { Err(PyErr::from(e)) },
+        let gil = Python::acquire_gil();
+        Ok(PyArray2::from_owned_array(gil.python(), points).to_owned())
+    }
+
+    fn knn_graph(&self, k: usize) -> PyResult<(Py<PyArray2<i64>>, Py<PyArray2<f64>>)> {
+        #[cfg(feature="rayon")]
+        let graph = with_tree!(&self.tree, tree => tree.par_knn_graph(k)?);
+        #[cfg(not(feature="rayon"))]
+        let graph = with_tree!(&self.tree, tree => tree.knn_graph(k)?);
+
+        let gil = Python::acquire_gil();
+        let mut indices = Array2::<i64>::zeros((graph.len(), k));
+        let mut distances = Array2::<f64>::zeros((graph.len(), k));
+        for (i, neighbors) in graph.iter().enumerate() {
+            if neighbors.len() < k {
+                return Err(PyErr::new::<exceptions::ValueError, _>("k must be less than the number of points in tree"));
+            }
+
+            for (j, neighbor) in neighbors.iter().enumerate() {
+                indices[[i, j]] = neighbor.point as i64;
+                distances[[i, j]] = neighbor.distance;
+            }
+        }
+
+        Ok((PyArray2::from_owned_array(gil.python(), indices).to_owned(), PyArray2::from_owned_array(gil.python(), distances).to_owned()))
+    }
+}
+
+impl Tree {
+    /// Convert a numpy array or plain sequence of floats into a point of the tree's dimensionality
+    fn to_point(&self, point: &PyAny) -> PyResult<Array1<f64>> {
+        let point = match point.extract::<&PyArray1<f64>>() {
+            Ok(array) => array.to_owned_array(),
+            Err(_) => Array1::from(point.extract::<Vec<f64>>()?),
+        };
+
+        // Trees without dimensions adopt them from the first point
+        let dimensions = self.num_dimensions();
+        if dimensions != 0 && point.len() != dimensions {
+            return Err(PyErr::new::<exceptions::ValueError, _>(format!("expected point with {} dimensions, got {}", dimensions, point.len())));
         }
+
+        Ok(point)
+    }
+
+    /// Number of dimensions of the backing tree
+    fn num_dimensions(&self) -> usize {
+        with_tree!(&self.tree, tree => tree.get_num_dimensions())
     }
 }
 
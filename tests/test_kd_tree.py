@@ -0,0 +1,147 @@
+"""Tests of the Python bindings, run with `maturin develop && pytest tests`"""
+import numpy as np
+import pytest
+
+from kd_tree import Tree
+
+
+def make_tree(points):
+    tree = Tree(points.shape[1], points.shape[0])
+    tree.add_points(points)
+    return tree
+
+
+def brute_force(points, query, k):
+    distances = np.linalg.norm(points - query, axis=1)
+    order = np.argsort(distances, kind="stable")[:k]
+    return order, distances[order]
+
+
+@pytest.mark.parametrize("dimensions", [2, 3, 4, 5])
+def test_backings_match_brute_force(dimensions):
+    # 2, 3 and 4 dimensional trees are backed by fixed size points, others by arrays
+    rng = np.random.default_rng(dimensions)
+    points = rng.random((500, dimensions))
+    tree = make_tree(points)
+
+    for query in rng.random((20, dimensions)):
+        point, distance = tree.find_closest(query)
+        order, expected = brute_force(points, query, 5)
+        assert np.allclose(point, points[order[0]])
+        assert distance == pytest.approx(expected[0])
+
+        closest, distances = tree.find_n_closest(query, 5)
+        assert closest.shape == (5, dimensions)
+        assert np.allclose(np.sort(distances), expected)
+
+
+def test_points_from_lists_or_arrays():
+    tree = Tree(3, 4)
+    tree.add_point([0.0, 0.0, 0.0])
+    tree.add_point(np.array([1.0, 1.0, 1.0]))
+
+    point, distance = tree.find_closest([0.9, 0.9, 0.9])
+    assert np.allclose(point, [1.0, 1.0, 1.0])
+    point, _ = tree.find_closest(np.array([0.1, 0.1, 0.1]))
+    assert np.allclose(point, [0.0, 0.0, 0.0])
+    assert distance == pytest.approx(np.sqrt(0.03))
+
+
+def test_reserve():
+    tree = Tree(2, 0)
+    tree.reserve(1000)
+    points = np.random.default_rng(0).random((1000, 2))
+    for point in points:
+        tree.add_point(point)
+
+    assert np.array_equal(tree.to_array(), points)
+    point, distance = tree.find_closest(points[123])
+    assert np.array_equal(point, points[123])
+    assert distance == 0.0
+
+
+def test_query_sorted_with_ties_by_index():
+    # Every point but the first and last is at distance 1 from the origin
+    points = np.array([[0.0, 0.0], [0.0, 1.0], [-1.0, 0.0], [1.0, 0.0], [0.0, -1.0], [2.0, 2.0]])
+    tree = make_tree(points)
+
+    indices, distances = tree.query(np.array([[0.0, 0.0]]), 5)
+    assert indices.tolist() == [[0, 1, 2, 3, 4]]
+    assert np.allclose(distances, [[0.0, 1.0, 1.0, 1.0, 1.0]])
+
+    # Ties cut off by k keep the lowest indices
+    indices, distances = tree.query(np.array([[0.0, 0.0], [2.0, 2.0]]), 2)
+    assert indices.tolist() == [[0, 1], [5, 1]]
+    assert np.allclose(distances, [[0.0, 1.0], [0.0, np.sqrt(5.0)]])
+
+
+def test_query_unsorted_holds_same_neighbors():
+    rng = np.random.default_rng(1)
+    points = rng.random((300, 3))
+    tree = make_tree(points)
+    queries = rng.random((10, 3))
+
+    sorted_indices, sorted_distances = tree.query(queries, 7)
+    indices, distances = tree.query(queries, 7, sort=False)
+    for row in range(len(queries)):
+        expected, expected_distances = brute_force(points, queries[row], 7)
+        assert sorted_indices[row].tolist() == expected.tolist()
+        assert np.allclose(sorted_distances[row], expected_distances)
+        assert sorted(indices[row].tolist()) == sorted(expected.tolist())
+
+
+def test_to_array_in_insertion_order():
+    points = np.random.default_rng(2).random((50, 4))
+    assert np.array_equal(make_tree(points).to_array(), points)
+    assert Tree(3, 0).to_array().shape == (0, 3)
+
+
+def test_knn_graph():
+    rng = np.random.default_rng(3)
+    points = rng.random((200, 2))
+    indices, distances = make_tree(points).knn_graph(4)
+    assert indices.shape == (200, 4) and distances.shape == (200, 4)
+
+    # Neighbors exclude the point itself
+    for row, point in enumerate(points):
+        expected, expected_distances = brute_force(points, point, 5)
+        assert indices[row].tolist() == expected[1:].tolist()
+        assert np.allclose(distances[row], expected_distances[1:])
+
+    with pytest.raises(ValueError, match="k must be less"):
+        make_tree(points[:3]).knn_graph(3)
+
+
+def test_fortran_ordered_points():
+    # Rows of either memory layout are read in place, so both build the same tree
+    rng = np.random.default_rng(4)
+    points = rng.random((300, 3))
+    c_tree = make_tree(np.ascontiguousarray(points))
+    f_tree = make_tree(np.asfortranarray(points))
+    assert np.array_equal(c_tree.to_array(), points)
+    assert np.array_equal(f_tree.to_array(), points)
+
+    for query in rng.random((10, 3)):
+        c_closest, c_distances = c_tree.find_n_closest(query, 5)
+        f_closest, f_distances = f_tree.find_n_closest(query, 5)
+        assert np.array_equal(f_closest, c_closest)
+        assert np.array_equal(f_distances, c_distances)
+
+
+def test_errors():
+    tree = Tree(3, 0)
+    with pytest.raises(ValueError, match="tree is empty"):
+        tree.find_closest([0.0, 0.0, 0.0])
+
+    with pytest.raises(ValueError, match="expected point with 3 dimensions, got 2"):
+        tree.add_point([0.0, 0.0])
+    with pytest.raises(ValueError, match="dimensionality"):
+        tree.add_points(np.zeros((2, 4)))
+    with pytest.raises(TypeError):
+        tree.add_point("abc")
+
+    tree.add_points(np.zeros((3, 3)))
+    with pytest.raises(ValueError, match="dimensionality"):
+        tree.find_n_closest(np.zeros(2), 1)
+    with pytest.raises(ValueError, match="k must not exceed"):
+        tree.query(np.zeros((1, 3)), 4)